@@ -1,11 +1,13 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
 use tempfile::NamedTempFile;
 
+const DATA: &str = "Lorem ipsum dolor sit amet, consectetur adipiscing elit. Vestibulum rhoncus ligula a consectetur cursus.";
+
 fn worst_case(c: &mut Criterion) {
     let f = NamedTempFile::new().unwrap();
     let store = kv::Store::<String>::open(f.path()).unwrap();
 
-    let data = "Lorem ipsum dolor sit amet, consectetur adipiscing elit. Vestibulum rhoncus ligula a consectetur cursus.".to_string();
+    let data = DATA.to_string();
 
     for _ in 0..100_000 {
         store.set("key1", &data).unwrap();
@@ -15,5 +17,56 @@ fn worst_case(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, worst_case);
+fn import(c: &mut Criterion) {
+    let data = DATA.to_string();
+    let open = |write_buffer| {
+        let f = NamedTempFile::new().unwrap();
+        let store = kv::Store::<String>::options()
+            .write_buffer(write_buffer)
+            .open(f.path())
+            .unwrap();
+        (f, store)
+    };
+
+    let mut group = c.benchmark_group("import 10k records");
+    group.sample_size(10);
+    group.bench_function("unbuffered sets", |b| {
+        b.iter_batched(
+            || open(0),
+            |(_f, store)| {
+                for i in 0..10_000 {
+                    store.set(&format!("key{i}"), &data).unwrap();
+                }
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.bench_function("buffered sets", |b| {
+        b.iter_batched(
+            || open(8 * 1024),
+            |(_f, store)| {
+                for i in 0..10_000 {
+                    store.set(&format!("key{i}"), &data).unwrap();
+                }
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.bench_function("batch", |b| {
+        b.iter_batched(
+            || open(8 * 1024),
+            |(_f, store)| {
+                let mut batch = kv::WriteBatch::new();
+                for i in 0..10_000 {
+                    batch.set(&format!("key{i}"), &data).unwrap();
+                }
+                store.apply(batch).unwrap();
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, worst_case, import);
 criterion_main!(benches);
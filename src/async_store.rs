@@ -0,0 +1,139 @@
+use std::path::PathBuf;
+
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{Codec, Error, JsonCodec, OpenOptions, Store};
+
+/// Asynchronous wrapper around a [`Store`], for use from within a Tokio runtime.
+///
+/// Every operation is run on Tokio's blocking thread pool through
+/// [`spawn_blocking`](tokio::task::spawn_blocking), so file accesses never stall the executor.
+pub struct AsyncStore<T, C = JsonCodec>(Store<T, C>);
+
+impl<T, C> Clone for AsyncStore<T, C> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T, C> From<Store<T, C>> for AsyncStore<T, C> {
+    fn from(store: Store<T, C>) -> Self {
+        Self(store)
+    }
+}
+
+impl<T: 'static, C: Codec + 'static> AsyncStore<T, C> {
+    /// Opens the database at the given path with the default options.
+    pub async fn open(path: impl Into<PathBuf>) -> Result<Self, Error> {
+        Self::open_with(path, Store::options()).await
+    }
+
+    /// Opens the database at the given path with the given options.
+    pub async fn open_with(
+        path: impl Into<PathBuf>,
+        options: OpenOptions<T, C>,
+    ) -> Result<Self, Error> {
+        let path = path.into();
+        run(move || options.open(&path)).await.map(Self)
+    }
+
+    /// Returns the underlying store, for the operations which have no asynchronous counterpart.
+    pub fn blocking(&self) -> &Store<T, C> {
+        &self.0
+    }
+
+    /// See [`Store::unset`].
+    pub async fn unset(&self, key: &str) -> Result<(), Error> {
+        let (store, key) = (self.0.clone(), key.to_string());
+        run(move || store.unset(&key)).await
+    }
+
+    /// See [`Store::contains`].
+    pub async fn contains(&self, key: &str) -> Result<bool, Error> {
+        let (store, key) = (self.0.clone(), key.to_string());
+        run(move || store.contains(&key)).await
+    }
+
+    /// See [`Store::flush`].
+    pub async fn flush(&self) -> Result<(), Error> {
+        let store = self.0.clone();
+        run(move || store.flush()).await
+    }
+
+    /// See [`Store::sync`].
+    pub async fn sync(&self) -> Result<(), Error> {
+        let store = self.0.clone();
+        run(move || store.sync()).await
+    }
+
+    /// See [`Store::compact`].
+    pub async fn compact(&self) -> Result<(), Error> {
+        let store = self.0.clone();
+        run(move || store.compact()).await
+    }
+}
+
+impl<T, C> AsyncStore<T, C>
+where
+    T: Serialize + Send + 'static,
+    C: Codec + 'static,
+{
+    /// See [`Store::set`].
+    pub async fn set(&self, key: &str, value: T) -> Result<(), Error> {
+        let (store, key) = (self.0.clone(), key.to_string());
+        run(move || store.set(&key, &value)).await
+    }
+}
+
+impl<T, C> AsyncStore<T, C>
+where
+    T: for<'a> Deserialize<'a> + Send + 'static,
+    C: Codec + 'static,
+{
+    /// See [`Store::get`].
+    pub async fn get(&self, key: &str) -> Result<Option<T>, Error> {
+        let (store, key) = (self.0.clone(), key.to_string());
+        run(move || store.get(&key)).await
+    }
+
+    /// See [`Store::load_map`].
+    pub async fn load_map(&self) -> Result<FxHashMap<String, T>, Error> {
+        let store = self.0.clone();
+        run(move || store.load_map()).await
+    }
+}
+
+/// Runs the blocking operation on Tokio's blocking thread pool.
+async fn run<R, F>(f: F) -> Result<R, Error>
+where
+    F: FnOnce() -> Result<R, Error> + Send + 'static,
+    R: Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(output) => output,
+        Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+        Err(err) => Err(Error::Read(err.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn async_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = AsyncStore::<String>::open(dir.path().join("db"))
+            .await
+            .unwrap();
+        store.set("a", "1".to_string()).await.unwrap();
+        store.set("b", "2".to_string()).await.unwrap();
+        store.unset("b").await.unwrap();
+
+        assert_eq!(Some("1".to_string()), store.get("a").await.unwrap());
+        assert!(!store.contains("b").await.unwrap());
+        assert_eq!(1, store.load_map().await.unwrap().len());
+        assert_eq!(Some("1".to_string()), store.blocking().get("a").unwrap());
+    }
+}
@@ -0,0 +1,147 @@
+use std::fs::File;
+use std::io::{self, IoSlice, Read, Seek, SeekFrom, Write};
+
+/// Storage holding the bytes of a database, for when it should live somewhere other than in a file
+/// opened by path, such as a virtual filesystem or a remote object.
+///
+/// A backend is an append-only sequence of bytes which can be read from any offset. Everything
+/// else, from the format of the records to the index, is handled by the [`Store`](crate::Store)
+/// opened on top of it through [`OpenOptions::open_backend`](crate::OpenOptions::open_backend).
+///
+/// It's implemented for [`File`], and for `Vec<u8>` as used by
+/// [`Store::in_memory`](crate::Store::in_memory).
+pub trait Backend: Send + Sync {
+    /// Reads bytes starting at the given offset into the buffer, and returns how many were read,
+    /// which is `0` past the end.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+
+    /// Appends the bytes at the end, all at once.
+    fn append(&mut self, buf: &[u8]) -> io::Result<()>;
+
+    /// Appends the bytes of every buffer at the end, in order and all at once.
+    ///
+    /// This appends them concatenated by default. Backends which can write several buffers at once
+    /// should do so instead, to spare the copy.
+    fn append_vectored(&mut self, bufs: &[&[u8]]) -> io::Result<()> {
+        self.append(&bufs.concat())
+    }
+
+    /// Returns the number of bytes held.
+    fn len(&self) -> io::Result<u64>;
+
+    /// Checks whether no bytes are held.
+    fn is_empty(&self) -> io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Drops the bytes past the given length, as done to repair a torn write.
+    fn truncate(&mut self, len: u64) -> io::Result<()>;
+
+    /// Replaces every byte held with the given ones, as done by a compaction.
+    ///
+    /// This truncates then appends by default, which loses the data if interrupted in between.
+    /// Backends which can swap their content atomically should do so instead.
+    fn replace(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.truncate(0)?;
+        self.append(buf)
+    }
+
+    /// Makes the appended bytes durable. Does nothing by default.
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Backend for File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        read_at(self, buf, offset)
+    }
+
+    fn append(&mut self, buf: &[u8]) -> io::Result<()> {
+        // Files which weren't opened in append mode write at their cursor.
+        self.seek(SeekFrom::End(0))?;
+        self.write_all(buf)
+    }
+
+    fn append_vectored(&mut self, bufs: &[&[u8]]) -> io::Result<()> {
+        self.seek(SeekFrom::End(0))?;
+        let mut slices: Vec<IoSlice> = bufs.iter().map(|buf| IoSlice::new(buf)).collect();
+        let mut slices = &mut slices[..];
+        // Usually done in a single call, unless the buffers are too many or too large.
+        while !slices.is_empty() {
+            match self.write_vectored(slices) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written) => IoSlice::advance_slices(&mut slices, written),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        self.set_len(len)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.sync_data()
+    }
+}
+
+impl Backend for Vec<u8> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        usize::try_from(offset)
+            .ok()
+            .and_then(|offset| self.get(offset..))
+            .unwrap_or_default()
+            .read(buf)
+    }
+
+    fn append(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.extend_from_slice(buf);
+        Ok(())
+    }
+
+    fn append_vectored(&mut self, bufs: &[&[u8]]) -> io::Result<()> {
+        for buf in bufs {
+            self.extend_from_slice(buf);
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(Vec::len(self) as u64)
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        Vec::truncate(self, usize::try_from(len).unwrap_or(usize::MAX));
+        Ok(())
+    }
+
+    fn replace(&mut self, buf: &[u8]) -> io::Result<()> {
+        buf.clone_into(self);
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+/// Fallback for platforms without positioned reads, such as WebAssembly, which moves the cursor of
+/// the file. Appends seek to the end on their own, so the only reads affected are concurrent ones.
+#[cfg(not(any(unix, windows)))]
+fn read_at(mut file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.read(buf)
+}
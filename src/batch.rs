@@ -0,0 +1,115 @@
+use std::marker::PhantomData;
+
+use serde::{Deserialize, Serialize};
+
+use crate::record::{OwnedRecord, TOMBSTONE};
+use crate::{Codec, Error, JsonCodec, Store};
+
+/// A set of writes to be applied to a [`Store`](crate::Store) at once.
+///
+/// Values are serialized as they are added, so errors surface at the call site rather than when
+/// the batch is applied with [`Store::apply`](crate::Store::apply).
+pub struct WriteBatch<T, C = JsonCodec> {
+    pub(crate) records: Vec<OwnedRecord>,
+    _phantom: PhantomData<fn(&T, C)>,
+}
+
+impl<T, C> Default for WriteBatch<T, C> {
+    fn default() -> Self {
+        Self {
+            records: Vec::new(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T, C> WriteBatch<T, C> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a record setting the given key to `None`.
+    pub fn unset(&mut self, key: &str) -> Result<(), Error> {
+        self.push(key, TOMBSTONE.to_vec());
+        Ok(())
+    }
+
+    fn push(&mut self, key: &str, value: Vec<u8>) {
+        self.records.push(OwnedRecord {
+            key: key.to_string(),
+            value,
+            expires_at: None,
+            written_at: None,
+            version: None,
+            blob: false,
+        });
+    }
+
+    /// Number of records in the batch.
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+impl<T: Serialize, C: Codec> WriteBatch<T, C> {
+    /// Adds a record setting the given key to the given value.
+    pub fn set(&mut self, key: &str, value: &T) -> Result<(), Error> {
+        let value = C::encode(value)?;
+        self.push(key, value);
+        Ok(())
+    }
+}
+
+/// Writes staged by [`Store::transaction`](crate::Store::transaction).
+///
+/// Nothing reaches the database until the transaction's closure returns successfully, at which
+/// point every staged record is appended with a single write.
+pub struct Transaction<'a, T, C = JsonCodec> {
+    store: &'a Store<T, C>,
+    batch: WriteBatch<T, C>,
+}
+
+impl<'a, T, C> Transaction<'a, T, C> {
+    pub(crate) fn new(store: &'a Store<T, C>) -> Self {
+        Self {
+            store,
+            batch: WriteBatch::new(),
+        }
+    }
+
+    pub(crate) fn into_batch(self) -> WriteBatch<T, C> {
+        self.batch
+    }
+
+    /// Stages setting the given key to `None`.
+    pub fn unset(&mut self, key: &str) -> Result<(), Error> {
+        self.batch.unset(key)
+    }
+}
+
+impl<T: Serialize, C: Codec> Transaction<'_, T, C> {
+    /// Stages setting the given key to the given value.
+    pub fn set(&mut self, key: &str, value: &T) -> Result<(), Error> {
+        self.batch.set(key, value)
+    }
+}
+
+impl<T, C> Transaction<'_, T, C>
+where
+    T: for<'a> Deserialize<'a>,
+    C: Codec,
+{
+    /// Retrieves the value associated with a key, taking the staged writes into account.
+    pub fn get(&self, key: &str) -> Result<Option<T>, Error> {
+        let staged = self.batch.records.iter().rev().find(|r| r.key == key);
+        match staged {
+            Some(record) if record.value == TOMBSTONE => Ok(None),
+            Some(record) => C::decode(&record.value).map(Some),
+            None => self.store.get(key),
+        }
+    }
+}
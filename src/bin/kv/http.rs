@@ -0,0 +1,200 @@
+//! A minimal HTTP/1.1 front-end to a store, handling one request per connection.
+//!
+//! - `GET /keys/{key}` returns the value of the key as JSON, or `404` if it's missing.
+//! - `PUT /keys/{key}` sets the key to the JSON value in the body of the request.
+//! - `DELETE /keys/{key}` unsets the key.
+//! - `GET /keys?prefix={prefix}` returns a JSON object mapping every key starting with the prefix
+//!   to its value, or every entry of the database without a prefix.
+//!
+//! Keys and prefixes are percent-decoded.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+
+use kv::{Error, Store};
+use serde_json::value::RawValue;
+use serde_json::Value;
+
+/// Largest request body accepted, to keep a single request from exhausting the memory.
+const MAX_BODY_LEN: usize = 64 << 20;
+
+struct Response {
+    status: u16,
+    body: Option<String>,
+}
+
+impl Response {
+    fn new(status: u16) -> Self {
+        Self { status, body: None }
+    }
+
+    fn json(value: Value) -> Self {
+        Self {
+            status: 200,
+            body: Some(value.to_string()),
+        }
+    }
+
+    /// Responds with JSON read as-is from the store.
+    fn raw(value: Box<RawValue>) -> Self {
+        Self {
+            status: 200,
+            body: Some(Box::<str>::from(value).into()),
+        }
+    }
+
+    fn error(status: u16, message: impl ToString) -> Self {
+        Self {
+            status,
+            body: Some(serde_json::json!({ "error": message.to_string() }).to_string()),
+        }
+    }
+}
+
+pub fn serve(store: Store<Value>, listener: TcpListener) -> Result<(), Error> {
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let store = store.clone();
+        std::thread::spawn(move || {
+            // The client is gone if the response can't be written, so there's no one to tell.
+            let _ = handle(&store, stream);
+        });
+    }
+    Ok(())
+}
+
+fn handle(store: &Store<Value>, stream: TcpStream) -> std::io::Result<()> {
+    let mut reader = BufReader::new(&stream);
+    let response = match read_request(&mut reader)? {
+        Some((method, target, body)) => route(store, &method, &target, &body),
+        None => Response::error(400, "Malformed request"),
+    };
+
+    let body = response.body.map(String::into_bytes).unwrap_or_default();
+    let mut writer = &stream;
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        body.len(),
+    )?;
+    writer.write_all(&body)?;
+    writer.flush()
+}
+
+/// Reads the method, target and body of a request, or `None` if it's malformed.
+fn read_request<R: BufRead>(reader: &mut R) -> std::io::Result<Option<(String, String, Vec<u8>)>> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Ok(None);
+    };
+    let (method, target) = (method.to_string(), target.to_string());
+
+    let mut content_len = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                match value.trim().parse() {
+                    Ok(len) if len <= MAX_BODY_LEN => content_len = len,
+                    _ => return Ok(None),
+                }
+            }
+        }
+    }
+
+    let mut body = vec![0; content_len];
+    reader.read_exact(&mut body)?;
+    Ok(Some((method, target, body)))
+}
+
+fn route(store: &Store<Value>, method: &str, target: &str, body: &[u8]) -> Response {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let result = if path == "/keys" && method == "GET" {
+        let prefix = query
+            .split('&')
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| *name == "prefix")
+            .map_or(Some(String::new()), |(_, prefix)| {
+                percent_decode(&prefix.replace('+', " "))
+            });
+        match prefix {
+            Some(prefix) => list(store, &prefix),
+            None => return Response::error(400, "Invalid prefix"),
+        }
+    } else if let Some(key) = path.strip_prefix("/keys/") {
+        let Some(key) = percent_decode(key) else {
+            return Response::error(400, "Invalid key");
+        };
+        match method {
+            "GET" => store.get_raw(&key).map(|value| match value {
+                Some(value) => Response::raw(value),
+                None => Response::error(404, "Key not found"),
+            }),
+            "PUT" => match serde_json::from_slice(body) {
+                Ok(value) => store.set(&key, &value).map(|()| Response::new(204)),
+                Err(err) => return Response::error(400, err),
+            },
+            "DELETE" => store.unset(&key).map(|()| Response::new(204)),
+            _ => return Response::error(405, "Method not allowed"),
+        }
+    } else {
+        return Response::error(404, "Not found");
+    };
+
+    result.unwrap_or_else(|err| Response::error(500, err))
+}
+
+fn list(store: &Store<Value>, prefix: &str) -> Result<Response, Error> {
+    let entries = store
+        .scan_prefix(prefix)
+        .collect::<Result<serde_json::Map<_, _>, _>>()?;
+    Ok(Response::json(Value::Object(entries)))
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Decodes the `%XX` escapes of a URL component, or returns `None` if they aren't valid UTF-8.
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
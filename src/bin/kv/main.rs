@@ -0,0 +1,547 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+mod http;
+mod resp;
+#[cfg(unix)]
+mod socket;
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+#[command(propagate_version = true)]
+struct Cli {
+    db_path: PathBuf,
+
+    /// How values are printed by `get`, which applies to every value when several are printed.
+    #[arg(long, global = true, value_enum, default_value_t = Output::Raw)]
+    output: Output,
+
+    /// Open the database without write access, failing any command which would modify it.
+    #[arg(long, global = true)]
+    read_only: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    Set {
+        key: String,
+        /// The value as JSON, or `-` to read it from stdin.
+        value: String,
+    },
+    Unset {
+        #[arg(required_unless_present = "prefix")]
+        key: Option<String>,
+        /// Unset every key starting with this prefix instead, with a single write.
+        #[arg(long, conflicts_with = "key")]
+        prefix: Option<String>,
+        /// Print the keys which would be unset by `--prefix` without unsetting them.
+        #[arg(long, requires = "prefix")]
+        dry_run: bool,
+    },
+    /// Move the value of a key, along with its expiry, to another key, with a single write.
+    Rename {
+        old: String,
+        new: String,
+        /// Replace the value of `new` if it holds one, instead of failing.
+        #[arg(long)]
+        overwrite: bool,
+    },
+    /// Print the value of a key. Exits with status 2 if a key is missing, unless `--default` is
+    /// given.
+    ///
+    /// With several keys or `--prefix`, prints a JSON object mapping every key to its value.
+    Get {
+        #[arg(required_unless_present = "prefix")]
+        keys: Vec<String>,
+        /// Also get every key starting with this prefix.
+        #[arg(long)]
+        prefix: Option<String>,
+        /// JSON value to print if a key is missing.
+        #[arg(long)]
+        default: Option<String>,
+        /// Print one `key<TAB>value` line per key instead of a JSON object.
+        #[arg(long)]
+        lines: bool,
+    },
+    Load,
+    /// Print the live entries, one `key<TAB>value` pair per line, in key order.
+    List {
+        /// Only list the keys starting with this prefix.
+        #[arg(long)]
+        prefix: Option<String>,
+        /// Print the keys without their values.
+        #[arg(long)]
+        keys_only: bool,
+    },
+    /// Apply the operations of a newline-delimited JSON file, or stdin if `-`, with a single write.
+    ///
+    /// Every line is either `{"op":"set","key":...,"value":...}` or `{"op":"unset","key":...}`.
+    /// Nothing is written unless every line is valid.
+    Apply {
+        file: PathBuf,
+    },
+    /// Print the live keys matching a glob pattern, one per line, where `*` matches any sequence
+    /// of characters and `?` any single character.
+    Keys {
+        #[arg(default_value = "*")]
+        pattern: String,
+    },
+    /// Print the live entries whose value matches a query such as `$.address.city == 'Paris'`,
+    /// one `key<TAB>value` pair per line, in key order.
+    Query {
+        expr: String,
+    },
+    /// Print statistics about the database, to help decide whether to compact it.
+    Stats {
+        /// Number of the largest values to list.
+        #[arg(long, default_value_t = 5)]
+        largest: usize,
+    },
+    /// Drop the overwritten and unset entries, and print the size of the database before and after.
+    Compact {
+        /// Write the compacted database to a new file instead, leaving the database untouched.
+        #[arg(long)]
+        into: Option<PathBuf>,
+    },
+    /// Copy the live entries of another database into this one.
+    Merge {
+        source: PathBuf,
+        /// Which value to keep for the keys held by both databases.
+        #[arg(long, value_enum, default_value_t = MergePreference::Src)]
+        prefer: MergePreference,
+    },
+    /// Follow the writes made to the database by other processes, printing one line per write.
+    Watch {
+        /// Only print the writes to keys starting with this prefix.
+        #[arg(default_value = "")]
+        prefix: String,
+    },
+    /// Ship every record of the database, then those written by other processes, to a follower.
+    Replicate {
+        /// Address of the follower, as listened on by `follow`.
+        to: String,
+    },
+    /// Listen for a replicating process, and append the records it ships to the database.
+    Follow {
+        #[arg(long, default_value = "127.0.0.1:7070")]
+        listen: String,
+    },
+    /// Serve the database over the network.
+    Serve {
+        /// Address to listen on, which defaults to port 8080 for HTTP and 6379 for Redis.
+        #[arg(long)]
+        listen: Option<String>,
+        #[arg(long, value_enum, default_value_t = Protocol::Http)]
+        protocol: Protocol,
+        /// Serve newline-delimited JSON requests over a Unix socket at this path instead, such as
+        /// `{"op":"get","key":...}`, `{"op":"set","key":...,"value":...}`, `{"op":"unset",...}`
+        /// and `{"op":"keys","prefix":...}`.
+        #[arg(long, conflicts_with_all = ["listen", "protocol"])]
+        socket: Option<PathBuf>,
+    },
+    /// Write the live entries to stdout.
+    Export {
+        #[arg(long, value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+        /// Write to this file instead, which is required for SQLite.
+        #[arg(required_if_eq("format", "sqlite"))]
+        out: Option<PathBuf>,
+    },
+    /// Set every key to its value from a file written by `export`.
+    Import {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = ImportFormat::Json)]
+        format: ImportFormat,
+        /// Unset the existing keys which aren't in the file.
+        #[arg(long)]
+        truncate: bool,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Output {
+    /// The value as compact JSON, `null` if the key is missing.
+    Raw,
+    /// The value as indented JSON, `null` if the key is missing.
+    Pretty,
+    /// A `{"found": ..., "value": ...}` object, which tells a missing key apart from a `null`.
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Protocol {
+    /// `GET`, `PUT` and `DELETE` on `/keys/{key}`, and `GET` on `/keys?prefix={prefix}`.
+    Http,
+    /// A subset of the Redis protocol: `GET`, `SET`, `DEL`, `EXISTS`, `KEYS` and `SCAN`.
+    Redis,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum MergePreference {
+    /// The value of the database being merged in.
+    Src,
+    /// The value of the database being merged into.
+    Dest,
+    /// Whichever value was written last.
+    Newest,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ExportFormat {
+    /// A single JSON object mapping every key to its value.
+    Json,
+    /// A `key,value` CSV table, with values written as JSON.
+    Csv,
+    /// A `kv` table of an SQLite database, with a `key` and a JSON `value` column.
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ImportFormat {
+    /// A single JSON object mapping every key to its value.
+    Json,
+    /// The `kv` table of an SQLite database, with a `key` and a JSON `value` column.
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+}
+
+/// Exit status of `get` when the key is missing.
+const MISSING: u8 = 2;
+
+fn main() -> Result<ExitCode, kv::Error> {
+    let cli = Cli::parse();
+
+    // The database is most likely opened for writing by the process being watched.
+    if let Command::Watch { prefix } = &cli.command {
+        watch(&cli.db_path, prefix)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Command::Replicate { to } = &cli.command {
+        let stream = std::net::TcpStream::connect(to)?;
+        kv::Replicator::new(&cli.db_path, None, stream)?.run(Duration::from_millis(200))?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let store = kv::Store::<serde_json::Value>::options()
+        .read_only(cli.read_only)
+        .open(&cli.db_path)?;
+
+    match cli.command {
+        Command::Set { key, value } => {
+            let value = if value == "-" {
+                serde_json::from_reader(std::io::stdin().lock())
+            } else {
+                serde_json::from_str(&value)
+            };
+            let value = value.map_err(|err| kv::Error::Decode(err.into()))?;
+            store.set(&key, &value)?
+        }
+        Command::Unset {
+            key,
+            prefix,
+            dry_run,
+        } => match (key, prefix) {
+            (Some(key), _) => store.unset(&key)?,
+            (None, Some(prefix)) if dry_run => {
+                let mut out = std::io::stdout().lock();
+                for key in store.keys().filter(|key| key.starts_with(&prefix)) {
+                    writeln!(out, "{key}")?;
+                }
+            }
+            (None, Some(prefix)) => {
+                let count = store.unset_prefix(&prefix)?;
+                println!("{count} keys unset");
+            }
+            (None, None) => unreachable!("clap requires either"),
+        },
+        Command::Rename {
+            old,
+            new,
+            overwrite,
+        } => store.rename(&old, &new, overwrite)?,
+        Command::Get {
+            keys,
+            prefix,
+            default,
+            lines,
+        } => {
+            let default: Option<serde_json::Value> = default
+                .map(|default| serde_json::from_str(&default))
+                .transpose()
+                .map_err(|err| kv::Error::Decode(err.into()))?;
+            let single = keys.len() == 1 && prefix.is_none() && !lines;
+
+            let found = store.get_many(&keys.iter().map(String::as_str).collect::<Vec<_>>())?;
+            let mut entries: Vec<_> = keys
+                .into_iter()
+                .map(|key| {
+                    let value = found.get(&key).cloned();
+                    (key, value)
+                })
+                .collect();
+            if let Some(prefix) = prefix {
+                for entry in store.scan_prefix(&prefix) {
+                    let (key, value) = entry?;
+                    entries.push((key, Some(value)));
+                }
+            }
+            let missing = default.is_none() && entries.iter().any(|(_, found)| found.is_none());
+
+            // What gets printed for every key, depending on whether it was found.
+            let printed = |found: Option<serde_json::Value>| match cli.output {
+                Output::Raw | Output::Pretty => found.or(default.clone()).unwrap_or_default(),
+                Output::Json => serde_json::json!({
+                    "found": found.is_some(),
+                    "value": found.or(default.clone()),
+                }),
+            };
+            let mut out = std::io::stdout().lock();
+            if single {
+                let (_, found) = entries.pop().expect("a single key was given");
+                match cli.output {
+                    Output::Pretty => writeln!(out, "{:#}", printed(found)),
+                    _ => writeln!(out, "{}", printed(found)),
+                }?;
+            } else if lines {
+                for (key, found) in entries {
+                    writeln!(out, "{key}\t{}", printed(found))?;
+                }
+            } else {
+                let object: serde_json::Map<_, _> = entries
+                    .into_iter()
+                    .map(|(key, found)| (key, printed(found)))
+                    .collect();
+                let object = serde_json::Value::Object(object);
+                match cli.output {
+                    Output::Pretty => writeln!(out, "{object:#}"),
+                    _ => writeln!(out, "{object}"),
+                }?;
+            }
+            if missing {
+                return Ok(ExitCode::from(MISSING));
+            }
+        }
+        Command::Load => {
+            let map = store.load_sorted()?;
+            println!("{map:?}");
+        }
+        Command::List { prefix, keys_only } => {
+            let prefix = prefix.unwrap_or_default();
+            let mut out = std::io::stdout().lock();
+            if keys_only {
+                for key in store.keys().filter(|key| key.starts_with(&prefix)) {
+                    writeln!(out, "{key}")?;
+                }
+            } else {
+                for entry in store.scan_prefix(&prefix) {
+                    let (key, value) = entry?;
+                    writeln!(out, "{key}\t{value}")?;
+                }
+            }
+        }
+        Command::Apply { file } => {
+            let reader: Box<dyn BufRead> = if file.as_os_str() == "-" {
+                Box::new(std::io::stdin().lock())
+            } else {
+                Box::new(BufReader::new(File::open(file)?))
+            };
+            let mut batch = kv::WriteBatch::new();
+            for (i, line) in reader.lines().enumerate() {
+                let line = line?;
+                if !line.trim().is_empty() {
+                    apply_line(&mut batch, &line)
+                        .map_err(|err| kv::Error::Read(format!("Line {}: {err}", i + 1)))?;
+                }
+            }
+            store.apply(batch)?;
+        }
+        Command::Keys { pattern } => {
+            let mut out = std::io::stdout().lock();
+            for key in store.keys_matching(&pattern) {
+                writeln!(out, "{key}")?;
+            }
+        }
+        Command::Query { expr } => {
+            let mut out = std::io::stdout().lock();
+            for (key, value) in store.query(&expr)? {
+                writeln!(out, "{key}\t{value}")?;
+            }
+        }
+        Command::Stats { largest } => {
+            let stats = store.stats();
+            println!("size:       {} bytes", stats.size);
+            println!("records:    {}", stats.records);
+            println!("live keys:  {}", stats.live_keys);
+            println!("tombstones: {}", stats.tombstones);
+            println!("dead:       {:.1}%", stats.dead_ratio * 100.0);
+
+            let mut sizes: Vec<_> = store
+                .value_sizes()?
+                .into_iter()
+                .map(|(key, size)| (size, key))
+                .collect();
+            sizes.sort_unstable_by(|a, b| b.cmp(a));
+            if largest > 0 && !sizes.is_empty() {
+                println!("largest values:");
+                for (size, key) in sizes.into_iter().take(largest) {
+                    println!("  {key}: {size} bytes");
+                }
+            }
+        }
+        Command::Compact { into } => {
+            let before = store.stats().size;
+            let after = match into {
+                Some(path) => {
+                    store.compact_into(&path)?;
+                    std::fs::metadata(&path)?.len()
+                }
+                None => {
+                    store.compact()?;
+                    store.stats().size
+                }
+            };
+            println!("{before} -> {after} bytes");
+        }
+        Command::Merge { source, prefer } => {
+            let source = kv::Store::open_read_only(&source)?;
+            let prefer = match prefer {
+                MergePreference::Src => kv::Prefer::Source,
+                MergePreference::Dest => kv::Prefer::Destination,
+                MergePreference::Newest => kv::Prefer::Newest,
+            };
+            let merged = store.merge_from(&source, prefer)?;
+            println!("{merged} keys merged");
+        }
+        Command::Watch { .. } | Command::Replicate { .. } => unreachable!(),
+        Command::Follow { listen } => {
+            let listener = TcpListener::bind(&listen)?;
+            eprintln!("Listening on {listen}");
+            // One at a time, so that the records of two replicators never interleave.
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                if let Err(err) = store.replicate_from(stream) {
+                    eprintln!("{err}");
+                }
+            }
+        }
+        #[cfg(unix)]
+        Command::Serve {
+            socket: Some(path), ..
+        } => {
+            eprintln!("Listening on {}", path.display());
+            socket::serve(store, &path)?;
+        }
+        #[cfg(not(unix))]
+        Command::Serve {
+            socket: Some(_), ..
+        } => {
+            return Err(kv::Error::Read(
+                "Unix sockets aren't supported on this platform".to_string(),
+            ))
+        }
+        Command::Serve {
+            listen, protocol, ..
+        } => {
+            let listen = listen.unwrap_or_else(|| match protocol {
+                Protocol::Http => "127.0.0.1:8080".to_string(),
+                Protocol::Redis => "127.0.0.1:6379".to_string(),
+            });
+            let listener = TcpListener::bind(&listen)?;
+            eprintln!("Listening on {listen}");
+            match protocol {
+                Protocol::Http => http::serve(store, listener)?,
+                Protocol::Redis => resp::serve(store, listener)?,
+            }
+        }
+        Command::Export { format, out } => match format {
+            ExportFormat::Json => {
+                let mut writer = output(out.as_deref())?;
+                store.export_json(&mut writer)?;
+                writeln!(writer)?;
+            }
+            ExportFormat::Csv => store.export_csv(output(out.as_deref())?)?,
+            #[cfg(feature = "sqlite")]
+            ExportFormat::Sqlite => {
+                store.export_sqlite(&out.expect("the path is required for SQLite"))?;
+            }
+        },
+        Command::Import {
+            file,
+            format,
+            truncate,
+        } => {
+            let mode = if truncate {
+                kv::ImportMode::Truncate
+            } else {
+                kv::ImportMode::Merge
+            };
+            match format {
+                ImportFormat::Json => store.import_json(File::open(file)?, mode)?,
+                #[cfg(feature = "sqlite")]
+                ImportFormat::Sqlite => store.import_sqlite(&file, mode)?,
+            }
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Adds the operation described by a line of an `apply` file to the batch.
+fn apply_line(batch: &mut kv::WriteBatch<serde_json::Value>, line: &str) -> Result<(), String> {
+    let op: serde_json::Value = serde_json::from_str(line).map_err(|err| err.to_string())?;
+    let key = op["key"].as_str().ok_or("missing `key`")?;
+    match op["op"].as_str() {
+        Some("set") => {
+            let value = op.get("value").ok_or("missing `value`")?;
+            batch.set(key, value)
+        }
+        Some("unset") => batch.unset(key),
+        _ => return Err("`op` must be `set` or `unset`".to_string()),
+    }
+    .map_err(|err| err.to_string())
+}
+
+fn watch(path: &Path, prefix: &str) -> Result<(), kv::Error> {
+    let mut tail = kv::Tail::<serde_json::Value>::open(path)?;
+    loop {
+        let mut out = std::io::stdout().lock();
+        for event in tail.poll()? {
+            match event {
+                kv::Event::Set { key, value } if key.starts_with(prefix) => {
+                    writeln!(out, "set\t{key}\t{value}")?
+                }
+                kv::Event::Unset { key } if key.starts_with(prefix) => {
+                    writeln!(out, "unset\t{key}")?
+                }
+                kv::Event::Expired { key } if key.starts_with(prefix) => {
+                    writeln!(out, "expired\t{key}")?
+                }
+                _ => {}
+            }
+        }
+        out.flush()?;
+        drop(out);
+        std::thread::sleep(Duration::from_millis(200));
+    }
+}
+
+/// Returns the file to write to, or stdout if there is none.
+fn output(path: Option<&Path>) -> Result<Box<dyn Write>, kv::Error> {
+    Ok(match path {
+        // The exports are buffered on their own.
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(std::io::stdout().lock()),
+    })
+}
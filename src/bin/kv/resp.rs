@@ -0,0 +1,202 @@
+//! A minimal front-end speaking the Redis protocol (RESP2), so that Redis clients can be used with
+//! a store.
+//!
+//! Supported commands are `GET`, `SET`, `DEL`, `EXISTS`, `KEYS` and `SCAN`, along with `PING`.
+//! Values set through `SET` are stored as JSON strings, and `GET` returns strings as-is and any
+//! other value as JSON. Patterns only support the `*` and `?` wildcards.
+
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream};
+
+use kv::{Error, Store, WriteBatch};
+use serde_json::Value;
+
+/// Largest bulk string accepted, as in Redis.
+const MAX_BULK_LEN: usize = 512 << 20;
+
+enum Reply {
+    Status(&'static str),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<String>),
+    Array(Vec<Reply>),
+}
+
+pub fn serve(store: Store<Value>, listener: TcpListener) -> Result<(), Error> {
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let store = store.clone();
+        std::thread::spawn(move || {
+            // Errors can only come from the connection, which is then closed.
+            let _ = handle(&store, stream);
+        });
+    }
+    Ok(())
+}
+
+fn handle(store: &Store<Value>, stream: TcpStream) -> io::Result<()> {
+    let mut reader = BufReader::new(&stream);
+    let mut writer = BufWriter::new(&stream);
+    loop {
+        let reply = match read_command(&mut reader)? {
+            Some(Ok(args)) if args.is_empty() => continue,
+            Some(Ok(args)) => run(store, &args).unwrap_or_else(|err| Reply::Error(err.to_string())),
+            Some(Err(err)) => Reply::Error(err),
+            None => return Ok(()),
+        };
+        write_reply(&mut writer, &reply)?;
+        // Pipelined commands are answered together.
+        if reader.buffer().is_empty() {
+            writer.flush()?;
+        }
+    }
+}
+
+/// Reads the arguments of the next command, either as an array of bulk strings or as an inline
+/// command. Returns `None` once the connection is closed, and an error message if the command is
+/// malformed.
+fn read_command<R: BufRead>(reader: &mut R) -> io::Result<Option<Result<Vec<String>, String>>> {
+    let Some(line) = read_line(reader)? else {
+        return Ok(None);
+    };
+    let Some(count) = line.strip_prefix('*') else {
+        return Ok(Some(Ok(line
+            .split_whitespace()
+            .map(str::to_string)
+            .collect())));
+    };
+    let Ok(count) = count.parse::<usize>() else {
+        return Ok(Some(Err(
+            "Protocol error: invalid multibulk length".to_string()
+        )));
+    };
+
+    let mut args = Vec::with_capacity(count.min(1024));
+    for _ in 0..count {
+        let Some(line) = read_line(reader)? else {
+            return Ok(None);
+        };
+        let len = match line.strip_prefix('$').map(str::parse::<usize>) {
+            Some(Ok(len)) if len <= MAX_BULK_LEN => len,
+            _ => return Ok(Some(Err("Protocol error: invalid bulk length".to_string()))),
+        };
+        let mut arg = vec![0; len + 2];
+        reader.read_exact(&mut arg)?;
+        arg.truncate(len);
+        match String::from_utf8(arg) {
+            Ok(arg) => args.push(arg),
+            Err(_) => return Ok(Some(Err("Arguments must be valid UTF-8".to_string()))),
+        }
+    }
+    Ok(Some(Ok(args)))
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+fn write_reply<W: Write>(writer: &mut W, reply: &Reply) -> io::Result<()> {
+    match reply {
+        Reply::Status(status) => write!(writer, "+{status}\r\n"),
+        Reply::Error(err) => write!(writer, "-ERR {}\r\n", err.replace(['\r', '\n'], " ")),
+        Reply::Integer(n) => write!(writer, ":{n}\r\n"),
+        Reply::Bulk(None) => write!(writer, "$-1\r\n"),
+        Reply::Bulk(Some(s)) => write!(writer, "${}\r\n{s}\r\n", s.len()),
+        Reply::Array(replies) => {
+            write!(writer, "*{}\r\n", replies.len())?;
+            replies
+                .iter()
+                .try_for_each(|reply| write_reply(writer, reply))
+        }
+    }
+}
+
+fn run(store: &Store<Value>, args: &[String]) -> Result<Reply, Error> {
+    let command = args[0].to_ascii_uppercase();
+    let reply = match (command.as_str(), &args[1..]) {
+        ("PING", []) => Reply::Status("PONG"),
+        ("PING", [message]) => Reply::Bulk(Some(message.clone())),
+        // Sent by `redis-cli` on startup, and allowed to be empty.
+        ("COMMAND", _) => Reply::Array(Vec::new()),
+        ("GET", [key]) => Reply::Bulk(store.get(key)?.map(|value| match value {
+            Value::String(s) => s,
+            value => value.to_string(),
+        })),
+        ("SET", [key, value]) => {
+            store.set(key, &Value::String(value.clone()))?;
+            Reply::Status("OK")
+        }
+        ("DEL", keys) if !keys.is_empty() => {
+            let mut batch = WriteBatch::new();
+            for key in keys {
+                if store.contains(key)? {
+                    batch.unset(key)?;
+                }
+            }
+            let deleted = batch.len();
+            store.apply(batch)?;
+            Reply::Integer(deleted as i64)
+        }
+        ("EXISTS", keys) if !keys.is_empty() => {
+            let mut count = 0;
+            for key in keys {
+                count += i64::from(store.contains(key)?);
+            }
+            Reply::Integer(count)
+        }
+        ("KEYS", [pattern]) => Reply::Array(
+            store
+                .keys_matching(pattern)
+                .map(|key| Reply::Bulk(Some(key)))
+                .collect(),
+        ),
+        ("SCAN", [cursor, options @ ..]) => scan(store, cursor, options),
+        ("GET" | "SET" | "DEL" | "EXISTS" | "KEYS" | "SCAN" | "PING", _) => Reply::Error(format!(
+            "wrong number of arguments for '{}' command",
+            args[0].to_ascii_lowercase()
+        )),
+        _ => Reply::Error(format!("unknown command '{}'", args[0])),
+    };
+    Ok(reply)
+}
+
+/// Runs `SCAN`, whose cursor is the position in the sorted list of matching keys from which to
+/// resume.
+fn scan(store: &Store<Value>, cursor: &str, options: &[String]) -> Reply {
+    let Ok(cursor) = cursor.parse::<usize>() else {
+        return Reply::Error("invalid cursor".to_string());
+    };
+    let (mut pattern, mut count) = ("*", 10);
+    for option in options.chunks(2) {
+        match option {
+            [name, value] if name.eq_ignore_ascii_case("MATCH") => pattern = value,
+            [name, value] if name.eq_ignore_ascii_case("COUNT") => match value.parse() {
+                Ok(value) if value > 0 => count = value,
+                _ => return Reply::Error("value is not an integer or out of range".to_string()),
+            },
+            _ => return Reply::Error("syntax error".to_string()),
+        }
+    }
+
+    let keys: Vec<String> = store
+        .keys_matching(pattern)
+        .skip(cursor)
+        .take(count)
+        .collect();
+    let next = if keys.len() < count {
+        0
+    } else {
+        cursor + keys.len()
+    };
+    let keys = keys.into_iter().map(|key| Reply::Bulk(Some(key))).collect();
+    Reply::Array(vec![
+        Reply::Bulk(Some(next.to_string())),
+        Reply::Array(keys),
+    ])
+}
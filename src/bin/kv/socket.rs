@@ -0,0 +1,81 @@
+//! A line-oriented protocol over a Unix domain socket, so that local processes can share a store
+//! through the single process which has it open.
+//!
+//! Every request is a line holding a JSON object, answered by a line holding a JSON object:
+//!
+//! - `{"op":"get","key":...}` is answered by `{"found":true,"value":...}` or `{"found":false}`.
+//! - `{"op":"set","key":...,"value":...}` and `{"op":"unset","key":...}` are answered by
+//!   `{"ok":true}`.
+//! - `{"op":"keys"}` is answered by `{"keys":[...]}`, optionally only with the keys starting with a
+//!   `"prefix"`.
+//!
+//! Failed requests are answered by `{"error":...}`.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+
+use kv::{Error, Store};
+use serde_json::{json, Value};
+
+pub fn serve(store: Store<Value>, path: &Path) -> Result<(), Error> {
+    // Left behind by a previous server, since the file outlives the socket.
+    if std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let store = store.clone();
+        std::thread::spawn(move || {
+            // Errors can only come from the connection, which is then closed.
+            let _ = handle(&store, stream);
+        });
+    }
+    Ok(())
+}
+
+fn handle(store: &Store<Value>, stream: UnixStream) -> io::Result<()> {
+    let mut writer = &stream;
+    for line in BufReader::new(&stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match serde_json::from_str(&line) {
+            Ok(request) => run(store, &request).unwrap_or_else(|err| json!({ "error": err })),
+            Err(err) => json!({ "error": err.to_string() }),
+        };
+        writeln!(writer, "{reply}")?;
+    }
+    Ok(())
+}
+
+fn run(store: &Store<Value>, request: &Value) -> Result<Value, String> {
+    let key = || request["key"].as_str().ok_or("missing `key`");
+    let reply = match request["op"].as_str() {
+        Some("get") => match store.get(key()?).map_err(|err| err.to_string())? {
+            Some(value) => json!({ "found": true, "value": value }),
+            None => json!({ "found": false }),
+        },
+        Some("set") => {
+            let value = request.get("value").ok_or("missing `value`")?;
+            store.set(key()?, value).map_err(|err| err.to_string())?;
+            json!({ "ok": true })
+        }
+        Some("unset") => {
+            store.unset(key()?).map_err(|err| err.to_string())?;
+            json!({ "ok": true })
+        }
+        Some("keys") => {
+            let prefix = request["prefix"].as_str().unwrap_or_default();
+            let keys: Vec<String> = store.keys().filter(|key| key.starts_with(prefix)).collect();
+            json!({ "keys": keys })
+        }
+        _ => return Err("`op` must be `get`, `set`, `unset` or `keys`".to_string()),
+    };
+    Ok(reply)
+}
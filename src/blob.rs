@@ -0,0 +1,231 @@
+//! Storage of large values in files of their own, as set up with
+//! [`OpenOptions::blob_threshold`](crate::OpenOptions::blob_threshold).
+//!
+//! The values of a database at `db` which are long enough are written to the `db.blobs` directory,
+//! in a file named after the SHA-256 of the value, so that identical values share it. The record
+//! in the log then holds the hash in place of the value, and is flagged as referring to a blob.
+//! Blobs hold the value as a single record of the binary format, which compresses, encrypts and
+//! checksums it like any other.
+//!
+//! References are only resolved when values are read, so opening the database and compacting it
+//! never touch the blobs, except for deleting those no longer referred to after a compaction.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::crypto::Cipher;
+use crate::record::Record;
+use crate::{sync_parent_dir, Error, Format, StoreInner};
+
+/// Path of the directory holding the blobs of the database.
+pub(crate) fn blob_dir(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".blobs");
+    path.with_file_name(file_name)
+}
+
+/// Reads the value held by the blob with the given hash, of the database at the given path.
+pub(crate) fn read_blob(
+    path: &Path,
+    hash: &[u8],
+    cipher: Option<&Cipher>,
+) -> Result<Vec<u8>, Error> {
+    let hash = std::str::from_utf8(hash)
+        .ok()
+        .filter(|hash| hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()))
+        .ok_or_else(|| Error::Read("Invalid blob reference".to_string()))?;
+    let raw = match std::fs::read(blob_dir(path).join(hash)) {
+        Ok(raw) => raw,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Err(Error::Read(format!("Missing blob `{hash}`")))
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let record = Format::Binary.decode(&raw, 0, cipher)?;
+    Ok(record.value.into_owned())
+}
+
+/// Writes the blob to the directory of the database at the given path, unless it's already there.
+fn write_blob(path: &Path, hash: &str, blob: &[u8]) -> io::Result<()> {
+    let dir = blob_dir(path);
+    let blob_path = dir.join(hash);
+    if blob_path.exists() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(&dir)?;
+    let tmp_path = dir.join(format!("{hash}.tmp"));
+    std::fs::write(&tmp_path, blob)?;
+    File::open(&tmp_path)?.sync_all()?;
+    std::fs::rename(&tmp_path, &blob_path)?;
+    sync_parent_dir(&blob_path)
+}
+
+impl StoreInner {
+    /// Returns the record to write in place of the given one to the database at the given path,
+    /// which refers to a blob holding its value if it's long enough, writing the blob beforehand.
+    ///
+    /// Blobs referred to by records written to another database, as done by
+    /// [`Store::compact_into`](crate::Store::compact_into), are copied over to it.
+    pub(crate) fn spill<'a>(
+        &self,
+        record: &Record<'a>,
+        path: Option<&Path>,
+    ) -> Result<Option<Record<'a>>, Error> {
+        let Some(path) = path else {
+            return Ok(None);
+        };
+        if record.blob {
+            if let Some(own) = self.path.as_deref().filter(|&own| own != path) {
+                let hash = String::from_utf8_lossy(&record.value);
+                let blob = std::fs::read(blob_dir(own).join(&*hash))?;
+                write_blob(path, &hash, &blob)?;
+            }
+            return Ok(None);
+        }
+        let spills = self
+            .blob_threshold
+            .is_some_and(|threshold| record.value.len() >= threshold);
+        if !spills || record.is_tombstone() {
+            return Ok(None);
+        }
+
+        let hash = format!("{:x}", Sha256::digest(&record.value));
+        let mut blob = Vec::new();
+        Format::Binary.encode(
+            &Record::new("", &record.value),
+            self.compression,
+            self.cipher.as_ref(),
+            &mut blob,
+        )?;
+        write_blob(path, &hash, &blob)?;
+        Ok(Some(Record {
+            value: Cow::Owned(hash.into_bytes()),
+            blob: true,
+            ..record.clone()
+        }))
+    }
+
+    /// Reads the value of the record from its blob if it refers to one, then migrates it to the
+    /// current version.
+    pub(crate) fn load<'a>(&self, mut record: Record<'a>) -> Result<Record<'a>, Error> {
+        if record.blob {
+            let path = self
+                .path
+                .as_deref()
+                .ok_or_else(|| Error::Read("Blobs require a database on disk".to_string()))?;
+            record.value = Cow::Owned(read_blob(path, &record.value, self.cipher.as_ref())?);
+            record.blob = false;
+        }
+        self.migrations.migrate(record)
+    }
+
+    /// Deletes the blobs which no record of the database refers to anymore.
+    pub(crate) fn collect_blobs(&self) -> Result<(), Error> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let entries = match std::fs::read_dir(blob_dir(path)) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        let referenced = self.scan(|_, record, referenced: &mut HashSet<_>| {
+            if record.blob {
+                referenced.insert(String::from_utf8_lossy(&record.value).into_owned());
+            }
+            Ok(())
+        })?;
+        for entry in entries {
+            let entry = entry?;
+            if !referenced.contains(&*entry.file_name().to_string_lossy()) {
+                std::fs::remove_file(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{Prefer, Store};
+
+    #[test]
+    fn blobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let blobs = || {
+            let mut blobs: Vec<_> = std::fs::read_dir(blob_dir(&path))
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect();
+            blobs.sort();
+            blobs
+        };
+        let large = Value::String("x".repeat(100));
+        let store = Store::<Value>::options()
+            .blob_threshold(64)
+            .open(&path)
+            .unwrap();
+        store.set("a", &large).unwrap();
+        store.set("b", &large).unwrap();
+        store.set("small", &Value::from(1)).unwrap();
+        store.flush().unwrap();
+
+        // The log only holds the hash of the value, shared by both keys.
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert!(!log.contains("xxx"));
+        assert_eq!(2, log.matches("\tblob=sha256\t").count());
+        assert_eq!(1, blobs().len());
+        assert_eq!(64, blobs()[0].len());
+        assert_eq!(Some(large.clone()), store.get("a").unwrap());
+        assert_eq!(large, store.load_map().unwrap()["b"]);
+
+        // The copy of the database gets the blobs it needs.
+        let copy = dir.path().join("copy");
+        store.compact_into(&copy).unwrap();
+        assert_eq!(
+            Some(large.clone()),
+            Store::<Value>::open(&copy).unwrap().get("b").unwrap()
+        );
+
+        // Merging into another database copies the blobs along.
+        let merged = Store::<Value>::options()
+            .blob_threshold(64)
+            .open(&dir.path().join("merged"))
+            .unwrap();
+        assert_eq!(3, merged.merge_from(&store, Prefer::Source).unwrap());
+        drop(merged);
+        let merged = Store::<Value>::open(&dir.path().join("merged")).unwrap();
+        assert_eq!(Some(large.clone()), merged.get("a").unwrap());
+        assert_eq!(Some(Value::from(1)), merged.get("small").unwrap());
+
+        // The blob is kept until no record refers to it.
+        store.unset("a").unwrap();
+        store.compact().unwrap();
+        assert_eq!(1, blobs().len());
+        store.set("b", &Value::from(2)).unwrap();
+        store.compact().unwrap();
+        assert!(blobs().is_empty());
+        drop(store);
+
+        // Values written before the threshold was set are moved to blobs by compaction.
+        let store = Store::<Value>::open(&path).unwrap();
+        store.set("c", &large).unwrap();
+        drop(store);
+        let store = Store::<Value>::options()
+            .blob_threshold(64)
+            .open(&path)
+            .unwrap();
+        store.compact().unwrap();
+        assert_eq!(1, blobs().len());
+        assert_eq!(Some(large), store.get("c").unwrap());
+    }
+}
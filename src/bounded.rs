@@ -0,0 +1,77 @@
+//! Bounded mode, in which a store behaves like a persistent cache of at most a given number of
+//! keys, as set with [`OpenOptions::max_live_keys`](crate::OpenOptions::max_live_keys).
+
+use std::collections::BTreeMap;
+
+use lru::LruCache;
+use parking_lot::Mutex;
+
+use crate::record::{Record, TOMBSTONE};
+use crate::{Error, Slot, StoreInner};
+
+/// Order in which the live keys were last written or read.
+pub(crate) struct Recency {
+    max_live_keys: usize,
+    order: Mutex<LruCache<String, ()>>,
+}
+
+impl Recency {
+    /// Starts tracking the keys of the index, which are taken to have been used in the order in
+    /// which they were written, since reads aren't persisted.
+    pub fn new(max_live_keys: usize, index: &BTreeMap<String, Slot>) -> Self {
+        let mut keys: Vec<_> = index.iter().collect();
+        keys.sort_unstable_by_key(|(_, slot)| slot.location);
+        let mut order = LruCache::unbounded();
+        for (key, _) in keys {
+            order.put(key.clone(), ());
+        }
+        Self {
+            max_live_keys,
+            order: Mutex::new(order),
+        }
+    }
+
+    /// Marks the key as the most recently used, if it's tracked.
+    pub fn touch(&self, key: &str) {
+        self.order.lock().promote(key);
+    }
+
+    /// Tracks the key as the most recently used, or stops tracking it if it got unset.
+    pub fn record(&self, record: &Record) {
+        let mut order = self.order.lock();
+        if record.is_tombstone() {
+            order.pop(&*record.key);
+        } else {
+            order.put(record.key.to_string(), ());
+        }
+    }
+
+    /// Stops tracking the key, which was removed from the index without a tombstone.
+    pub fn forget(&self, key: &str) {
+        self.order.lock().pop(key);
+    }
+}
+
+impl StoreInner {
+    /// Unsets the least recently used keys while there are more than the store allows.
+    pub(crate) fn evict_lru(&mut self) -> Result<(), Error> {
+        let Some(recency) = &self.recency else {
+            return Ok(());
+        };
+        let excess = self.index.len().saturating_sub(recency.max_live_keys);
+        if excess == 0 {
+            return Ok(());
+        }
+        let keys: Vec<String> = {
+            let mut order = recency.order.lock();
+            (0..excess)
+                .map_while(|_| order.pop_lru().map(|(key, ())| key))
+                .collect()
+        };
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let records: Vec<Record> = keys.iter().map(|k| Record::new(k, TOMBSTONE)).collect();
+        self.append(&records)
+    }
+}
@@ -0,0 +1,139 @@
+use std::ops::Bound;
+use std::time::Duration;
+
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{Codec, Error, JsonCodec, Store};
+
+/// A typed view over the keys of a [`Store`] which belong to a named bucket.
+///
+/// Created by [`Store::bucket`]. The keys of the bucket are stored in the database prefixed with
+/// its name and a `/`, so `set("abc", ..)` on the `sessions` bucket writes the `sessions/abc` key.
+/// The prefix is added and stripped transparently, so the keys of different buckets never clash,
+/// but the entries remain visible under their full key through the store itself.
+pub struct Bucket<T, C = JsonCodec> {
+    store: Store<T, C>,
+    prefix: String,
+}
+
+impl<T, C> Clone for Bucket<T, C> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            prefix: self.prefix.clone(),
+        }
+    }
+}
+
+impl<T, C> Bucket<T, C> {
+    pub(crate) fn new(store: Store<T, C>, name: &str) -> Self {
+        Self {
+            store,
+            prefix: format!("{name}/"),
+        }
+    }
+
+    /// Name of the bucket.
+    pub fn name(&self) -> &str {
+        &self.prefix[..self.prefix.len() - 1]
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{key}", self.prefix)
+    }
+
+    /// See [`Store::unset`].
+    pub fn unset(&self, key: &str) -> Result<(), Error> {
+        self.store.unset(&self.key(key))
+    }
+
+    /// See [`Store::contains`].
+    pub fn contains(&self, key: &str) -> Result<bool, Error> {
+        self.store.contains(&self.key(key))
+    }
+
+    /// Returns the keys of the bucket that currently hold a value, without the bucket's prefix, in
+    /// lexicographic order.
+    pub fn keys(&self) -> impl Iterator<Item = String> {
+        let keys: Vec<String> = self
+            .store
+            .0
+            .read()
+            .live_range((Bound::Included(&*self.prefix), Bound::Unbounded))
+            .map_while(|(k, _)| k.strip_prefix(&self.prefix))
+            .map(str::to_string)
+            .collect();
+        keys.into_iter()
+    }
+}
+
+impl<T: Serialize, C: Codec> Bucket<T, C> {
+    /// See [`Store::set`].
+    pub fn set(&self, key: &str, value: &T) -> Result<(), Error> {
+        self.store.set(&self.key(key), value)
+    }
+
+    /// See [`Store::set_with_ttl`].
+    pub fn set_with_ttl(&self, key: &str, value: &T, ttl: Duration) -> Result<(), Error> {
+        self.store.set_with_ttl(&self.key(key), value, ttl)
+    }
+}
+
+impl<T, C> Bucket<T, C>
+where
+    T: for<'a> Deserialize<'a>,
+    C: Codec,
+{
+    /// See [`Store::get`].
+    pub fn get(&self, key: &str) -> Result<Option<T>, Error> {
+        self.store.get(&self.key(key))
+    }
+
+    /// Returns an iterator over the live entries of the bucket, without the bucket's prefix, in
+    /// lexicographic key order.
+    pub fn iter(&self) -> impl Iterator<Item = Result<(String, T), Error>> {
+        let len = self.prefix.len();
+        self.store
+            .scan_prefix(&self.prefix)
+            .map(move |entry| entry.map(|(key, value)| (key[len..].to_string(), value)))
+    }
+
+    /// Loads the entries of the bucket in memory, without the bucket's prefix.
+    pub fn load_map(&self) -> Result<FxHashMap<String, T>, Error> {
+        self.iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    #[test]
+    fn buckets() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u32>::open(f.path()).unwrap();
+        let sessions = store.bucket::<String>("sessions");
+        let counts = store.bucket::<u32>("counts");
+
+        sessions.set("a", &"abc".to_string()).unwrap();
+        counts.set("a", &1).unwrap();
+        store.set("a", &2).unwrap();
+
+        assert_eq!(Some("abc".to_string()), sessions.get("a").unwrap());
+        assert_eq!(Some(1), counts.get("a").unwrap());
+        assert_eq!(Some(2), store.get("a").unwrap());
+        assert_eq!(vec!["a".to_string()], sessions.keys().collect::<Vec<_>>());
+        assert_eq!(Some(1), store.get("counts/a").unwrap());
+
+        counts.unset("a").unwrap();
+        assert!(!counts.contains("a").unwrap());
+        assert!(counts.load_map().unwrap().is_empty());
+        assert_eq!(
+            vec![("a".to_string(), "abc".to_string())],
+            sessions.iter().collect::<Result<Vec<_>, _>>().unwrap()
+        );
+    }
+}
@@ -0,0 +1,264 @@
+//! Changefeed over the log of a database, from which other systems can keep a copy of it up to date
+//! without loading it again in full.
+
+use std::{io, vec};
+
+use serde::Deserialize;
+
+use crate::record::Record;
+use crate::{Codec, Error, Event, JsonCodec, Position, Store, StoreInner};
+
+/// Number of records read from the log at a time by [`Changes`].
+const CHUNK: usize = 256;
+
+/// A write made to a [`Store`], as reported by [`Store::changes_since`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change<T> {
+    /// Position right after the record of the change, from which to ask for the next ones.
+    pub seq: Position,
+    pub event: Event<T>,
+}
+
+/// Streaming iterator over the writes made to a [`Store`] since a position.
+///
+/// Created by [`Store::changes_since`]. The log is read a few hundred records at a time, without
+/// holding the lock of the store in between, so memory usage stays bounded however far behind the
+/// position is. Writes made while iterating are returned too.
+pub struct Changes<T, C = JsonCodec> {
+    store: Store<T, C>,
+    /// Position right after the last record read.
+    seq: Position,
+    changes: vec::IntoIter<Change<T>>,
+}
+
+impl<T, C> Store<T, C> {
+    /// Returns the position of the end of the log, from which [`Store::changes_since`] returns the
+    /// writes made from now on.
+    ///
+    /// To start a copy of the database, take the position before loading it in full, then apply
+    /// the changes since: those which the copy already holds are simply applied twice.
+    pub fn current_seq(&self) -> Position {
+        let inner = self.read();
+        let active = inner.active();
+        Position {
+            segment: active.id,
+            offset: active.len.max(inner.header.len() as u64),
+            generation: inner.generation(),
+        }
+    }
+}
+
+impl<T, C> Store<T, C>
+where
+    T: for<'a> Deserialize<'a>,
+    C: Codec,
+{
+    /// Returns the writes made since the given position, as returned by [`Store::current_seq`] or
+    /// as the `seq` of the last change read, in the order in which they were made.
+    ///
+    /// Positions point into the files of the database, like those of a [`Tail`](crate::Tail), so
+    /// they only remain valid until the next compaction, after which the copy must be started over.
+    /// Fails with [`Error::Read`] for a position taken before a compaction, which the iterator also
+    /// returns if the database gets compacted while iterating. Records whose value can't be
+    /// decoded are skipped.
+    pub fn changes_since(&self, seq: Position) -> Result<Changes<T, C>, Error> {
+        self.read().check_position(seq)?;
+        Ok(Changes {
+            store: self.clone(),
+            seq,
+            changes: Vec::new().into_iter(),
+        })
+    }
+}
+
+impl<T, C> Iterator for Changes<T, C>
+where
+    T: for<'a> Deserialize<'a>,
+    C: Codec,
+{
+    type Item = Result<Change<T>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(change) = self.changes.next() {
+                return Some(Ok(change));
+            }
+
+            let inner = self.store.read();
+            let mut changes = Vec::new();
+            let mut end = self.seq;
+            let read = inner.scan_since(self.seq, CHUNK, |seq, record| {
+                end = seq;
+                let record = inner.load(record)?;
+                if let Some(event) = Event::from_record::<C>(&record) {
+                    changes.push(Change { seq, event });
+                }
+                Ok(())
+            });
+            match read {
+                Ok(0) => return None,
+                Ok(_) => {
+                    self.seq = end;
+                    self.changes = changes.into_iter();
+                }
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+impl StoreInner {
+    /// Fails with [`Error::Read`] if the position was taken before a compaction.
+    fn check_position(&self, position: Position) -> Result<usize, Error> {
+        let stale = || Error::Read("Position is no longer in the log".to_string());
+        if position.generation != self.generation() {
+            return Err(stale());
+        }
+        let i = self
+            .segments
+            .binary_search_by_key(&position.segment, |segment| segment.id)
+            .map_err(|_| stale())?;
+        if position.offset > self.segments[i].len.max(self.header.len() as u64) {
+            return Err(stale());
+        }
+        Ok(i)
+    }
+
+    /// Calls `f` with at most `limit` records from the given position on, along with the position
+    /// right after each, and returns how many there were.
+    fn scan_since<F>(&self, since: Position, limit: usize, mut f: F) -> Result<usize, Error>
+    where
+        F: FnMut(Position, Record) -> Result<(), Error>,
+    {
+        let first = self.check_position(since)?;
+        let header_len = self.header.len() as u64;
+        let mut count = 0;
+        for segment in &self.segments[first..] {
+            if count == limit {
+                break;
+            }
+            let mut offset = header_len;
+            if segment.id == since.segment {
+                offset = offset.max(since.offset);
+            }
+            let mut reader = io::BufReader::new(segment.reader_at(offset));
+            let mut raw = Vec::new();
+            while count < limit {
+                raw.clear();
+                let read = self.format.read(&mut reader, &mut raw)?;
+                if read == 0 {
+                    break;
+                }
+                let record = self.decode(&raw, offset)?;
+                offset += read as u64;
+                let seq = Position {
+                    segment: segment.id,
+                    offset,
+                    generation: since.generation,
+                };
+                f(seq, record)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_since() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::<u32>::options()
+            .segment_size(64)
+            .open(&dir.path().join("db"))
+            .unwrap();
+        store.set("a", &1).unwrap();
+        let seq = store.current_seq();
+        assert_eq!(0, store.changes_since(seq).unwrap().count());
+
+        for i in 0..5 {
+            store.set("b", &i).unwrap();
+        }
+        store.unset("a").unwrap();
+        let changes: Vec<_> = store
+            .changes_since(seq)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(6, changes.len());
+        assert_eq!(
+            Event::Set {
+                key: "b".to_string(),
+                value: 0
+            },
+            changes[0].event
+        );
+        assert_eq!(
+            Event::Unset {
+                key: "a".to_string()
+            },
+            changes[5].event
+        );
+        // Segments were rotated along the way.
+        assert!(changes[5].seq.segment > seq.segment);
+        assert_eq!(store.current_seq(), changes[5].seq);
+
+        let rest: Vec<_> = store
+            .changes_since(changes[3].seq)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(&changes[4..], &rest[..]);
+
+        store.compact().unwrap();
+        assert!(matches!(store.changes_since(seq), Err(Error::Read(_))));
+    }
+
+    #[test]
+    fn changes_since_compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let store = Store::<u32>::open(&path).unwrap();
+        for i in 0..1000 {
+            store.set(&format!("key {i}"), &i).unwrap();
+        }
+        let seq = store.current_seq();
+        // More than a chunk, read while writing.
+        for i in 0..300 {
+            store.set("a", &i).unwrap();
+        }
+        let mut changes = store.changes_since(seq).unwrap();
+        assert!(matches!(
+            changes.next(),
+            Some(Ok(Change {
+                event: Event::Set { value: 0, .. },
+                ..
+            }))
+        ));
+        store.set("a", &300).unwrap();
+        assert_eq!(300, changes.count());
+
+        // The file is still longer than the position once compacted.
+        store.compact().unwrap();
+        assert!(std::fs::metadata(&path).unwrap().len() > seq.offset);
+        assert!(matches!(store.changes_since(seq), Err(Error::Read(_))));
+        let mut changes = store.changes_since(store.current_seq()).unwrap();
+        store.set("b", &1).unwrap();
+        store.compact().unwrap();
+        assert!(matches!(changes.next(), Some(Err(Error::Read(_)))));
+
+        // Still refused once reopened.
+        let seq = store.current_seq();
+        drop(changes);
+        drop(store);
+        let store = Store::<u32>::open(&path).unwrap();
+        assert_eq!(seq, store.current_seq());
+        store.set("c", &1).unwrap();
+        assert_eq!(1, store.changes_since(seq).unwrap().count());
+        store.compact().unwrap();
+        assert!(matches!(store.changes_since(seq), Err(Error::Read(_))));
+    }
+}
@@ -0,0 +1,60 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{decode_err, encode_err, Error};
+
+/// How values are turned into the bytes stored in the database, and back.
+///
+/// The codec is picked through the second type parameter of [`Store`](crate::Store), which
+/// defaults to [`JsonCodec`]. Implementations should report failures to serialize as
+/// [`Error::Encode`] and failures to deserialize as [`Error::Decode`], with the error of the
+/// serializer as their source.
+///
+/// A database must always be opened with the codec it was written with, which is only checked for
+/// codecs with a [`NAME`](Codec::NAME), since unnamed ones aren't recorded in the database.
+pub trait Codec {
+    /// Name recorded in the header of the databases created with the codec, so that opening them
+    /// with another one fails with [`Error::CodecMismatch`]. Databases created with an unnamed
+    /// codec aren't checked.
+    const NAME: Option<&'static str> = None;
+
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error>;
+
+    fn decode<T: for<'a> Deserialize<'a>>(bytes: &[u8]) -> Result<T, Error>;
+}
+
+/// Stores values as JSON, which keeps the database human-readable.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    const NAME: Option<&'static str> = Some("json");
+
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+        serde_json::to_vec(value).map_err(encode_err)
+    }
+
+    fn decode<T: for<'a> Deserialize<'a>>(bytes: &[u8]) -> Result<T, Error> {
+        serde_json::from_slice(bytes).map_err(decode_err)
+    }
+}
+
+/// Stores values as MessagePack, which is considerably more compact than JSON for nested data.
+///
+/// Structs are encoded as maps keyed by field name rather than as arrays, so that fields can be
+/// added or reordered without breaking existing databases.
+#[cfg(feature = "msgpack")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MsgPackCodec;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MsgPackCodec {
+    const NAME: Option<&'static str> = Some("msgpack");
+
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+        rmp_serde::to_vec_named(value).map_err(encode_err)
+    }
+
+    fn decode<T: for<'a> Deserialize<'a>>(bytes: &[u8]) -> Result<T, Error> {
+        rmp_serde::from_slice(bytes).map_err(decode_err)
+    }
+}
@@ -0,0 +1,63 @@
+//! Encryption of values at rest.
+//!
+//! Values are encrypted with XChaCha20-Poly1305 under the key given through
+//! [`OpenOptions::encryption_key`](crate::OpenOptions::encryption_key), each with a random 24-byte
+//! nonce which is stored in front of the ciphertext. Keys, expirations and tombstones are left in
+//! plaintext so that the index can be rebuilt without decrypting anything but the values.
+
+use crate::Error;
+
+#[cfg(feature = "encryption")]
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+#[cfg(feature = "encryption")]
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+#[cfg(feature = "encryption")]
+const NONCE_LEN: usize = 24;
+
+/// Encrypts and decrypts values under the key of the store.
+#[cfg(feature = "encryption")]
+pub(crate) struct Cipher(XChaCha20Poly1305);
+
+/// Stands in for the cipher when the `encryption` feature is off, which can never be created.
+#[cfg(not(feature = "encryption"))]
+pub(crate) enum Cipher {}
+
+#[cfg(feature = "encryption")]
+impl Cipher {
+    pub fn new(key: &[u8; 32]) -> Self {
+        Self(XChaCha20Poly1305::new(key.into()))
+    }
+
+    pub fn encrypt(&self, value: &[u8]) -> Result<Vec<u8>, Error> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .0
+            .encrypt(&nonce, value)
+            .map_err(|_| Error::Write("Unable to encrypt value".to_string()))?;
+        let mut encrypted = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        encrypted.extend_from_slice(&nonce);
+        encrypted.extend_from_slice(&ciphertext);
+        Ok(encrypted)
+    }
+
+    pub fn decrypt(&self, value: &[u8], offset: u64) -> Result<Vec<u8>, Error> {
+        let (nonce, ciphertext) = value
+            .split_at_checked(NONCE_LEN)
+            .ok_or(Error::Decrypt { offset })?;
+        self.0
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| Error::Decrypt { offset })
+    }
+}
+
+#[cfg(not(feature = "encryption"))]
+impl Cipher {
+    pub fn encrypt(&self, _value: &[u8]) -> Result<Vec<u8>, Error> {
+        match *self {}
+    }
+
+    pub fn decrypt(&self, _value: &[u8], _offset: u64) -> Result<Vec<u8>, Error> {
+        match *self {}
+    }
+}
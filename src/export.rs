@@ -0,0 +1,258 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::record::{Record, TOMBSTONE};
+use crate::{decode_err, encode_err, Codec, Error, Store};
+
+/// What happens to the existing entries of the database when importing data into it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ImportMode {
+    /// Keep the existing entries, overwriting those whose key is imported.
+    #[default]
+    Merge,
+    /// Unset every existing key which isn't imported.
+    Truncate,
+}
+
+impl<T, C> Store<T, C>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+    C: Codec,
+{
+    /// Writes the live entries of the database to `writer` as a single JSON object mapping every
+    /// key to its value, in lexicographic key order.
+    ///
+    /// Values are written as JSON whatever the codec of the store is. Entries are read one at a
+    /// time, so the database never has to fit in memory.
+    pub fn export_json<W: Write>(&self, writer: W) -> Result<(), Error> {
+        let mut writer = std::io::BufWriter::new(writer);
+        writer.write_all(b"{")?;
+        for (i, entry) in self.range(..).enumerate() {
+            let (key, value) = entry?;
+            if i > 0 {
+                writer.write_all(b",")?;
+            }
+            serde_json::to_writer(&mut writer, &key).map_err(encode_err)?;
+            writer.write_all(b":")?;
+            serde_json::to_writer(&mut writer, &value).map_err(encode_err)?;
+        }
+        writer.write_all(b"}")?;
+        Ok(writer.flush()?)
+    }
+
+    /// Writes the live entries of the database to `writer` as RFC 4180 CSV, in lexicographic key
+    /// order.
+    ///
+    /// The output starts with a `key,value` header, followed by one row per entry whose value is
+    /// written as JSON. Fields are quoted whenever they contain a comma, a quote or a line break.
+    pub fn export_csv<W: Write>(&self, writer: W) -> Result<(), Error> {
+        let mut writer = std::io::BufWriter::new(writer);
+        writer.write_all(b"key,value\r\n")?;
+        for entry in self.range(..) {
+            let (key, value) = entry?;
+            let value = serde_json::to_string(&value).map_err(encode_err)?;
+            write!(writer, "{},{}\r\n", csv_field(&key), csv_field(&value))?;
+        }
+        Ok(writer.flush()?)
+    }
+
+    /// Sets every key of the JSON object read from `reader` to its value, as written by
+    /// [`Store::export_json`].
+    ///
+    /// The whole object is parsed before anything is written, and every record is then appended
+    /// with a single write, so a malformed document leaves the database untouched.
+    pub fn import_json<R: Read>(&self, reader: R, mode: ImportMode) -> Result<(), Error> {
+        let entries: BTreeMap<String, T> =
+            serde_json::from_reader(std::io::BufReader::new(reader)).map_err(decode_err)?;
+        self.import_entries(&entries, mode)
+    }
+
+    /// Writes the live entries of the database to a new `kv` table of the SQLite database at the
+    /// given path, which gets created if needed.
+    ///
+    /// The table has a `key` text column, which is its primary key, and a `value` text column
+    /// holding values as JSON, so that they can be queried with SQLite's JSON functions. Fails if
+    /// the SQLite database already has a `kv` table.
+    #[cfg(feature = "sqlite")]
+    pub fn export_sqlite(&self, path: &std::path::Path) -> Result<(), Error> {
+        let mut conn = rusqlite::Connection::open(path)?;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "CREATE TABLE kv (key TEXT PRIMARY KEY NOT NULL, value TEXT NOT NULL)",
+            (),
+        )?;
+        {
+            let mut insert = tx.prepare("INSERT INTO kv (key, value) VALUES (?1, ?2)")?;
+            for entry in self.range(..) {
+                let (key, value) = entry?;
+                let value = serde_json::to_string(&value).map_err(encode_err)?;
+                insert.execute((key, value))?;
+            }
+        }
+        Ok(tx.commit()?)
+    }
+
+    /// Sets every key of the `kv` table of the SQLite database at the given path to its value, as
+    /// written by [`Store::export_sqlite`].
+    ///
+    /// As with [`Store::import_json`], every row is read before anything is written.
+    #[cfg(feature = "sqlite")]
+    pub fn import_sqlite(&self, path: &std::path::Path, mode: ImportMode) -> Result<(), Error> {
+        let conn = rusqlite::Connection::open_with_flags(
+            path,
+            rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
+        )?;
+        let mut select = conn.prepare("SELECT key, value FROM kv")?;
+        let mut rows = select.query(())?;
+        let mut entries = BTreeMap::new();
+        while let Some(row) = rows.next()? {
+            let key: String = row.get(0)?;
+            let value: String = row.get(1)?;
+            let value: T = serde_json::from_str(&value).map_err(decode_err)?;
+            entries.insert(key, value);
+        }
+        self.import_entries(&entries, mode)
+    }
+
+    /// Sets every key to its value with a single write.
+    fn import_entries(&self, entries: &BTreeMap<String, T>, mode: ImportMode) -> Result<(), Error> {
+        let values = entries
+            .iter()
+            .map(|(key, value)| Ok((key, C::encode(value)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let mut inner = self.0.write();
+        let removed: Vec<String> = match mode {
+            ImportMode::Merge => Vec::new(),
+            ImportMode::Truncate => inner
+                .live()
+                .map(|(key, _)| key)
+                .filter(|key| !entries.contains_key(*key))
+                .cloned()
+                .collect(),
+        };
+        let records: Vec<Record> = removed
+            .iter()
+            .map(|key| Record::new(key, TOMBSTONE))
+            .chain(values.iter().map(|(key, value)| Record::new(key, value)))
+            .collect();
+        if records.is_empty() {
+            return Ok(());
+        }
+        inner.append(&records)
+    }
+}
+
+/// Quotes a CSV field if needed.
+fn csv_field(field: &str) -> std::borrow::Cow<'_, str> {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\"")).into()
+    } else {
+        field.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    #[test]
+    fn export_json() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<Vec<u32>>::open(f.path()).unwrap();
+        store.set("b", &vec![1, 2]).unwrap();
+        store.set("a\"", &vec![]).unwrap();
+        store.set("c", &vec![3]).unwrap();
+        store.unset("c").unwrap();
+
+        let mut out = Vec::new();
+        store.export_json(&mut out).unwrap();
+        assert_eq!(r#"{"a\"":[],"b":[1,2]}"#, String::from_utf8(out).unwrap());
+
+        let f = NamedTempFile::new().unwrap();
+        let mut out = Vec::new();
+        Store::<u32>::open(f.path())
+            .unwrap()
+            .export_json(&mut out)
+            .unwrap();
+        assert_eq!(b"{}", &out[..]);
+    }
+
+    #[test]
+    fn export_csv() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<serde_json::Value>::open(f.path()).unwrap();
+        store.set("a", &serde_json::json!(1)).unwrap();
+        store.set("b,c", &serde_json::json!("x,y")).unwrap();
+        store.set("d\ne", &serde_json::json!([1, 2])).unwrap();
+
+        let mut out = Vec::new();
+        store.export_csv(&mut out).unwrap();
+        assert_eq!(
+            concat!(
+                "key,value\r\n",
+                "a,1\r\n",
+                r#""b,c","""x,y""""#,
+                "\r\n",
+                "\"d\ne\",\"[1,2]\"\r\n"
+            ),
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn import_json() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u32>::open(f.path()).unwrap();
+        store.set("a", &1).unwrap();
+        store.set("b", &2).unwrap();
+
+        store
+            .import_json(&br#"{"b":3,"c":4}"#[..], ImportMode::Merge)
+            .unwrap();
+        assert_eq!(vec![Some(1), Some(3), Some(4)], values(&store));
+
+        assert!(store
+            .import_json(&br#"{"d":"x"}"#[..], ImportMode::Truncate)
+            .is_err());
+        assert_eq!(vec![Some(1), Some(3), Some(4)], values(&store));
+
+        store
+            .import_json(&br#"{"c":5}"#[..], ImportMode::Truncate)
+            .unwrap();
+        assert_eq!(vec![None, None, Some(5)], values(&store));
+    }
+
+    #[test]
+    #[cfg(feature = "sqlite")]
+    fn sqlite() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::<u32>::in_memory();
+        store.set("a", &1).unwrap();
+        store.set("b", &2).unwrap();
+        store.unset("b").unwrap();
+        store.set("c", &3).unwrap();
+
+        let path = dir.path().join("out.db");
+        store.export_sqlite(&path).unwrap();
+        assert!(store.export_sqlite(&path).is_err());
+
+        let copy = Store::<u32>::in_memory();
+        copy.set("b", &4).unwrap();
+        copy.import_sqlite(&path, ImportMode::Merge).unwrap();
+        assert_eq!(vec![Some(1), Some(4), Some(3)], values(&copy));
+        copy.import_sqlite(&path, ImportMode::Truncate).unwrap();
+        assert_eq!(vec![Some(1), None, Some(3)], values(&copy));
+    }
+
+    fn values(store: &Store<u32>) -> Vec<Option<u32>> {
+        ["a", "b", "c"]
+            .into_iter()
+            .map(|key| store.get(key).unwrap())
+            .collect()
+    }
+}
@@ -0,0 +1,64 @@
+//! Matching of keys against glob patterns, where `*` matches any sequence of characters and `?`
+//! matches a single character. Every other character matches itself.
+
+/// Part of the pattern before its first wildcard, which every matching key starts with.
+pub(crate) fn literal_prefix(pattern: &str) -> &str {
+    let end = pattern.find(['*', '?']).unwrap_or(pattern.len());
+    &pattern[..end]
+}
+
+/// Checks whether the whole key matches the pattern.
+pub(crate) fn matches(pattern: &str, key: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let key: Vec<char> = key.chars().collect();
+    let (mut p, mut k) = (0, 0);
+    // Position of the last `*` in the pattern, and of the key where it started matching.
+    let mut backtrack = None;
+
+    while k < key.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, k));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == key[k] => {
+                p += 1;
+                k += 1;
+            }
+            // Let the last `*` swallow one more character and try again.
+            _ => match backtrack {
+                Some((star, start)) => {
+                    backtrack = Some((star, start + 1));
+                    p = star + 1;
+                    k = start + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob() {
+        assert!(matches("user:*", "user:1"));
+        assert!(matches("user:*", "user:"));
+        assert!(!matches("user:*", "users:1"));
+        assert!(matches("*:?", "user:1"));
+        assert!(!matches("*:?", "user:12"));
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(!matches("a*b*c", "aXbYbZ"));
+        assert!(matches("é?", "éà"));
+        assert!(matches("", ""));
+        assert!(!matches("", "a"));
+
+        assert_eq!("user:", literal_prefix("user:*:name"));
+        assert_eq!("a", literal_prefix("a?"));
+        assert_eq!("abc", literal_prefix("abc"));
+    }
+}
@@ -0,0 +1,357 @@
+//! Group commit of the writes made concurrently through clones of a [`Store`](crate::Store).
+//!
+//! Writers queue their records, and the first of them to find no write in progress becomes the
+//! leader: it takes every queued record, including those queued while it waits for the lock of
+//! the store, and appends them all at once, with a single write and a single sync. The other
+//! writers wait for the leader to report the outcome of theirs, unless they become the leader of
+//! the next group.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::panic;
+use std::{fmt, io};
+
+use parking_lot::{Condvar, Mutex, MutexGuard};
+
+use crate::record::{OwnedRecord, Record};
+use crate::Error;
+
+/// Failure of a write, telling whether the records can be written again.
+pub(crate) enum WriteError {
+    /// The records were refused before anything was written.
+    Refused(Error),
+    /// The records may have been written, so writing them again could duplicate them.
+    Failed(Error),
+    /// The records were written, but a hook panicked while being told of them, with this payload.
+    Panicked(Box<dyn Any + Send>),
+}
+
+impl WriteError {
+    /// Returns the error, resuming the panic of the hook if one panicked.
+    pub fn into_inner(self) -> Error {
+        match self {
+            Self::Refused(err) | Self::Failed(err) => err,
+            Self::Panicked(payload) => panic::resume_unwind(payload),
+        }
+    }
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Refused(err) | Self::Failed(err) => err.fmt(f),
+            Self::Panicked(_) => f.write_str("A hook panicked"),
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct GroupCommit {
+    queue: Mutex<Queue>,
+    committed: Condvar,
+}
+
+#[derive(Default)]
+struct Queue {
+    /// Ticket of the next write to be queued.
+    next: u64,
+    /// Writes waiting for a leader, with their ticket.
+    pending: Vec<(u64, Vec<OwnedRecord>)>,
+    /// Outcome of the writes which were made, until their writer picks it up.
+    results: HashMap<u64, Result<(), Error>>,
+    leading: bool,
+}
+
+impl GroupCommit {
+    /// Writes the records with `write`, along with those of the concurrent calls, and returns the
+    /// outcome of the write of these.
+    ///
+    /// Records are written in the order in which the calls queued them, and those of a single call
+    /// are never split.
+    pub fn commit<F>(&self, records: Vec<OwnedRecord>, write: F) -> Result<(), Error>
+    where
+        F: Fn(&[Record]) -> Result<(), WriteError>,
+    {
+        let mut queue = self.queue.lock();
+        let ticket = queue.next;
+        queue.next += 1;
+        queue.pending.push((ticket, records));
+        loop {
+            if let Some(result) = queue.results.remove(&ticket) {
+                return result;
+            }
+            if queue.leading {
+                self.committed.wait(&mut queue);
+                continue;
+            }
+
+            queue.leading = true;
+            let group = std::mem::take(&mut queue.pending);
+            let (results, panicked) = MutexGuard::unlocked(&mut queue, || {
+                let mut abandon = Abandon {
+                    commit: self,
+                    tickets: group
+                        .iter()
+                        .map(|(t, _)| *t)
+                        .filter(|&t| t != ticket)
+                        .collect(),
+                };
+                let written = write_group(group, &write);
+                abandon.tickets.clear();
+                written
+            });
+            queue.leading = false;
+            queue.results.extend(results);
+            self.committed.notify_all();
+            // The hook panicked in the thread of the leader, so that's where the panic goes on,
+            // once the others know their records were written.
+            if let Some(payload) = panicked {
+                queue.results.remove(&ticket);
+                drop(queue);
+                panic::resume_unwind(payload);
+            }
+        }
+    }
+}
+
+/// Fails the writes of the other calls of a group if its leader panics while writing it, other than
+/// in a hook, and hands the lead over, so that their writers don't wait for it forever.
+struct Abandon<'a> {
+    commit: &'a GroupCommit,
+    /// Tickets of the calls still waiting for the outcome of their write.
+    tickets: Vec<u64>,
+}
+
+impl Drop for Abandon<'_> {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            return;
+        }
+        let mut queue = self.commit.queue.lock();
+        queue.leading = false;
+        for &ticket in &self.tickets {
+            let err = Error::Write("The write of the group panicked".to_string());
+            queue.results.insert(ticket, Err(err));
+        }
+        self.commit.committed.notify_all();
+    }
+}
+
+/// Writes the records of the group at once, or those of every call on their own if they were
+/// refused, so that a call whose records are refused doesn't fail the others.
+///
+/// A write which failed once it may have started is reported to every call instead, since writing
+/// the records again could duplicate them. Records whose hooks panicked were written, so their
+/// calls succeed, and the payload of the panic is returned to be resumed once they know it.
+fn write_group<F>(group: Vec<(u64, Vec<OwnedRecord>)>, write: &F) -> Written
+where
+    F: Fn(&[Record]) -> Result<(), WriteError>,
+{
+    let records: Vec<Record> = group
+        .iter()
+        .flat_map(|(_, records)| records.iter().map(OwnedRecord::as_record))
+        .collect();
+    let mut panicked = None;
+    let results = match write(&records) {
+        Ok(()) => group
+            .into_iter()
+            .map(|(ticket, _)| (ticket, Ok(())))
+            .collect(),
+        Err(WriteError::Panicked(payload)) => {
+            panicked = Some(payload);
+            group
+                .into_iter()
+                .map(|(ticket, _)| (ticket, Ok(())))
+                .collect()
+        }
+        Err(WriteError::Refused(_)) if group.len() > 1 => group
+            .into_iter()
+            .map(|(ticket, records)| {
+                let records: Vec<Record> = records.iter().map(OwnedRecord::as_record).collect();
+                let result = match write(&records) {
+                    Err(WriteError::Panicked(payload)) => {
+                        panicked.get_or_insert(payload);
+                        Ok(())
+                    }
+                    result => result.map_err(WriteError::into_inner),
+                };
+                (ticket, result)
+            })
+            .collect(),
+        Err(err) => {
+            let err = err.into_inner();
+            let mut results: Vec<_> = group[1..]
+                .iter()
+                .map(|(ticket, _)| (*ticket, Err(duplicate(&err))))
+                .collect();
+            results.insert(0, (group[0].0, Err(err)));
+            results
+        }
+    };
+    (results, panicked)
+}
+
+/// Outcome of the write of every call of a group, along with the payload of a hook which panicked.
+type Written = (Vec<(u64, Result<(), Error>)>, Option<Box<dyn Any + Send>>);
+
+/// Returns a copy of the error, for every call of a group to get the same one. Errors which can't
+/// be cloned are rebuilt with the same variant and message, and the same kind for I/O errors.
+fn duplicate(err: &Error) -> Error {
+    match err {
+        Error::Io(err) => Error::Io(io::Error::new(err.kind(), err.to_string())),
+        Error::Encode(err) => Error::Encode(err.to_string().into()),
+        Error::Decode(err) => Error::Decode(err.to_string().into()),
+        #[cfg(feature = "sqlite")]
+        Error::Sqlite(err) => Error::Write(err.to_string()),
+        Error::Read(message) => Error::Read(message.clone()),
+        Error::Write(message) => Error::Write(message.clone()),
+        Error::Corrupt { offset, line } => Error::Corrupt {
+            offset: *offset,
+            line: line.clone(),
+        },
+        Error::Locked { owner } => Error::Locked { owner: *owner },
+        Error::Decrypt { offset } => Error::Decrypt { offset: *offset },
+        Error::ReadOnly => Error::ReadOnly,
+        Error::InvalidKey(key) => Error::InvalidKey(key.clone()),
+        Error::ValueTooLarge { key, size, max } => Error::ValueTooLarge {
+            key: key.clone(),
+            size: *size,
+            max: *max,
+        },
+        Error::Full => Error::Full,
+        Error::TypeMismatch { expected, found } => Error::TypeMismatch {
+            expected: expected.clone(),
+            found: found.clone(),
+        },
+        Error::CodecMismatch { expected, found } => Error::CodecMismatch {
+            expected: expected.clone(),
+            found: found.clone(),
+        },
+        Error::Query(message) => Error::Query(message.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Barrier;
+
+    use super::*;
+    use crate::{KeyPolicy, Store, SyncPolicy};
+
+    #[test]
+    fn group_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::<u32>::options()
+            .sync(SyncPolicy::EveryWrite)
+            .key_policy(KeyPolicy::Strict)
+            .open(&dir.path().join("db"))
+            .unwrap();
+        let barrier = Barrier::new(8);
+        std::thread::scope(|scope| {
+            for thread in 0..8 {
+                let (store, barrier) = (store.clone(), &barrier);
+                scope.spawn(move || {
+                    barrier.wait();
+                    for i in 0..50 {
+                        store.set(&format!("{thread}-{i}"), &i).unwrap();
+                        // Refused writes don't affect those grouped with them.
+                        assert!(matches!(
+                            store.set(&format!("{thread},{i}"), &i),
+                            Err(Error::InvalidKey(_))
+                        ));
+                        if i % 2 == 0 {
+                            store.unset(&format!("{thread}-{i}")).unwrap();
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(8 * 25, store.keys().count());
+        assert_eq!(Some(49), store.get("7-49").unwrap());
+    }
+
+    #[test]
+    fn panicked_group() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::<u32>::options()
+            .sync(SyncPolicy::EveryWrite)
+            .on_set(|key, _| assert_ne!("panic", key))
+            .open(&dir.path().join("db"))
+            .unwrap();
+        let barrier = Barrier::new(8);
+        let panics = AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            for thread in 0..8 {
+                let (store, barrier, panics) = (store.clone(), &barrier, &panics);
+                scope.spawn(move || {
+                    barrier.wait();
+                    for i in 0..50 {
+                        let key = if thread == 0 && i == 25 {
+                            "panic".to_string()
+                        } else {
+                            format!("{thread}-{i}")
+                        };
+                        // The hook panics in the thread leading the group of the write, once
+                        // every record of the group is written, so the other writes succeed.
+                        let set = || store.set(&key, &i);
+                        match std::panic::catch_unwind(std::panic::AssertUnwindSafe(set)) {
+                            Ok(result) => result.unwrap(),
+                            Err(_) => {
+                                panics.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                    }
+                    store.set(&thread.to_string(), &0).unwrap();
+                });
+            }
+        });
+        assert_eq!(1, panics.load(Ordering::Relaxed));
+        assert_eq!(Some(25), store.get("panic").unwrap());
+        for thread in 0..8 {
+            assert_eq!(Some(0), store.get(&thread.to_string()).unwrap());
+            for i in (0..50).filter(|&i| thread != 0 || i != 25) {
+                assert_eq!(Some(i), store.get(&format!("{thread}-{i}")).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn failed_group() {
+        let group = || {
+            (0..3)
+                .map(|ticket| (ticket, vec![OwnedRecord::from(Record::new("a", b"1"))]))
+                .collect()
+        };
+        let writes = Cell::new(0);
+
+        // Refused records are written again one call at a time.
+        let (results, _) = write_group(group(), &|records: &[Record]| {
+            writes.set(writes.get() + 1);
+            match records.len() {
+                3 => Err(WriteError::Refused(Error::Full)),
+                _ => Ok(()),
+            }
+        });
+        assert_eq!(4, writes.replace(0));
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+
+        // Records which may have been written aren't, and every call gets the same error.
+        let (results, _) = write_group(group(), &|_: &[Record]| {
+            writes.set(writes.get() + 1);
+            let err = io::Error::new(io::ErrorKind::StorageFull, "sync failed");
+            Err(WriteError::Failed(Error::Io(err)))
+        });
+        assert_eq!(1, writes.get());
+        assert_eq!(
+            vec![0, 1, 2],
+            results.iter().map(|(t, _)| *t).collect::<Vec<_>>()
+        );
+        assert!(results.iter().all(|(_, result)| matches!(
+            result,
+            Err(Error::Io(err)) if err.kind() == io::ErrorKind::StorageFull
+                && err.to_string() == "sync failed"
+        )));
+    }
+}
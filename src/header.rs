@@ -0,0 +1,229 @@
+//! Header at the start of every file of a database.
+//!
+//! New databases start with a line of tab-separated `name=value` fields, in the same scheme as the
+//! metadata of records, which comes before the magic of the binary format if any:
+//!
+//! ```text
+//! %kv<TAB>version=1<TAB>codec=json<TAB>flags=zstd,encrypted<TAB>type=User<TAB>crc=4f2a1c3b
+//! ```
+//!
+//! `version` is that of the layout of the file, which is refused if newer than this version
+//! supports. `codec` names the [`Codec`](crate::Codec) the values are encoded with, and `flags`
+//! lists the features needed to read them, any of which this version doesn't know of being
+//! refused too. `type` is only present for databases created with a type tag, and `generation`
+//! for databases which were compacted without being segmented, counting the compactions so that
+//! positions in the file taken before the last one can be told apart. Unknown fields are ignored,
+//! so that new ones can be added without bumping the version.
+//!
+//! The line can't be mistaken for a record since `%k` isn't a valid escape in a key. Files created
+//! before headers were written are still read, with nothing to check them against.
+//!
+//! In the [`Format::Ndjson`] format, the line is written as a JSON string instead, so that every
+//! line of the file is valid JSON, which is also how the format is recognized.
+
+use std::fmt::Write as _;
+use std::io::{BufRead, Read};
+
+use crate::record::{decode_key, encode_key, verify_checksum};
+use crate::{line_error, Error, Format};
+
+const LINE_MAGIC: &[u8] = b"%kv\t";
+/// Start of the header line of databases in the NDJSON format, i.e. [`LINE_MAGIC`] in JSON.
+const JSON_LINE_MAGIC: &[u8] = b"\"%kv\\t";
+/// Latest version of the layout of the files, which is the one written.
+const VERSION: u32 = 1;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Header {
+    pub format: Format,
+    /// Name of the codec of the values, if it has one.
+    pub codec: Option<String>,
+    /// Whether values may be compressed with zstd.
+    pub compressed: bool,
+    /// Whether values may be encrypted.
+    pub encrypted: bool,
+    /// Name given to the type of the values by the store which created the database.
+    pub type_tag: Option<String>,
+    /// Number of times the file was rewritten by a compaction.
+    pub generation: u64,
+}
+
+impl Header {
+    /// Reads the header from the start of a file, returning it along with its raw bytes, or `None`
+    /// if the file is empty or its header line isn't fully written yet.
+    pub fn read<R: BufRead>(mut reader: R) -> Result<Option<(Self, Vec<u8>)>, Error> {
+        let mut bytes = Vec::new();
+        let mut header = Header::default();
+        let start = reader.fill_buf()?;
+        let json = start.starts_with(JSON_LINE_MAGIC);
+        if json || start.starts_with(LINE_MAGIC) {
+            reader.read_until(b'\n', &mut bytes)?;
+            let Some(line) = bytes.strip_suffix(b"\n") else {
+                return Ok(None);
+            };
+            let corrupt = || Error::Corrupt {
+                offset: 0,
+                line: None,
+            };
+            let line = std::str::from_utf8(line).map_err(|_| corrupt())?;
+            if json {
+                let line: String = serde_json::from_str(line).map_err(|_| corrupt())?;
+                header.parse_line(&line)?;
+                header.format = Format::Ndjson;
+                return Ok(Some((header, bytes)));
+            }
+            header.parse_line(line)?;
+        }
+
+        let line_len = bytes.len();
+        reader
+            .take(Format::Binary.header().len() as u64)
+            .read_to_end(&mut bytes)?;
+        header.format = match Format::detect(&bytes[line_len..])? {
+            Some(format) => format,
+            None if line_len == 0 => return Ok(None),
+            None => Format::Text,
+        };
+        bytes.truncate(line_len + header.format.header().len());
+        Ok(Some((header, bytes)))
+    }
+
+    /// Returns the bytes with which the files of a database with this header start.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut line = String::from_utf8_lossy(LINE_MAGIC).into_owned();
+        let _ = write!(line, "version={VERSION}");
+        if let Some(codec) = &self.codec {
+            let _ = write!(line, "\tcodec={}", encode_key(codec));
+        }
+        let flags: Vec<&str> = [(self.compressed, "zstd"), (self.encrypted, "encrypted")]
+            .into_iter()
+            .filter_map(|(set, flag)| set.then_some(flag))
+            .collect();
+        let _ = write!(line, "\tflags={}", flags.join(","));
+        if let Some(type_tag) = &self.type_tag {
+            let _ = write!(line, "\ttype={}", encode_key(type_tag));
+        }
+        if self.generation > 0 {
+            let _ = write!(line, "\tgeneration={}", self.generation);
+        }
+        let crc = crc32fast::hash(line.as_bytes());
+        let _ = write!(line, "\tcrc={crc:08x}");
+        if self.format == Format::Ndjson {
+            line = serde_json::Value::String(line).to_string();
+        }
+        line.push('\n');
+
+        let mut buf = line.into_bytes();
+        buf.extend_from_slice(self.format.header());
+        buf
+    }
+
+    /// Parses the header line, without its trailing newline, refusing the versions and flags this
+    /// version doesn't support.
+    fn parse_line(&mut self, line: &str) -> Result<(), Error> {
+        let body = verify_checksum(line, 0)?;
+        // Skips the magic.
+        for field in body.split('\t').skip(1) {
+            let (name, value) = field.split_once('=').ok_or_else(|| line_error(0, line))?;
+            let decode = || {
+                decode_key(value)
+                    .map(|value| value.into_owned())
+                    .ok_or_else(|| line_error(0, line))
+            };
+            match name {
+                "version" => {
+                    let version: u32 = value.parse().map_err(|_| line_error(0, line))?;
+                    if version > VERSION {
+                        return Err(Error::Read(format!(
+                            "Unsupported database format version: {version}"
+                        )));
+                    }
+                }
+                "codec" => self.codec = Some(decode()?),
+                "type" => self.type_tag = Some(decode()?),
+                "generation" => {
+                    self.generation = value.parse().map_err(|_| line_error(0, line))?;
+                }
+                "flags" => {
+                    for flag in value.split(',').filter(|flag| !flag.is_empty()) {
+                        match flag {
+                            "zstd" if cfg!(feature = "zstd") => self.compressed = true,
+                            "zstd" => {
+                                return Err(Error::Read(
+                                    "Database is compressed with zstd, which requires the `zstd` \
+                                     feature"
+                                        .to_string(),
+                                ))
+                            }
+                            "encrypted" => self.encrypted = true,
+                            flag => {
+                                return Err(Error::Read(format!(
+                                    "Database requires an unsupported feature: {flag}"
+                                )))
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header() {
+        assert_eq!(None, Header::read(&b""[..]).unwrap());
+        let (header, bytes) = Header::read(&b"key,1\n"[..]).unwrap().unwrap();
+        assert_eq!(Header::default(), header);
+        assert!(bytes.is_empty());
+        let (header, bytes) = Header::read(Format::Binary.header()).unwrap().unwrap();
+        assert_eq!(Format::Binary, header.format);
+        assert_eq!(Format::Binary.header(), bytes);
+
+        for format in [Format::Text, Format::Binary, Format::Ndjson] {
+            let header = Header {
+                format,
+                codec: Some("json".to_string()),
+                compressed: cfg!(feature = "zstd"),
+                encrypted: true,
+                type_tag: Some("Vec<(u8, String)>\t".to_string()),
+                generation: 3,
+            };
+            let mut file = header.encode();
+            let len = file.len();
+            file.extend_from_slice(b"more");
+            assert_eq!(
+                Some((header, file[..len].to_vec())),
+                Header::read(&file[..]).unwrap()
+            );
+        }
+
+        let with_fields = |fields: &str| {
+            let line = format!("%kv\t{fields}");
+            let crc = crc32fast::hash(line.as_bytes());
+            format!("{line}\tcrc={crc:08x}\n")
+        };
+        let header = with_fields("version=1\tflags=\tcodec=msgpack\tnew=field");
+        let (header, _) = Header::read(header.as_bytes()).unwrap().unwrap();
+        assert_eq!(Some("msgpack"), header.codec.as_deref());
+        for unsupported in ["version=2", "flags=encrypted,quantum"] {
+            let header = with_fields(unsupported);
+            assert!(matches!(
+                Header::read(header.as_bytes()),
+                Err(Error::Read(_))
+            ));
+        }
+
+        // Not fully written yet.
+        assert_eq!(None, Header::read(&b"%kv\tversion=1"[..]).unwrap());
+        assert!(matches!(
+            Header::read(&b"%kv\tversion=1\tcrc=00000000\n"[..]),
+            Err(Error::Corrupt { offset: 0, .. })
+        ));
+    }
+}
@@ -0,0 +1,217 @@
+//! Persisted copy of the index, which spares a full scan of the log when opening a database.
+//!
+//! A database at `db` gets its index written to `db.idx` when the store is dropped and after every
+//! compaction. The file starts with a line recording the length and modification time, in
+//! nanoseconds, of every segment at the time, followed by one line per live key, and ends with a
+//! CRC32 of everything before it:
+//!
+//! ```text
+//! %kv-hint<TAB>version=1<TAB>segments=0:1234:1690000000000000000<TAB>records=12<TAB>tombstones=3<TAB>clock=1690000000000
+//! some key,0,56<TAB>exp=1700000000000
+//! crc=4f2a1c3b
+//! ```
+//!
+//! The hint is only used if the segments are still exactly as they were, since anything written to
+//! the log afterwards, e.g. by a store which crashed before it could write a hint of its own, isn't
+//! in it. Otherwise, the index is rebuilt from the log as usual. Note that records damaged while
+//! the segments are left alone, or by something setting their modification time back, are only
+//! detected once read rather than when opening the database.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::record::{decode_key, encode_key};
+use crate::segment::Segment;
+use crate::{sync_parent_dir, Location, Slot, StoreInner};
+
+const MAGIC: &str = "%kv-hint";
+const VERSION: u32 = 1;
+
+/// Path of the hint of the database.
+pub(crate) fn hint_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".idx");
+    path.with_file_name(file_name)
+}
+
+impl StoreInner {
+    /// Writes the hint of the database, replacing the previous one at once. Does nothing for
+    /// stores which can't write to the database, whose database has no path, or which failed to
+    /// open before their index was built.
+    ///
+    /// Pending records must have been flushed beforehand, for the hint to be used.
+    pub(crate) fn write_hint(&self) -> io::Result<()> {
+        let Some(path) = self
+            .path
+            .as_deref()
+            .filter(|_| self.indexed && !self.read_only)
+        else {
+            return Ok(());
+        };
+        let mut hint = format!(
+            "{MAGIC}\tversion={VERSION}\tsegments={}\trecords={}\ttombstones={}\tclock={}\n",
+            describe(&self.segments)?,
+            self.records,
+            self.tombstones,
+            self.clock,
+        );
+        for (key, slot) in &self.index {
+            let Location { segment, offset } = slot.location;
+            let _ = write!(hint, "{},{segment},{offset}", encode_key(key));
+            if let Some(expires_at) = slot.expires_at {
+                let _ = write!(hint, "\texp={expires_at}");
+            }
+            hint.push('\n');
+        }
+        let crc = crc32fast::hash(hint.as_bytes());
+        let _ = writeln!(hint, "crc={crc:08x}");
+
+        let hint_path = hint_path(path);
+        let mut tmp_path = hint_path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        std::fs::write(&tmp_path, hint)?;
+        File::open(&tmp_path)?.sync_all()?;
+        std::fs::rename(&tmp_path, &hint_path)?;
+        sync_parent_dir(&hint_path)
+    }
+
+    /// Loads the index from the hint of the database, returning whether there was one which
+    /// matches the log.
+    pub(crate) fn load_hint(&mut self) -> io::Result<bool> {
+        let Some(path) = &self.path else {
+            return Ok(false);
+        };
+        let hint = match std::fs::read_to_string(hint_path(path)) {
+            Ok(hint) => hint,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        match parse(&hint, &describe(&self.segments)?) {
+            Some((index, [records, tombstones, clock])) => {
+                self.index = index;
+                self.records = records;
+                self.tombstones = tombstones;
+                self.clock = clock;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+/// Describes the segments as they currently are on disk, by their id, length and modification time.
+fn describe(segments: &[Segment]) -> io::Result<String> {
+    let mut described = Vec::new();
+    for segment in segments {
+        let metadata = std::fs::metadata(&segment.path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_err(io::Error::other)?;
+        described.push(format!(
+            "{}:{}:{}",
+            segment.id,
+            metadata.len(),
+            modified.as_nanos()
+        ));
+    }
+    Ok(described.join(","))
+}
+
+/// Parses the hint, returning the index along with the number of records, of tombstones and the
+/// clock, unless it's damaged or doesn't describe the given segments.
+fn parse(hint: &str, segments: &str) -> Option<(BTreeMap<String, Slot>, [u64; 3])> {
+    let (body, crc) = hint.strip_suffix('\n')?.rsplit_once("crc=")?;
+    if u32::from_str_radix(crc, 16).ok()? != crc32fast::hash(body.as_bytes()) {
+        return None;
+    }
+
+    let mut lines = body.lines();
+    let mut fields = lines.next()?.split('\t');
+    if fields.next()? != MAGIC || fields.next()? != format!("version={VERSION}") {
+        return None;
+    }
+    if fields.next()?.strip_prefix("segments=")? != segments {
+        return None;
+    }
+    let mut counters = [0; 3];
+    for (counter, name) in counters
+        .iter_mut()
+        .zip(["records=", "tombstones=", "clock="])
+    {
+        *counter = fields.next()?.strip_prefix(name)?.parse().ok()?;
+    }
+
+    let mut index = BTreeMap::new();
+    for line in lines {
+        let (entry, expiry) = match line.split_once('\t') {
+            Some((entry, expiry)) => (entry, Some(expiry.strip_prefix("exp=")?.parse().ok()?)),
+            None => (line, None),
+        };
+        let mut parts = entry.rsplitn(3, ',');
+        let offset = parts.next()?.parse().ok()?;
+        let segment = parts.next()?.parse().ok()?;
+        let key = decode_key(parts.next()?)?;
+        let slot = Slot {
+            location: Location { segment, offset },
+            expires_at: expiry,
+        };
+        index.insert(key.into_owned(), slot);
+    }
+    Some((index, counters))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::Store;
+
+    #[test]
+    fn hint() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let store = Store::<u32>::open(&path).unwrap();
+        store.set("a,b", &1).unwrap();
+        store.set("gone", &2).unwrap();
+        store.unset("gone").unwrap();
+        store
+            .set_with_ttl("c", &3, Duration::from_secs(60))
+            .unwrap();
+        drop(store);
+
+        let hint = std::fs::read_to_string(hint_path(&path)).unwrap();
+        assert!(hint.starts_with("%kv-hint\tversion=1\tsegments=0:"));
+        assert!(hint.contains("\trecords=4\ttombstones=1\t"));
+        assert!(hint.contains("\na%2Cb,0,"));
+        assert!(!hint.contains("gone"));
+        assert!(hint
+            .lines()
+            .any(|line| line.starts_with("c,0,") && line.contains("\texp=")));
+        let store = Store::<u32>::open(&path).unwrap();
+        assert_eq!(vec!["a,b", "c"], store.keys().collect::<Vec<_>>());
+        assert_eq!(Some(3), store.get("c").unwrap());
+        drop(store);
+
+        // Records written after the hint make it stale.
+        let mut log = File::options().append(true).open(&path).unwrap();
+        log.write_all(b"d,4\n").unwrap();
+        drop(log);
+        let store = Store::<u32>::open(&path).unwrap();
+        assert_eq!(Some(4), store.get("d").unwrap());
+        drop(store);
+
+        // So does damage to the hint itself.
+        let hint = std::fs::read_to_string(hint_path(&path)).unwrap();
+        std::fs::write(hint_path(&path), hint.replacen(",0,", ",1,", 1)).unwrap();
+        let store = Store::<u32>::open(&path).unwrap();
+        assert_eq!(Some(1), store.get("a,b").unwrap());
+        assert_eq!(3, store.keys().count());
+    }
+}
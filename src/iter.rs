@@ -0,0 +1,51 @@
+use std::vec;
+
+use serde::Deserialize;
+
+use crate::{Codec, Error, JsonCodec, Store};
+
+/// Streaming iterator over the live entries of a [`Store`].
+///
+/// Created by [`Store::iter`]. The set of keys is captured when the iterator is created, but
+/// values are only read as the iterator advances, so memory usage stays bounded by the number of
+/// keys rather than the size of the values. Keys that get unset in the meantime are skipped, and
+/// keys that get overwritten yield their newest value.
+pub struct Iter<T, C = JsonCodec> {
+    store: Store<T, C>,
+    keys: vec::IntoIter<String>,
+}
+
+impl<T, C> Iter<T, C> {
+    pub(crate) fn new(store: Store<T, C>, keys: Vec<String>) -> Self {
+        Self {
+            store,
+            keys: keys.into_iter(),
+        }
+    }
+}
+
+impl<T, C> Iterator for Iter<T, C>
+where
+    T: for<'a> Deserialize<'a>,
+    C: Codec,
+{
+    type Item = Result<(String, T), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for key in self.keys.by_ref() {
+            let value = match self.store.0.read().read_value(&key) {
+                Ok(Some(value)) => value,
+                Ok(None) => continue,
+                Err(err) => return Some(Err(err)),
+            };
+
+            return Some(C::decode(&value).map(|value| (key, value)));
+        }
+
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.keys.size_hint().1)
+    }
+}
@@ -0,0 +1,143 @@
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use rustc_hash::FxHashMap;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::{decode_err, encode_err, Codec, Error, JsonCodec, Store};
+
+/// A view over a [`Store`] whose keys are of any serializable type `K` rather than strings.
+///
+/// Created by [`Store::with_keys`]. Keys are stored in the database as their compact JSON
+/// representation, so the `(42, 7)` key is written as `[42,7]`, and the string `"a"` as `"a"`
+/// quotes included. Entries are listed in the lexicographic order of those representations, which
+/// for numbers isn't their numeric order.
+pub struct KeyedStore<K, T, C = JsonCodec> {
+    store: Store<T, C>,
+    _key: PhantomData<fn() -> K>,
+}
+
+impl<K, T, C> Clone for KeyedStore<K, T, C> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            _key: PhantomData,
+        }
+    }
+}
+
+impl<K: Serialize, T, C> KeyedStore<K, T, C> {
+    pub(crate) fn new(store: Store<T, C>) -> Self {
+        Self {
+            store,
+            _key: PhantomData,
+        }
+    }
+
+    /// Returns the store with the keys as they're written in the database.
+    pub fn store(&self) -> &Store<T, C> {
+        &self.store
+    }
+
+    /// Returns the key as it's written in the database.
+    pub fn encode_key(key: &K) -> Result<String, Error> {
+        serde_json::to_string(key).map_err(encode_err)
+    }
+
+    /// See [`Store::unset`].
+    pub fn unset(&self, key: &K) -> Result<(), Error> {
+        self.store.unset(&Self::encode_key(key)?)
+    }
+
+    /// See [`Store::contains`].
+    pub fn contains(&self, key: &K) -> Result<bool, Error> {
+        self.store.contains(&Self::encode_key(key)?)
+    }
+}
+
+impl<K: DeserializeOwned, T, C> KeyedStore<K, T, C> {
+    /// Returns the keys which currently hold a value, failing for those which can't be decoded as
+    /// a `K`.
+    pub fn keys(&self) -> impl Iterator<Item = Result<K, Error>> {
+        self.store.keys().map(|key| decode_key(&key))
+    }
+}
+
+impl<K: Serialize, T: Serialize, C: Codec> KeyedStore<K, T, C> {
+    /// See [`Store::set`].
+    pub fn set(&self, key: &K, value: &T) -> Result<(), Error> {
+        self.store.set(&Self::encode_key(key)?, value)
+    }
+
+    /// See [`Store::set_with_ttl`].
+    pub fn set_with_ttl(&self, key: &K, value: &T, ttl: Duration) -> Result<(), Error> {
+        self.store.set_with_ttl(&Self::encode_key(key)?, value, ttl)
+    }
+}
+
+impl<K, T, C> KeyedStore<K, T, C>
+where
+    K: Serialize + DeserializeOwned,
+    T: for<'a> Deserialize<'a>,
+    C: Codec,
+{
+    /// See [`Store::get`].
+    pub fn get(&self, key: &K) -> Result<Option<T>, Error> {
+        self.store.get(&Self::encode_key(key)?)
+    }
+
+    /// Returns an iterator over the live entries, in the lexicographic order of the encoded keys.
+    pub fn iter(&self) -> impl Iterator<Item = Result<(K, T), Error>> {
+        self.store
+            .scan_prefix("")
+            .map(|entry| entry.and_then(|(key, value)| Ok((decode_key(&key)?, value))))
+    }
+
+    /// Loads the live entries in memory.
+    pub fn load_map(&self) -> Result<FxHashMap<K, T>, Error>
+    where
+        K: Eq + Hash,
+    {
+        self.iter().collect()
+    }
+}
+
+fn decode_key<K: DeserializeOwned>(key: &str) -> Result<K, Error> {
+    serde_json::from_str(key).map_err(decode_err)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    #[test]
+    fn keyed() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<String>::open(f.path())
+            .unwrap()
+            .with_keys::<(u64, u32)>();
+        store.set(&(42, 7), &"a".to_string()).unwrap();
+        store.set(&(3, 1), &"b".to_string()).unwrap();
+        store.set(&(5, 0), &"c".to_string()).unwrap();
+        store.unset(&(5, 0)).unwrap();
+
+        assert_eq!(Some("a".to_string()), store.get(&(42, 7)).unwrap());
+        assert_eq!(None, store.get(&(5, 0)).unwrap());
+        assert!(store.contains(&(3, 1)).unwrap());
+        assert_eq!(Some("b".to_string()), store.store().get("[3,1]").unwrap());
+        assert_eq!(
+            vec![(3, 1), (42, 7)],
+            store.keys().collect::<Result<Vec<_>, _>>().unwrap()
+        );
+        let map = store.load_map().unwrap();
+        assert_eq!(Some(&"a".to_string()), map.get(&(42, 7)));
+
+        // Keys written through the store itself may not decode.
+        store.store().set("plain", &"d".to_string()).unwrap();
+        assert!(store.iter().any(|entry| entry.is_err()));
+    }
+}
@@ -1,68 +1,594 @@
-use std::fs::File;
+use std::collections::BTreeMap;
+use std::fs::{File, TryLockError};
 use std::io;
-use std::io::{BufRead, Seek, Write};
+use std::io::Write;
 use std::marker::PhantomData;
-use std::path::Path;
-use std::sync::Arc;
+use std::num::NonZeroUsize;
+use std::ops::{Bound, RangeBounds};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Weak};
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::time::Instant;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use parking_lot::Mutex;
+// The clocks of `std` panic in browsers, where they're read from JavaScript instead.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+use web_time::Instant;
+
+use lru::LruCache;
+use parking_lot::{Mutex, RwLock, RwLockReadGuard};
 use rustc_hash::FxHashMap;
 use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
 use thiserror::Error;
 
-#[derive(Error, Debug, PartialEq, Eq)]
+#[cfg(feature = "tokio")]
+mod async_store;
+mod backend;
+mod batch;
+mod blob;
+mod bounded;
+mod bucket;
+mod changes;
+mod codec;
+mod crypto;
+mod export;
+mod glob;
+mod group;
+mod header;
+mod hint;
+mod iter;
+mod keyed;
+mod limit;
+mod merge;
+mod metadata;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod migration;
+mod options;
+#[cfg(feature = "rayon")]
+mod parallel;
+#[cfg(feature = "python")]
+mod python;
+mod query;
+mod raw;
+mod record;
+mod replication;
+mod segment;
+mod snapshot;
+mod stats;
+mod sweep;
+mod tail;
+mod watch;
+#[cfg(feature = "web")]
+mod web;
+
+#[cfg(feature = "tokio")]
+pub use async_store::AsyncStore;
+pub use backend::Backend;
+pub use batch::{Transaction, WriteBatch};
+pub use bucket::Bucket;
+pub use changes::{Change, Changes};
+#[cfg(feature = "msgpack")]
+pub use codec::MsgPackCodec;
+pub use codec::{Codec, JsonCodec};
+pub use export::ImportMode;
+pub use iter::Iter;
+pub use keyed::KeyedStore;
+pub use merge::Prefer;
+pub use metadata::RecordMeta;
+#[cfg(feature = "metrics")]
+pub use metrics::Metrics;
+pub use options::{
+    CacheConfig, CompactionPolicy, Compression, Format, FullPolicy, KeyPolicy, OpenOptions,
+    RecoveryPolicy, SyncPolicy,
+};
+pub use raw::RawStore;
+pub use replication::Replicator;
+pub use snapshot::Snapshot;
+pub use stats::Stats;
+pub use tail::{Position, Tail};
+pub use watch::Event;
+#[cfg(feature = "web")]
+pub use web::LocalStorage;
+
+use bounded::Recency;
+use crypto::Cipher;
+use group::{GroupCommit, WriteError};
+use header::Header;
+use migration::Migrations;
+use record::{OwnedRecord, Record, TOMBSTONE};
+use segment::{list_segments, lock_path, read_owner, segment_path, write_owner, Segment};
+use watch::{Hooks, Watcher};
+
+/// Error of a [`Codec`] or of another library, kept as the source of an [`Error`].
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Error, Debug)]
 pub enum Error {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+
+    /// A value couldn't be serialized by the codec.
+    #[error("Unable to encode value: {0}")]
+    Encode(#[source] BoxError),
+
+    /// A value couldn't be deserialized by the codec, or an imported one wasn't valid JSON.
+    #[error("Unable to decode value: {0}")]
+    Decode(#[source] BoxError),
+
+    #[cfg(feature = "sqlite")]
+    #[error("SQLite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+
+    /// The data read was invalid, for a reason which no other variant covers.
     #[error("Unable to read record: {0}")]
     Read(String),
 
+    /// The write was refused, for a reason which no other variant covers.
     #[error("Unable to write record: {0}")]
     Write(String),
 
-    #[error("Key `{0}` contains invalid characters")]
+    /// A record failed its checksum or couldn't be parsed. For the text format, `line` holds the
+    /// record as read, if it was valid UTF-8.
+    #[error("Corrupt record at offset {offset}{}", line.as_ref().map(|line| format!(": `{line}`")).unwrap_or_default())]
+    Corrupt { offset: u64, line: Option<String> },
+
+    /// The database is already open for writing, by the process with the given id if it's known.
+    #[error("Database is already opened for writing by another handle{}", owner.map(|pid| format!(" (process {pid})")).unwrap_or_default())]
+    Locked { owner: Option<u32> },
+
+    #[error("Unable to decrypt record at offset {offset}: the key is missing or wrong")]
+    Decrypt { offset: u64 },
+
+    #[error("Database is open read-only")]
+    ReadOnly,
+
+    /// The key isn't allowed by the [`KeyPolicy`] of the store.
+    #[error("Invalid key: `{0}`")]
     InvalidKey(String),
+
+    /// The encoded value is longer than the maximum set with [`OpenOptions::max_value_size`].
+    #[error("Value of `{key}` is {size} bytes long, more than the maximum of {max}")]
+    ValueTooLarge {
+        key: String,
+        size: usize,
+        max: usize,
+    },
+
+    /// The write would take the database past its maximum size. See
+    /// [`OpenOptions::max_file_size`].
+    #[error("Database has reached its maximum size")]
+    Full,
+
+    /// The database was created by a store opened with another type tag. See
+    /// [`OpenOptions::type_tag`].
+    #[error("Database holds values of type `{found}`, not `{expected}`")]
+    TypeMismatch { expected: String, found: String },
+
+    /// The database was created by a store with another [`Codec`].
+    #[error("Database is encoded with the `{found}` codec, not `{expected}`")]
+    CodecMismatch { expected: String, found: String },
+
+    /// A query passed to [`Store::query`] couldn't be parsed.
+    #[error("Invalid query: {0}")]
+    Query(String),
+}
+
+fn encode_err<E: std::error::Error + Send + Sync + 'static>(err: E) -> Error {
+    Error::Encode(Box::new(err))
 }
 
-fn write_err<E: std::error::Error>(err: E) -> Error {
-    Error::Write(err.to_string())
+fn decode_err<E: std::error::Error + Send + Sync + 'static>(err: E) -> Error {
+    Error::Decode(Box::new(err))
 }
 
-fn read_err<E: std::error::Error>(err: E) -> Error {
-    Error::Read(err.to_string())
+/// Checks that the bytes of a value are valid JSON, as they're only trusted to be by its codec.
+fn raw_json(value: Vec<u8>) -> Result<Box<RawValue>, Error> {
+    let value = String::from_utf8(value).map_err(decode_err)?;
+    RawValue::from_string(value).map_err(decode_err)
 }
 
-fn line_error(line_number: usize, line: &str) -> Error {
-    Error::Read(format!("Invalid data as line {line_number}: `{line}`"))
+fn line_error(offset: u64, line: &str) -> Error {
+    Error::Corrupt {
+        offset,
+        line: Some(line.to_string()),
+    }
 }
 
-pub struct Store<T>(Arc<Mutex<StoreInner<T>>>);
+/// A key-value store holding values of type `T`, serialized with the [`Codec`] `C`.
+///
+/// Reads only take a shared lock and use positional reads, so any number of them can happen
+/// concurrently across clones of the store. Writes are exclusive, but those of single keys made
+/// concurrently are grouped into a single write, and a single sync.
+pub struct Store<T, C = JsonCodec>(
+    Arc<RwLock<StoreInner>>,
+    Arc<GroupCommit>,
+    PhantomData<fn() -> (T, C)>,
+);
 
-impl<T> Clone for Store<T> {
+impl<T, C> Clone for Store<T, C> {
     fn clone(&self) -> Self {
-        Self(self.0.clone())
+        Self(self.0.clone(), self.1.clone(), PhantomData)
     }
 }
 
-struct StoreInner<T> {
-    file: File,
-    _phantom: PhantomData<T>,
+struct StoreInner {
+    /// `None` for a database held by a custom [`Backend`].
+    path: Option<PathBuf>,
+    /// Files making up the log, oldest first. Records are only ever appended to the last one.
+    segments: Vec<Segment>,
+    /// Size past which writes go to a new segment.
+    segment_size: Option<u64>,
+    /// Handle on the lock file of a segmented database, which holds the lock while the store is
+    /// open. A database made of a single file is locked through that file instead.
+    lock: Option<File>,
+    /// Location of the latest record of every live key.
+    index: BTreeMap<String, Slot>,
+    /// Number of records in the log, live or not.
+    records: u64,
+    /// Number of those records which unset a key.
+    tombstones: u64,
+    /// Latest timestamp given to a record, below which new ones never go, so that they stay
+    /// ordered even if the system clock goes back.
+    clock: u64,
+    auto_compact: Option<AutoCompact>,
+    /// Keeps the thread sweeping expired keys running, if there is one.
+    sweeper: Option<mpsc::Sender<()>>,
+    /// Size past which the database can't grow, and how writes make room under it.
+    max_size: Option<(u64, FullPolicy)>,
+    /// Set in bounded mode, to evict the least recently used keys.
+    recency: Option<Recency>,
+    key_policy: KeyPolicy,
+    max_value_size: Option<usize>,
+    /// Length from which values are stored in blobs, if they are.
+    blob_threshold: Option<usize>,
+    #[cfg(feature = "metrics")]
+    metrics: Arc<metrics::Counters>,
+    watchers: Vec<Watcher>,
+    hooks: Hooks,
+    migrations: Migrations,
+    /// Capacity of the buffer of the active segment.
+    write_buffer: usize,
+    /// Recently read values, behind its own lock since it gets updated by reads.
+    cache: Option<Mutex<LruCache<String, Vec<u8>>>>,
+    /// Shared with every open [`Snapshot`], which rely on the records they point to staying where
+    /// they are.
+    snapshots: Arc<()>,
+    sync: SyncPolicy,
+    last_sync: Instant,
+    recovery: RecoveryPolicy,
+    format: Format,
+    /// Bytes with which every file of the database starts. See [`Header`].
+    header: Vec<u8>,
+    /// Name of the codec of the store, checked against that of the database.
+    codec: Option<&'static str>,
+    /// Type tag the store was opened with, likewise checked.
+    type_tag: Option<String>,
+    compression: Compression,
+    cipher: Option<Cipher>,
+    /// Set if the store follows a database written by another process.
+    follower: Option<Follower>,
+    /// Set for followers and for stores opened with [`Store::open_read_only`], whose files are
+    /// opened without write access.
+    read_only: bool,
+    /// Set once the index holds the whole database, after which a hint of it can be written.
+    indexed: bool,
 }
 
-impl<T> Store<T> {
-    /// Opens the database at the given path.
-    pub fn open(path: &Path) -> io::Result<Self> {
-        let file = File::options()
-            .read(true)
-            .write(true)
-            .create(true)
-            .append(true)
-            .open(path)?;
+/// State of a store opened with [`Store::open_follower`].
+struct Follower {
+    /// Identifies the file of a database which isn't segmented, which its owner replaces with a
+    /// new one when compacting it. Only available on Unix, where replacements are otherwise only
+    /// detected by the file getting shorter.
+    file_id: Option<(u64, u64)>,
+}
+
+/// Settings of the background compaction, along with the channel through which it's triggered.
+struct AutoCompact {
+    policy: CompactionPolicy,
+    /// Missing on targets without threads, such as browsers, where writes compact the database
+    /// themselves instead.
+    trigger: Option<mpsc::SyncSender<()>>,
+}
+
+/// Corrupt records which were tolerated during a scan because of the [`RecoveryPolicy`].
+#[derive(Default)]
+struct Damage {
+    /// Content of the skipped records.
+    skipped: Vec<Vec<u8>>,
+    /// Offset of the record at the end of the last segment whose write was torn, if any.
+    torn_tail: Option<u64>,
+}
+
+/// Latest record of every live key, as read by [`StoreInner::live_records`].
+type LiveRecords = BTreeMap<String, OwnedRecord>;
+
+/// Where a record is located in the log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Location {
+    segment: u32,
+    offset: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Slot {
+    location: Location,
+    expires_at: Option<u64>,
+}
+
+impl Slot {
+    fn is_live(&self, now: u64) -> bool {
+        self.expires_at.is_none_or(|expires_at| now < expires_at)
+    }
+}
+
+impl<T, C: Codec> Store<T, C> {
+    /// Opens the database at the given path with the default options.
+    ///
+    /// The whole file is scanned once to build an in-memory index of where the latest value of
+    /// every key is located, which lets lookups seek directly to the right record.
+    ///
+    /// An exclusive advisory lock is taken on the file for as long as the store is open, so that
+    /// two processes can't interleave their writes. Opening a database which is already open
+    /// elsewhere fails with [`Error::Locked`], naming the owning process as recorded in the
+    /// `.lock` file next to the database; share clones of the [`Store`] instead. The lock is
+    /// released by the operating system if the process crashes, so the next one can take over.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        Self::options().open(path)
+    }
+
+    /// Returns the options with which to open a database, for when the defaults of
+    /// [`Store::open`] don't fit.
+    pub fn options() -> OpenOptions<T, C> {
+        OpenOptions::new()
+    }
+
+    fn open_with(path: &Path, options: &OpenOptions<T, C>) -> Result<Self, Error> {
+        if options.read_only {
+            return Self::open_read_only_with(path, options);
+        }
+        let has_segments = !list_segments(path)?.is_empty();
+        if !options.create && !has_segments && !path.exists() {
+            let message = format!("Database `{}` doesn't exist", path.display());
+            return Err(io::Error::new(io::ErrorKind::NotFound, message).into());
+        }
+        let segmented = options.segment_size.is_some() || has_segments;
+        let (lock, segments) = if segmented {
+            let lock = open_file(&lock_path(path))?;
+            lock_file(&lock, path)?;
+            // Listed again now that no other handle can be compacting the database.
+            let mut ids = list_segments(path)?;
+            if ids.is_empty() {
+                ids.push(1);
+            }
+            let segments = ids
+                .into_iter()
+                .map(|id| Segment::open(id, segment_path(path, id)))
+                .collect::<io::Result<Vec<_>>>()?;
+            (Some(lock), segments)
+        } else {
+            let file = open_file(path)?;
+            lock_file(&file, path)?;
+            let segment = Segment::new(0, path.to_path_buf(), Box::new(file))?;
+            (None, vec![segment])
+        };
+        // Only informative, and may not be writable while locked on some platforms.
+        let _ = write_owner(path);
+        Self::with_segments(
+            Some(path),
+            lock,
+            segments,
+            options.segment_size,
+            None,
+            options,
+        )
+    }
+
+    /// Opens the database at the given path without write access, with the default options.
+    ///
+    /// Its files are opened read-only, so the store can't modify them in any way: writes fail with
+    /// [`Error::ReadOnly`], and damage tolerated by the [`RecoveryPolicy`] is skipped rather than
+    /// repaired. A shared lock is taken instead of an exclusive one, so any number of read-only
+    /// stores can be open at once, but none while the database is open for writing, which is what
+    /// [`Store::open_follower`] is for. The database must already exist.
+    pub fn open_read_only(path: &Path) -> Result<Self, Error> {
+        Self::options().read_only(true).open(path)
+    }
+
+    fn open_read_only_with(path: &Path, options: &OpenOptions<T, C>) -> Result<Self, Error> {
+        let ids = list_segments(path)?;
+        let (lock, segments) = if ids.is_empty() {
+            let file = File::open(path)?;
+            lock_file_shared(&file, path)?;
+            let segment = Segment::new(0, path.to_path_buf(), Box::new(file))?;
+            (None, vec![segment])
+        } else {
+            let lock = File::open(lock_path(path))?;
+            lock_file_shared(&lock, path)?;
+            let segments = ids
+                .into_iter()
+                .map(|id| {
+                    let path = segment_path(path, id);
+                    Segment::new(id, path.clone(), Box::new(File::open(path)?))
+                })
+                .collect::<io::Result<Vec<_>>>()?;
+            (Some(lock), segments)
+        };
+        Self::with_segments(Some(path), lock, segments, None, None, options)
+    }
+
+    /// Opens the database at the given path as a read-only follower of the process writing to it,
+    /// with the default options.
+    ///
+    /// No lock is taken, so any number of followers can read a database while another process
+    /// owns it. Every read first checks whether the database has grown, and indexes the records
+    /// appended since, so reads see the writes of the owner once they reach the file. A database
+    /// compacted by its owner is indexed again from scratch, unless a [`Snapshot`] is open.
+    ///
+    /// Writes fail with [`Error::ReadOnly`]. The database must already exist.
+    pub fn open_follower(path: &Path) -> Result<Self, Error> {
+        Self::options().open_follower(path)
+    }
+
+    fn open_follower_with(path: &Path, options: &OpenOptions<T, C>) -> Result<Self, Error> {
+        let (segments, follower) = follower_segments(path)?;
+        Self::with_segments(Some(path), None, segments, None, Some(follower), options)
+    }
+
+    /// Creates an empty database which is only kept in memory, with the default options.
+    ///
+    /// It behaves exactly like one opened from a file, but is lost once the last clone of the store
+    /// is dropped, unless it gets saved with [`Store::compact_into`]. This makes it a cheap
+    /// stand-in for a real database in tests.
+    pub fn in_memory() -> Self {
+        Self::options().open_in_memory()
+    }
+
+    fn open_backend_with(
+        backend: Box<dyn Backend>,
+        options: &OpenOptions<T, C>,
+    ) -> Result<Self, Error> {
+        let segment = Segment::new(0, PathBuf::new(), backend)?;
+        // Segments are files of their own, so they don't apply.
+        Self::with_segments(None, None, vec![segment], None, None, options)
+    }
 
-        let inner = StoreInner {
-            file,
-            _phantom: PhantomData::default(),
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "open", skip_all, fields(path = ?path), err)
+    )]
+    fn with_segments(
+        path: Option<&Path>,
+        lock: Option<File>,
+        segments: Vec<Segment>,
+        segment_size: Option<u64>,
+        follower: Option<Follower>,
+        options: &OpenOptions<T, C>,
+    ) -> Result<Self, Error> {
+        let header = Header {
+            format: options.format,
+            codec: C::NAME.map(String::from),
+            compressed: options.compression != Compression::None,
+            encrypted: options.cipher().is_some(),
+            type_tag: options.type_tag.clone(),
+            generation: 0,
         };
+        let mut inner = StoreInner {
+            path: path.map(Path::to_path_buf),
+            segments,
+            segment_size,
+            lock,
+            index: BTreeMap::new(),
+            records: 0,
+            tombstones: 0,
+            clock: 0,
+            auto_compact: None,
+            sweeper: None,
+            max_size: options.max_file_size,
+            recency: None,
+            key_policy: options.key_policy,
+            max_value_size: options.max_value_size,
+            blob_threshold: options.blob_threshold,
+            #[cfg(feature = "metrics")]
+            metrics: Arc::default(),
+            watchers: Vec::new(),
+            hooks: options.hooks.clone(),
+            migrations: Migrations::default(),
+            write_buffer: options.write_buffer,
+            cache: NonZeroUsize::new(options.cache.max_entries)
+                .map(|max_entries| Mutex::new(LruCache::new(max_entries))),
+            snapshots: Arc::new(()),
+            sync: options.sync,
+            last_sync: Instant::now(),
+            recovery: options.recovery,
+            format: header.format,
+            header: header.encode(),
+            codec: C::NAME,
+            type_tag: header.type_tag,
+            compression: options.compression,
+            cipher: options.cipher(),
+            read_only: options.read_only || follower.is_some(),
+            follower,
+            indexed: false,
+        };
+        if inner.follower.is_some() {
+            inner.catch_up()?;
+        } else {
+            inner.read_header()?;
+            if !inner.read_only {
+                let header = inner.header.clone();
+                for segment in &mut inner.segments {
+                    segment.init(&header)?;
+                }
+            }
+            // The hint only spares the scan, so it's ignored if it can't be read.
+            let hinted = inner.load_hint().unwrap_or(false);
+            #[cfg(feature = "tracing")]
+            tracing::debug!(hinted, "loaded hint");
+            if hinted {
+                // The scan would have refused a wrong key, which would otherwise go unnoticed
+                // until something is read.
+                let first = inner.index.values().next().map(|slot| slot.location);
+                if let Some(location) = first.filter(|_| inner.cipher.is_some()) {
+                    inner.read_record(location)?;
+                }
+            } else {
+                inner.rebuild_index()?;
+            }
+            inner.indexed = true;
+        }
+        if let Some(max_live_keys) = options.max_live_keys.filter(|_| !inner.read_only) {
+            inner.recency = Some(Recency::new(max_live_keys, &inner.index));
+            inner.evict_lru()?;
+        }
+        #[cfg(feature = "tracing")]
+        tracing::info!(
+            size = inner.size(),
+            segments = inner.segments.len(),
+            records = inner.records,
+            live_keys = inner.index.len(),
+            "opened database"
+        );
+
+        // Read-only stores can't compact the database they read, nor sweep it.
+        let auto_compact = options.compaction.filter(|_| !inner.read_only);
+        let sweep_interval = options.sweep_interval.filter(|_| !inner.read_only);
+        let inner = Arc::new(RwLock::new(inner));
+        let threads = cfg!(not(all(target_arch = "wasm32", target_os = "unknown")));
+        if let Some(policy) = auto_compact {
+            let trigger = threads.then(|| spawn_compactor(Arc::downgrade(&inner)));
+            inner.write().auto_compact = Some(AutoCompact { policy, trigger });
+        }
+        if let Some(interval) = sweep_interval.filter(|_| threads) {
+            inner.write().sweeper = Some(sweep::spawn_sweeper(Arc::downgrade(&inner), interval));
+        }
+        Ok(Store(inner, Arc::default(), PhantomData))
+    }
+}
+
+impl<T, C> Store<T, C> {
+    /// Indexes the writes made by the owner of the database since the last read, if the store is
+    /// a follower. See [`Store::open_follower`].
+    ///
+    /// Reads do this on their own, but ignore its errors, and keep answering from what was
+    /// indexed until then.
+    pub fn refresh(&self) -> Result<(), Error> {
+        self.0.write().refresh()
+    }
 
-        Ok(Store(Arc::new(Mutex::new(inner))))
+    /// Takes the lock for reading, after catching up with the writes of the owner of the database
+    /// if the store is a follower.
+    fn read(&self) -> RwLockReadGuard<'_, StoreInner> {
+        let inner = self.0.read();
+        if !inner.is_stale().unwrap_or(true) {
+            return inner;
+        }
+        drop(inner);
+        // Reported by `Store::refresh` instead.
+        let _ = self.0.write().refresh();
+        self.0.read()
     }
 
     /// Sets the given key to `None`.
@@ -70,154 +596,3577 @@ impl<T> Store<T> {
     /// This appends `key,null` to the database, which in effect removes it from the database.
     /// Previous entries are not deleted.
     pub fn unset(&self, key: &str) -> Result<(), Error> {
-        let key = validate_key(key)?;
-        // The type for the Option doesn't matter since we write None. This lets us call `unset` in
-        // generic contexts without having to specify `Serialize`.
-        let value = serde_json::to_string(&Option::<u8>::None).map_err(write_err)?;
-        writeln!(self.0.lock().file, "{key},{value}").map_err(write_err)
+        self.commit(Record::new(key, TOMBSTONE))
+    }
+
+    /// Appends the record, grouped with those written concurrently through clones of the store.
+    fn commit(&self, record: Record) -> Result<(), Error> {
+        let records = vec![OwnedRecord::from(record)];
+        self.1
+            .commit(records, |records| self.0.write().try_append(records))
     }
 
-    /// Searches the database for an instance of the given key.
+    /// Unsets every key starting with the given prefix, and returns how many there were.
+    ///
+    /// The tombstones are appended with a single write while holding the lock, so keys set
+    /// concurrently through clones of the store are either all unset or left untouched.
+    pub fn unset_prefix(&self, prefix: &str) -> Result<usize, Error> {
+        let mut inner = self.0.write();
+        let keys: Vec<String> = inner
+            .live_range((Bound::Included(prefix), Bound::Unbounded))
+            .map(|(k, _)| k)
+            .take_while(|k| k.starts_with(prefix))
+            .cloned()
+            .collect();
+        if keys.is_empty() {
+            return Ok(0);
+        }
+        let records: Vec<Record> = keys.iter().map(|k| Record::new(k, TOMBSTONE)).collect();
+        inner.append(&records)?;
+        Ok(keys.len())
+    }
+
+    /// Moves the value of `old`, along with its expiry, to `new`, and unsets `old`.
+    ///
+    /// Fails with [`Error::Write`] if `old` holds no value, or if `new` holds one and `overwrite`
+    /// isn't set. Both records are appended with a single write while holding the lock, so the
+    /// value is never visible under both keys or neither, even after a crash.
+    pub fn rename(&self, old: &str, new: &str, overwrite: bool) -> Result<(), Error> {
+        let mut inner = self.0.write();
+        let Some(slot) = inner.slot(old) else {
+            return Err(Error::Write(format!("Key `{old}` holds no value")));
+        };
+        if old == new {
+            return Ok(());
+        }
+        if !overwrite && inner.slot(new).is_some() {
+            return Err(Error::Write(format!("Key `{new}` already holds a value")));
+        }
+        let record = inner.read_record(slot.location)?;
+        inner.append(&[
+            Record::new(new, &record.value).with_expiry(record.expires_at),
+            Record::new(old, TOMBSTONE),
+        ])
+    }
+
+    /// Checks whether the given key currently holds a value.
+    ///
+    /// This is answered from the index alone, without reading the database.
     pub fn contains(&self, key: &str) -> Result<bool, Error> {
-        let key = validate_key(key)?;
-        self.scan(move |k, v, contains: &mut bool| {
-            if k == key {
-                *contains = v != "null";
-            }
+        Ok(self.read().slot(key).is_some())
+    }
+
+    /// Returns the keys that currently hold a value, in lexicographic order.
+    ///
+    /// Values are never read, so this is much cheaper than going through [`Store::load_map`].
+    pub fn keys(&self) -> impl Iterator<Item = String> {
+        let keys: Vec<String> = self.read().live().map(|(k, _)| k.clone()).collect();
+        keys.into_iter()
+    }
+
+    /// Returns the keys that currently hold a value and match the given glob pattern, in
+    /// lexicographic order.
+    ///
+    /// In the pattern, `*` matches any sequence of characters and `?` matches any single character.
+    /// Only the keys starting with the part of the pattern before the first wildcard are looked at.
+    pub fn keys_matching(&self, pattern: &str) -> impl Iterator<Item = String> {
+        let prefix = glob::literal_prefix(pattern);
+        let keys: Vec<String> = self
+            .read()
+            .live_range((Bound::Included(prefix), Bound::Unbounded))
+            .map(|(k, _)| k)
+            .take_while(|k| k.starts_with(prefix))
+            .filter(|k| glob::matches(pattern, k))
+            .cloned()
+            .collect();
+        keys.into_iter()
+    }
+
+    /// Returns the number of keys that currently hold a value.
+    ///
+    /// This is answered from the in-memory index, without reading any value.
+    pub fn len(&self) -> usize {
+        self.read().live().count()
+    }
+
+    /// Checks whether no key currently holds a value.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns statistics about the database, to help decide when to compact it.
+    ///
+    /// This is answered from counters kept up to date by writes, without reading the database.
+    pub fn stats(&self) -> Stats {
+        let inner = self.read();
+        let records = inner.records;
+        Stats {
+            size: inner.size(),
+            records,
+            live_keys: inner.live().count(),
+            tombstones: inner.tombstones,
+            dead_ratio: inner.dead_ratio(),
+        }
+    }
+
+    /// Returns what is known about the current value of a key, such as when it was last written
+    /// and how many times, or `None` if the key holds no value.
+    ///
+    /// Counting the versions of the key scans the whole database. Timestamps come from the system
+    /// clock, but never go backwards within a database.
+    pub fn metadata(&self, key: &str) -> Result<Option<RecordMeta>, Error> {
+        let inner = self.read();
+        let Some(slot) = inner.slot(key) else {
+            return Ok(None);
+        };
+        let record = inner.read_record(slot.location)?;
+        let versions = inner.scan(|_, record, versions: &mut usize| {
+            *versions += usize::from(record.key == key);
             Ok(())
-        })
+        })?;
+        let time = |millis| UNIX_EPOCH + Duration::from_millis(millis);
+        Ok(Some(RecordMeta {
+            modified: record.written_at.map(time),
+            expires: record.expires_at.map(time),
+            size: record.value.len(),
+            versions,
+            segment: slot.location.segment,
+            offset: slot.location.offset,
+        }))
     }
 
-    /// Scans the database and calls the given function for every line.
-    fn scan<Output, F>(&self, f: F) -> Result<Output, Error>
-    where
-        Output: Default,
-        F: Fn(&str, &str, &mut Output) -> Result<(), Error>,
-    {
-        let mut inner = self.0.lock();
-        inner.file.rewind().map_err(read_err)?;
+    /// Returns the length of the value of every key that currently holds one, as encoded by the
+    /// codec of the store, in lexicographic order of the keys.
+    ///
+    /// This is the [`RecordMeta::size`] of every key, read from the records without decoding the
+    /// values or scanning the database for their versions.
+    pub fn value_sizes(&self) -> Result<Vec<(String, usize)>, Error> {
+        let inner = self.read();
+        inner
+            .live()
+            .map(|(key, slot)| {
+                let record = inner.read_record(slot.location)?;
+                Ok((key.clone(), record.value.len()))
+            })
+            .collect()
+    }
 
-        let mut output = Output::default();
+    /// Returns a handle on the bucket with the given name, holding values of type `U` under keys
+    /// of its own.
+    ///
+    /// Buckets let a single database hold several separate maps, possibly of different types. See
+    /// [`Bucket`] for how their keys are stored.
+    pub fn bucket<U>(&self, name: &str) -> Bucket<U, C> {
+        Bucket::new(Store(self.0.clone(), self.1.clone(), PhantomData), name)
+    }
 
-        let reader = io::BufReader::new(&inner.file);
-        for (line_number, line) in reader.lines().enumerate() {
-            let line = line.map_err(read_err)?;
+    /// Returns a view over the database using keys of type `K`, such as tuples or numbers, rather
+    /// than strings. See [`KeyedStore`] for how they're stored.
+    pub fn with_keys<K: Serialize>(&self) -> KeyedStore<K, T, C> {
+        KeyedStore::new(self.clone())
+    }
 
-            let (k, v) = split_key_value(&line, line_number)?;
-            f(k, v, &mut output)?;
+    /// Writes all the records of the batch to the database at once.
+    ///
+    /// The records are serialized into a single buffer which is appended with one write while
+    /// holding the lock, so no other write can be interleaved with them.
+    pub fn apply(&self, batch: WriteBatch<T, C>) -> Result<(), Error> {
+        if batch.is_empty() {
+            return Ok(());
         }
+        let records: Vec<Record> = batch.records.iter().map(|r| r.as_record()).collect();
+        self.0.write().append(&records)
+    }
 
+    /// Runs the given closure and atomically commits the writes it staged.
+    ///
+    /// The writes are buffered in the [`Transaction`] and appended with a single write once the
+    /// closure returns `Ok`. If it returns an error, the staged writes are discarded and the
+    /// database is left untouched. Reads made through other handles while the closure runs do not
+    /// see the staged writes.
+    pub fn transaction<R, F>(&self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut Transaction<T, C>) -> Result<R, Error>,
+    {
+        let mut txn = Transaction::new(self);
+        let output = f(&mut txn)?;
+        self.apply(txn.into_batch())?;
         Ok(output)
     }
-}
 
-impl<T: Serialize> Store<T> {
-    /// Sets the given key to the given value.
-    pub fn set(&self, key: &str, value: &T) -> Result<(), Error> {
-        let key = validate_key(key)?;
-        let value = serde_json::to_string(&Some(value)).map_err(write_err)?;
-        writeln!(self.0.lock().file, "{key},{value}").map_err(write_err)
+    /// Writes the records buffered in memory to the file, without syncing it.
+    ///
+    /// This is also done when the last clone of the store is dropped, but errors are then ignored.
+    pub fn flush(&self) -> Result<(), Error> {
+        self.0.write().flush()
+    }
+
+    /// Flushes all writes to the storage device, regardless of the [`SyncPolicy`].
+    pub fn sync(&self) -> Result<(), Error> {
+        self.0.write().sync()
+    }
+
+    /// Rewrites the database so that it only contains the latest value of every key.
+    ///
+    /// Overwritten entries, unset keys and expired keys are dropped. The live entries are first
+    /// written to a temporary file next to the database, which is then atomically renamed over it,
+    /// so a crash mid-compaction leaves the original file intact.
+    ///
+    /// A segmented database is instead rewritten into new segments following the existing ones,
+    /// which are deleted afterwards. Should the compaction be interrupted, the new segments only
+    /// repeat values which the old ones already hold, so nothing is lost either.
+    ///
+    /// A database held by a custom [`Backend`] is rewritten through [`Backend::replace`], so it's
+    /// only as safe as the backend makes it.
+    ///
+    /// Fails if a [`Snapshot`] of the store is open.
+    pub fn compact(&self) -> Result<(), Error> {
+        self.0.write().compact()
+    }
+
+    /// Writes a compacted copy of the database to a new file at the given path, leaving the
+    /// database itself untouched.
+    ///
+    /// The copy holds the latest value of every key, in the format and with the compression of
+    /// the store, and is never segmented. Fails if the file already exists.
+    pub fn compact_into(&self, path: &Path) -> Result<(), Error> {
+        self.read().compact_into(path)
     }
 }
 
-impl<T> Store<T>
-where
-    T: for<'a> Deserialize<'a>,
-{
-    /// Retrieves the value associated with a key.
-    pub fn get(&self, key: &str) -> Result<Option<T>, Error> {
-        let key = validate_key(key)?;
-        self.scan(move |k, v, value: &mut Option<T>| {
-            if k == key {
-                *value = serde_json::from_str(v).map_err(read_err)?;
+impl StoreInner {
+    /// Scans every segment to find the latest record of every key.
+    ///
+    /// This is also where damage tolerated by the [`RecoveryPolicy`] gets repaired: a torn record
+    /// at the end of the log is truncated away, and corrupt records are quarantined if requested.
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "scan", skip_all, err))]
+    fn rebuild_index(&mut self) -> Result<(), Error> {
+        let now = now_millis();
+        let (mut records, mut tombstones, mut clock) = (0, 0, 0);
+        let (index, damage) =
+            self.scan_checked(|location, record, index: &mut BTreeMap<_, _>| {
+                records += 1;
+                tombstones += u64::from(record.is_tombstone());
+                clock = clock.max(record.written_at.unwrap_or(0));
+                if record.is_tombstone() || record.is_expired(now) {
+                    index.remove(&*record.key);
+                } else {
+                    let slot = Slot {
+                        location,
+                        expires_at: record.expires_at,
+                    };
+                    index.insert(record.key.to_string(), slot);
+                }
+                Ok(())
+            })?;
+        self.index = index;
+        self.records = records;
+        self.tombstones = tombstones;
+        self.clock = clock;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            size = self.size(),
+            records,
+            tombstones,
+            live_keys = self.index.len(),
+            "scanned database"
+        );
+        #[cfg(feature = "tracing")]
+        if !damage.skipped.is_empty() || damage.torn_tail.is_some() {
+            tracing::warn!(
+                recovery = ?self.recovery,
+                skipped = damage.skipped.len(),
+                torn_tail = ?damage.torn_tail,
+                "recovered from corrupt records"
+            );
+        }
+
+        // A read-only store leaves the damage as it found it.
+        if self.read_only {
+            return Ok(());
+        }
+        // There is nowhere to quarantine the records of a custom backend to, so they are skipped.
+        if let Some(path) = self
+            .path
+            .as_ref()
+            .filter(|_| self.recovery == RecoveryPolicy::Quarantine && !damage.skipped.is_empty())
+        {
+            let mut quarantine = File::options()
+                .create(true)
+                .append(true)
+                .open(quarantine_path(path))?;
+            for line in &damage.skipped {
+                quarantine.write_all(line)?;
             }
-            Ok(())
-        })
+        }
+        if let Some(offset) = damage.torn_tail {
+            let active = self.active_mut();
+            active.storage.truncate(offset)?;
+            active.len = offset;
+        }
+
+        Ok(())
     }
 
-    /// Loads the entire database in memory in the form of a hash map.
-    pub fn load_map(&self) -> Result<FxHashMap<String, T>, Error> {
-        self.scan(|k, v, map: &mut FxHashMap<String, T>| {
-            let v: Option<T> = serde_json::from_str(v).map_err(read_err)?;
-            match v {
-                Some(v) => map.insert(k.to_string(), v),
-                None => map.remove(k),
-            };
-            Ok(())
-        })
+    /// Appends records to the log with a single write and updates the index to point to them.
+    ///
+    /// Records are stamped with the current time and with the current version of the migrations,
+    /// unless they already carry them, as those copied from another database do.
+    fn append(&mut self, records: &[Record]) -> Result<(), Error> {
+        self.try_append(records).map_err(WriteError::into_inner)
     }
-}
 
-fn split_key_value(line: &str, line_number: usize) -> Result<(&str, &str), Error> {
-    let mut split = line.splitn(2, ',');
-    let k = split.next().ok_or_else(|| line_error(line_number, line))?;
-    let v = split.next().ok_or_else(|| line_error(line_number, line))?;
+    /// Same as [`StoreInner::append`], but tells apart the records which were refused before
+    /// anything was written.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "set", level = "debug", skip_all, fields(records = records.len()), err)
+    )]
+    fn try_append(&mut self, records: &[Record]) -> Result<(), WriteError> {
+        let (buf, offsets) = self.encode_records(records).map_err(WriteError::Refused)?;
+        #[cfg(feature = "metrics")]
+        let metrics = Arc::clone(&self.metrics);
+        #[cfg(feature = "metrics")]
+        let _timer = metrics.write(records.len(), buf.len());
 
-    Ok((k, v))
-}
+        self.make_room(buf.len()).map_err(WriteError::Refused)?;
+        // The records are never split across segments, so that they are still written at once.
+        if self.is_full(self.active().len, buf.len()) {
+            self.rotate().map_err(WriteError::Refused)?;
+        }
+        self.write_encoded(records, &buf, offsets)
+    }
 
-fn validate_key(key: &str) -> Result<&str, Error> {
-    if key
-        .chars()
-        .all(|c| matches!(c, '0'..='9' | 'A'..='Z' | 'a'..='z' | ' ' | ':' | '/' | '.'))
-    {
-        Ok(key)
-    } else {
-        Err(Error::InvalidKey(key.to_string()))
+    /// Checks that the records can be written, and encodes them into a single buffer, returning it
+    /// along with the offset of every record in it.
+    fn encode_records(&mut self, records: &[Record]) -> Result<(Vec<u8>, Vec<u64>), Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        if let Some(record) = records
+            .iter()
+            .find(|record| !record.is_tombstone() && !self.key_policy.allows(&record.key))
+        {
+            return Err(Error::InvalidKey(record.key.to_string()));
+        }
+        if let Some(max) = self.max_value_size {
+            if let Some(record) = records
+                .iter()
+                .find(|record| !record.is_tombstone() && record.value.len() > max)
+            {
+                return Err(Error::ValueTooLarge {
+                    key: record.key.to_string(),
+                    size: record.value.len(),
+                    max,
+                });
+            }
+        }
+        let now = now_millis().max(self.clock);
+        let version = self.migrations.current();
+        let mut buf = Vec::new();
+        let mut offsets = Vec::with_capacity(records.len());
+        for record in records {
+            offsets.push(buf.len() as u64);
+            let written_at = record.written_at.unwrap_or(now);
+            self.clock = self.clock.max(written_at);
+            let record = record
+                .clone()
+                .with_timestamp(Some(written_at))
+                .with_version(record.version.or(version));
+            self.encode(&record, &mut buf)?;
+        }
+        Ok((buf, offsets))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::collections::HashMap;
+    /// Writes the encoded records to the active segment, then updates the index to point to them
+    /// and notifies the watchers.
+    fn write_encoded(
+        &mut self,
+        records: &[Record],
+        buf: &[u8],
+        offsets: Vec<u64>,
+    ) -> Result<(), WriteError> {
+        let write_buffer = self.write_buffer;
+        let active = self.active_mut();
+        let segment = active.id;
+        let start = active.len;
+        active
+            .append(buf, write_buffer)
+            .map_err(|err| WriteError::Failed(err.into()))?;
+        match self.sync {
+            SyncPolicy::Never => {}
+            SyncPolicy::EveryWrite => self.sync().map_err(WriteError::Failed)?,
+            SyncPolicy::Interval(interval) => {
+                if self.last_sync.elapsed() >= interval {
+                    self.sync().map_err(WriteError::Failed)?;
+                }
+            }
+        }
 
-    use rand::Rng;
-    use tempfile::NamedTempFile;
+        self.records += records.len() as u64;
+        self.tombstones += records.iter().filter(|r| r.is_tombstone()).count() as u64;
+        for (record, offset) in records.iter().zip(offsets) {
+            if let Some(cache) = &self.cache {
+                cache.lock().pop(&*record.key);
+            }
+            if let Some(recency) = &self.recency {
+                recency.record(record);
+            }
+            if record.is_tombstone() {
+                self.index.remove(&*record.key);
+            } else {
+                let slot = Slot {
+                    location: Location {
+                        segment,
+                        offset: start + offset,
+                    },
+                    expires_at: record.expires_at,
+                };
+                self.index.insert(record.key.to_string(), slot);
+            }
+        }
+        self.watchers
+            .retain_mut(|watcher| records.iter().all(watcher));
+        // The records are written by now, so a panicking hook is only passed on once the write is
+        // over, for writers grouped with the one it panicked for to know theirs succeeded.
+        let hooks = panic::catch_unwind(AssertUnwindSafe(|| self.hooks.written(records)));
 
-    #[test]
-    fn fuzz_test() {
-        let f = NamedTempFile::new().unwrap();
-        let store = Store::<u8>::open(f.path()).unwrap();
-        let mut map = HashMap::<String, u8>::new();
+        if let Some(auto_compact) = &self.auto_compact {
+            let CompactionPolicy {
+                max_dead_ratio,
+                min_file_size,
+            } = auto_compact.policy;
+            if self.dead_ratio() > max_dead_ratio && self.size() >= min_file_size {
+                match auto_compact.trigger.clone() {
+                    // The compaction is already pending if the channel is full.
+                    Some(trigger) => {
+                        let _ = trigger.try_send(());
+                    }
+                    // Errors are ignored just as in the background.
+                    None => {
+                        let _ = self.compact();
+                    }
+                }
+            }
+        }
 
-        let mut rng = rand::thread_rng();
-        for _ in 0..100_000 {
-            let key = format!("key{}", rng.gen::<u32>());
-            let value = rng.gen();
+        let evicted = self.evict_lru();
+        hooks.map_err(WriteError::Panicked)?;
+        evicted.map_err(WriteError::Failed)
+    }
 
-            store.set(&key, &value).unwrap();
-            map.insert(key, value);
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(self.active_mut().flush()?)
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        // A read-only store has nothing to sync, and no write access with which to do it.
+        if self.read_only {
+            return Ok(());
         }
+        self.flush()?;
+        self.active_mut().storage.sync()?;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
 
-        let store = store.load_map().unwrap();
-        for (key, value) in map {
-            assert_eq!(value, *store.get(&key).unwrap());
+    /// Path of a database stored in files, which segmented databases always are.
+    fn path(&self) -> &Path {
+        self.path
+            .as_deref()
+            .expect("only databases stored in files are compacted to files or segmented")
+    }
+
+    /// The segment to which records are appended.
+    fn active(&self) -> &Segment {
+        self.segments
+            .last()
+            .expect("there is always an active segment")
+    }
+
+    fn active_mut(&mut self) -> &mut Segment {
+        self.segments
+            .last_mut()
+            .expect("there is always an active segment")
+    }
+
+    fn segment(&self, id: u32) -> &Segment {
+        let i = self
+            .segments
+            .binary_search_by_key(&id, |segment| segment.id)
+            .expect("the index only points to existing segments");
+        &self.segments[i]
+    }
+
+    /// Checks whether writing `len` more bytes to a segment of the given length would take it past
+    /// the segment size. Segments holding no record are never full.
+    fn is_full(&self, segment_len: u64, len: usize) -> bool {
+        let empty = segment_len <= self.header.len() as u64;
+        self.segment_size
+            .is_some_and(|size| !empty && segment_len + len as u64 > size)
+    }
+
+    /// Creates a segment with the given id, ready to be written to.
+    fn create_segment(&self, id: u32) -> Result<Segment, Error> {
+        let path = segment_path(self.path(), id);
+        let mut segment = Segment::open(id, path)?;
+        segment.init(&self.header)?;
+        Ok(segment)
+    }
+
+    /// Seals the active segment and starts a new one, then deletes the segments which are no
+    /// longer needed.
+    fn rotate(&mut self) -> Result<(), Error> {
+        self.flush()?;
+        if self.sync != SyncPolicy::Never {
+            self.sync()?;
         }
+        let segment = self.create_segment(self.active().id + 1)?;
+        self.segments.push(segment);
+        self.remove_dead_segments()
     }
 
-    #[test]
-    fn validate_key_test() {
-        assert_eq!(Ok(""), validate_key(""));
-        assert_eq!(Ok("key"), validate_key("key"));
-        assert_eq!(Ok("key with spaces"), validate_key("key with spaces"));
-        assert!(validate_key("this,is,a,bad,key").is_err());
-        assert!(validate_key("this is\nalso bad").is_err());
+    /// Deletes the oldest segments for as long as none of their records hold the current value of
+    /// a key.
+    ///
+    /// Dead segments which come after a live one are kept, since their tombstones may be all that
+    /// hides the records of the older segments.
+    fn remove_dead_segments(&mut self) -> Result<(), Error> {
+        // Retried on the next rotation.
+        if self.has_snapshots() {
+            return Ok(());
+        }
+        while self.segments.len() > 1 {
+            let oldest = self.segments[0].id;
+            if self
+                .index
+                .values()
+                .any(|slot| slot.location.segment == oldest)
+            {
+                break;
+            }
+            let segment = self.segments.remove(0);
+            std::fs::remove_file(&segment.path)?;
+        }
+        Ok(())
     }
 
-    #[test]
-    fn separator_test() {
-        assert_eq!(Ok(("a", "b")), split_key_value("a,b", 0));
-        assert_eq!(Ok(("a", "b,c")), split_key_value("a,b,c", 0));
+    fn has_snapshots(&self) -> bool {
+        Arc::strong_count(&self.snapshots) > 1
     }
 
-    #[test]
-    fn unset() {
-        let f = NamedTempFile::new().unwrap();
-        let store = Store::<String>::open(f.path()).unwrap();
-        store.set("key", &"hello".to_string()).unwrap();
-        assert!(store.contains("key").unwrap());
-        store.unset("key").unwrap();
-        assert!(!store.contains("key").unwrap());
+    /// Number of records which don't hold the current value of a key, tombstones included.
+    fn dead_records(&self) -> u64 {
+        self.records.saturating_sub(self.index.len() as u64)
+    }
+
+    /// Fraction of the records which are dead.
+    fn dead_ratio(&self) -> f64 {
+        if self.records == 0 {
+            0.0
+        } else {
+            self.dead_records() as f64 / self.records as f64
+        }
+    }
+
+    /// Size of the database across all its segments, including the buffered writes.
+    fn size(&self) -> u64 {
+        self.segments.iter().map(|segment| segment.len).sum()
+    }
+
+    /// See [`Store::compact`].
+    fn compact(&mut self) -> Result<(), Error> {
+        self.compact_without(&[])
+    }
+
+    /// Compacts the database, leaving out the given keys, which are unset without writing
+    /// tombstones for them. They are only removed from the index if the compaction succeeds.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "compaction", skip_all, err)
+    )]
+    fn compact_without(&mut self, evicted: &[String]) -> Result<(), Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        if self.has_snapshots() {
+            return Err(Error::Write(
+                "Database can't be compacted while snapshots are open".to_string(),
+            ));
+        }
+        #[cfg(feature = "metrics")]
+        let metrics = Arc::clone(&self.metrics);
+        #[cfg(feature = "metrics")]
+        let _timer = metrics.compaction();
+        #[cfg(feature = "tracing")]
+        let (size, records) = (self.size(), self.records);
+        let mut live = self.live_records()?;
+        for key in evicted {
+            live.remove(key);
+        }
+
+        if self.lock.is_some() {
+            self.compact_segments(live)?;
+        } else {
+            // Unlike segments, which are replaced by new ones, the file keeps its id, so positions
+            // in it are only told apart by the generation in its header.
+            let header = self.next_generation()?;
+            let header = std::mem::replace(&mut self.header, header);
+            let compacted = if self.path.is_none() {
+                self.compact_backend(live)
+            } else {
+                self.compact_file(live)
+            };
+            if let Err(err) = compacted {
+                self.header = header;
+                return Err(err);
+            }
+        }
+        self.records = self.index.len() as u64;
+        self.tombstones = 0;
+        self.collect_blobs()?;
+        #[cfg(feature = "tracing")]
+        tracing::info!(
+            size_before = size,
+            size_after = self.size(),
+            records_before = records,
+            records_after = self.records,
+            "compacted database"
+        );
+        // A stale hint is ignored anyway.
+        let _ = self.write_hint();
+        self.hooks.compacted();
+        Ok(())
+    }
+
+    /// Reads the latest record of every live key.
+    fn live_records(&self) -> Result<LiveRecords, Error> {
+        self.scan(|location, record, live: &mut BTreeMap<String, _>| {
+            if self.is_latest(location, &record.key) {
+                live.insert(record.key.to_string(), OwnedRecord::from(record));
+            }
+            Ok(())
+        })
+    }
+
+    /// See [`Store::compact_into`].
+    fn compact_into(&self, path: &Path) -> Result<(), Error> {
+        let live = self.live_records()?;
+        let file = File::options().write(true).create_new(true).open(path)?;
+        let (file, _, _) = self.write_records(file, live, Some(path))?;
+        file.sync_all()?;
+        Ok(sync_parent_dir(path)?)
+    }
+
+    /// Writes a database holding only the given records to the writer, for the database at the
+    /// given path if any, and returns the index and length of the result.
+    fn write_records<W: Write>(
+        &self,
+        writer: W,
+        live: LiveRecords,
+        path: Option<&Path>,
+    ) -> Result<(W, BTreeMap<String, Slot>, u64), Error> {
+        let mut writer = io::BufWriter::new(writer);
+        let header = &self.header;
+        writer.write_all(header)?;
+        let mut index = BTreeMap::new();
+        let mut len = header.len() as u64;
+        let mut line = Vec::new();
+        for (key, record) in live {
+            line.clear();
+            self.encode_for(&record.as_record(), path, &mut line)?;
+            writer.write_all(&line)?;
+            let slot = Slot {
+                location: Location {
+                    segment: 0,
+                    offset: len,
+                },
+                expires_at: record.expires_at,
+            };
+            index.insert(key, slot);
+            len += line.len() as u64;
+        }
+        let writer = writer.into_inner().map_err(|err| err.into_error())?;
+        Ok((writer, index, len))
+    }
+
+    /// Replaces the bytes held by a custom backend with ones holding only the given records.
+    fn compact_backend(&mut self, live: LiveRecords) -> Result<(), Error> {
+        let (bytes, index, len) = self.write_records(Vec::new(), live, None)?;
+        let active = self.active_mut();
+        active.storage.replace(&bytes)?;
+        active.storage.sync()?;
+        active.len = len;
+        active.pending.clear();
+        self.index = index;
+        Ok(())
+    }
+
+    /// Replaces the database file with one holding only the given records.
+    fn compact_file(&mut self, live: LiveRecords) -> Result<(), Error> {
+        // The new file is locked before it replaces the old one so that the lock is never released,
+        // and the same handle is kept afterwards since reopening the file would conflict with it.
+        let path = self.path().to_path_buf();
+        let tmp_path = compaction_path(&path);
+        match std::fs::remove_file(&tmp_path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+        let tmp = open_file(&tmp_path)?;
+        lock_file(&tmp, &path)?;
+
+        let (tmp, index, len) = self.write_records(tmp, live, Some(&path))?;
+        tmp.sync_all()?;
+
+        std::fs::rename(&tmp_path, &path)?;
+        sync_parent_dir(&path)?;
+        let active = self.active_mut();
+        active.storage = Box::new(tmp);
+        active.len = len;
+        active.pending.clear();
+        self.index = index;
+
+        Ok(())
+    }
+
+    /// Writes the given records to new segments, then deletes the old ones.
+    fn compact_segments(&mut self, live: LiveRecords) -> Result<(), Error> {
+        let mut lines = live
+            .into_iter()
+            .map(|(key, record)| {
+                let mut line = Vec::new();
+                self.encode(&record.as_record(), &mut line)?;
+                Ok((key, record.expires_at, line))
+            })
+            .peekable();
+
+        let mut segments = Vec::new();
+        let mut index = BTreeMap::new();
+        let mut id = self.active().id;
+        loop {
+            id += 1;
+            let mut segment = self.create_segment(id)?;
+            let mut buf = Vec::new();
+            while let Some(next) = lines.next_if(|next: &Result<_, Error>| {
+                next.as_ref()
+                    .map_or(true, |(_, _, line)| !self.is_full(segment.len, line.len()))
+            }) {
+                let (key, expires_at, line) = next?;
+                buf.extend_from_slice(&line);
+                let slot = Slot {
+                    location: Location {
+                        segment: id,
+                        offset: segment.len,
+                    },
+                    expires_at,
+                };
+                index.insert(key, slot);
+                segment.len += line.len() as u64;
+            }
+            segment.storage.append(&buf)?;
+            segment.storage.sync()?;
+            segments.push(segment);
+
+            if lines.peek().is_none() {
+                break;
+            }
+        }
+        drop(lines);
+        sync_parent_dir(self.path())?;
+
+        // Oldest first, so that whatever remains after a crash is still replayed correctly.
+        for segment in std::mem::replace(&mut self.segments, segments) {
+            std::fs::remove_file(&segment.path)?;
+        }
+        sync_parent_dir(self.path())?;
+        self.index = index;
+
+        Ok(())
+    }
+
+    /// Reads the header of the database, if it holds one yet, and checks it against the settings
+    /// of the store.
+    ///
+    /// Type tags and codecs are only checked if both the database and the store have one.
+    fn read_header(&mut self) -> Result<(), Error> {
+        let Some((header, bytes)) = self.segments[0].read_header()? else {
+            return Ok(());
+        };
+        if let (Some(expected), Some(found)) = (&self.type_tag, &header.type_tag) {
+            if expected != found {
+                return Err(Error::TypeMismatch {
+                    expected: expected.clone(),
+                    found: found.clone(),
+                });
+            }
+        }
+        if let (Some(expected), Some(found)) = (self.codec, &header.codec) {
+            if expected != found {
+                return Err(Error::CodecMismatch {
+                    expected: expected.to_string(),
+                    found: found.clone(),
+                });
+            }
+        }
+        if header.encrypted && self.cipher.is_none() {
+            return Err(Error::Decrypt { offset: 0 });
+        }
+        self.format = header.format;
+        self.header = bytes;
+        Ok(())
+    }
+
+    /// Returns the number of times the database was compacted, as recorded in its header.
+    ///
+    /// Always `0` for segmented databases, and for files created before headers were written.
+    fn generation(&self) -> u64 {
+        Header::read(&self.header[..])
+            .ok()
+            .flatten()
+            .map_or(0, |(header, _)| header.generation)
+    }
+
+    /// Returns the header with its generation bumped, to be written by a compaction, unless the
+    /// database was created before headers were written.
+    fn next_generation(&self) -> Result<Vec<u8>, Error> {
+        let Some((mut header, bytes)) = Header::read(&self.header[..])? else {
+            return Ok(self.header.clone());
+        };
+        if bytes.len() == header.format.header().len() {
+            return Ok(self.header.clone());
+        }
+        header.generation += 1;
+        Ok(header.encode())
+    }
+
+    /// Checks whether the database followed by a follower changed since it was last indexed.
+    fn is_stale(&self) -> Result<bool, Error> {
+        let Some(follower) = &self.follower else {
+            return Ok(false);
+        };
+        let path = self.path();
+        let ids: Vec<u32> = self.segments.iter().map(|segment| segment.id).collect();
+        if ids != [0] {
+            return Ok(
+                list_segments(path)? != ids || self.active().storage.len()? != self.active().len
+            );
+        }
+        let metadata = std::fs::metadata(path)?;
+        Ok(file_id(&metadata) != follower.file_id || metadata.len() != self.active().len)
+    }
+
+    /// Indexes the writes made to the database since it was last indexed by a follower, starting
+    /// over if the database was compacted in the meantime.
+    fn refresh(&mut self) -> Result<(), Error> {
+        let Some(follower) = &self.follower else {
+            return Ok(());
+        };
+        let path = self.path().to_path_buf();
+        let ids: Vec<u32> = self.segments.iter().map(|segment| segment.id).collect();
+        let (replaced, added) = if ids == [0] {
+            let metadata = std::fs::metadata(&path)?;
+            let replaced =
+                file_id(&metadata) != follower.file_id || metadata.len() < self.active().len;
+            (replaced, Vec::new())
+        } else {
+            let current = list_segments(&path)?;
+            match current.strip_prefix(&ids[..]) {
+                Some(added) => (false, added.to_vec()),
+                None => (true, Vec::new()),
+            }
+        };
+
+        if replaced {
+            // The records the snapshots point to would be gone.
+            if self.has_snapshots() {
+                return Ok(());
+            }
+            let (segments, follower) = follower_segments(&path)?;
+            self.segments = segments;
+            self.follower = Some(follower);
+            self.index.clear();
+            self.records = 0;
+            self.tombstones = 0;
+            if let Some(cache) = &self.cache {
+                cache.lock().clear();
+            }
+        }
+        for id in added {
+            let segment = Segment::open_read_only(id, segment_path(&path, id))?;
+            self.segments.push(segment);
+        }
+        self.catch_up()
+    }
+
+    /// Indexes the records which a follower hasn't read yet, up to the last one whose write is
+    /// complete. The length of every segment is how far it was indexed.
+    fn catch_up(&mut self) -> Result<(), Error> {
+        // Only known once the database holds something.
+        if self.segments[0].len == 0 {
+            self.read_header()?;
+        }
+
+        let mut records = Vec::new();
+        let header_len = self.header.len() as u64;
+        for i in 0..self.segments.len() {
+            let len = self.segments[i].storage.len()?;
+            let mut offset = self.segments[i].len.max(header_len);
+            if len <= offset {
+                continue;
+            }
+            self.segments[i].len = len;
+            let segment = &self.segments[i];
+            let mut reader = io::BufReader::new(segment.reader_at(offset));
+            let mut raw = Vec::new();
+            loop {
+                raw.clear();
+                let read = self.format.read(&mut reader, &mut raw)?;
+                // The rest is still being written.
+                if read == 0 || self.format.is_torn(&raw) {
+                    break;
+                }
+                match self.decode(&raw, offset) {
+                    Ok(record) => {
+                        let location = Location {
+                            segment: segment.id,
+                            offset,
+                        };
+                        records.push((location, OwnedRecord::from(record)));
+                    }
+                    Err(err @ Error::Decrypt { .. }) => return Err(err),
+                    Err(_) if self.recovery.skips_corrupt() => {}
+                    Err(err) => return Err(err),
+                }
+                offset += read as u64;
+            }
+            self.segments[i].len = offset;
+        }
+
+        let now = now_millis();
+        for (location, record) in &records {
+            self.records += 1;
+            self.tombstones += u64::from(record.value == TOMBSTONE);
+            self.clock = self.clock.max(record.written_at.unwrap_or(0));
+            if let Some(cache) = &self.cache {
+                cache.lock().pop(&record.key);
+            }
+            let record = record.as_record();
+            if record.is_tombstone() || record.is_expired(now) {
+                self.index.remove(&*record.key);
+            } else {
+                let slot = Slot {
+                    location: *location,
+                    expires_at: record.expires_at,
+                };
+                self.index.insert(record.key.to_string(), slot);
+            }
+        }
+        self.watchers.retain_mut(|watcher| {
+            records
+                .iter()
+                .all(|(_, record)| watcher(&record.as_record()))
+        });
+        for (_, record) in &records {
+            self.hooks.written(&[record.as_record()]);
+        }
+        Ok(())
+    }
+
+    /// Returns the location of the latest record for the given key, unless it has expired.
+    fn slot(&self, key: &str) -> Option<Slot> {
+        let now = now_millis();
+        self.index
+            .get(key)
+            .filter(|slot| slot.is_live(now))
+            .copied()
+    }
+
+    /// Iterates over the keys which currently hold a value.
+    fn live(&self) -> impl Iterator<Item = (&String, &Slot)> {
+        let now = now_millis();
+        self.index.iter().filter(move |(_, slot)| slot.is_live(now))
+    }
+
+    /// Iterates, in order, over the keys within the given range which currently hold a value.
+    fn live_range<'a>(
+        &self,
+        range: (Bound<&'a str>, Bound<&'a str>),
+    ) -> impl Iterator<Item = (&String, &Slot)> {
+        let now = now_millis();
+        self.index
+            .range::<str, _>(range)
+            .filter(move |(_, slot)| slot.is_live(now))
+    }
+
+    /// Checks whether the record at the given location holds the current value of its key.
+    fn is_latest(&self, location: Location, key: &str) -> bool {
+        self.slot(key).is_some_and(|slot| slot.location == location)
+    }
+
+    /// Reads the raw value of the latest record for the given key.
+    fn read_value(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        #[cfg(feature = "metrics")]
+        let _timer = self.metrics.read();
+        let Some(slot) = self.slot(key) else {
+            #[cfg(feature = "metrics")]
+            self.metrics.miss();
+            return Ok(None);
+        };
+        if let Some(recency) = &self.recency {
+            recency.touch(key);
+        }
+        if let Some(value) = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.lock().get(key).cloned())
+        {
+            return Ok(Some(value));
+        }
+
+        let value = self.read_at(slot.location)?;
+        if let Some(cache) = &self.cache {
+            cache.lock().put(key.to_string(), value.clone());
+        }
+        Ok(Some(value))
+    }
+
+    /// Appends the encoded record to the buffer, in the format and with the settings of the store.
+    fn encode(&self, record: &Record, buf: &mut Vec<u8>) -> Result<(), Error> {
+        self.encode_for(record, self.path.as_deref(), buf)
+    }
+
+    /// Same as [`StoreInner::encode`], for a record written to the database at the given path,
+    /// which gets the blob holding its value if any.
+    fn encode_for(
+        &self,
+        record: &Record,
+        path: Option<&Path>,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), Error> {
+        let spilled = self.spill(record, path)?;
+        let record = spilled.as_ref().unwrap_or(record);
+        self.format
+            .encode(record, self.compression, self.cipher.as_ref(), buf)
+    }
+
+    /// Parses a record read from the log.
+    fn decode<'a>(&self, raw: &'a [u8], offset: u64) -> Result<Record<'a>, Error> {
+        self.format.decode(raw, offset, self.cipher.as_ref())
+    }
+
+    /// Reads the raw value of the record at the given location.
+    fn read_at(&self, location: Location) -> Result<Vec<u8>, Error> {
+        Ok(self.read_record(location)?.value)
+    }
+
+    /// Reads the record at the given location, migrating its value to the current version.
+    fn read_record(&self, location: Location) -> Result<OwnedRecord, Error> {
+        let Location { segment, offset } = location;
+        let mut reader = io::BufReader::new(self.segment(segment).reader_at(offset));
+        let mut raw = Vec::new();
+        self.format.read(&mut reader, &mut raw)?;
+        let record = self.load(self.decode(&raw, offset)?)?;
+        Ok(OwnedRecord::from(record))
+    }
+
+    /// Scans the database and calls the given function with every record and its location, with
+    /// values migrated to the current version.
+    fn scan<Output, F>(&self, f: F) -> Result<Output, Error>
+    where
+        Output: Default,
+        F: FnMut(Location, Record, &mut Output) -> Result<(), Error>,
+    {
+        Ok(self.scan_checked(f)?.0)
+    }
+
+    /// Same as [`StoreInner::scan`], but also reports the corrupt records which the
+    /// [`RecoveryPolicy`] allowed the scan to move past.
+    fn scan_checked<Output, F>(&self, mut f: F) -> Result<(Output, Damage), Error>
+    where
+        Output: Default,
+        F: FnMut(Location, Record, &mut Output) -> Result<(), Error>,
+    {
+        let mut output = Output::default();
+        let mut damage = Damage::default();
+
+        for (i, segment) in self.segments.iter().enumerate() {
+            let is_last = i + 1 == self.segments.len();
+            let mut offset = self.header.len() as u64;
+            let mut reader = io::BufReader::new(segment.reader_at(offset));
+            let mut raw = Vec::new();
+            loop {
+                raw.clear();
+                let read = self.format.read(&mut reader, &mut raw)?;
+                if read == 0 {
+                    break;
+                }
+
+                match self.decode(&raw, offset) {
+                    Ok(record) => {
+                        let location = Location {
+                            segment: segment.id,
+                            offset,
+                        };
+                        f(location, self.migrations.migrate(record)?, &mut output)?
+                    }
+                    // Skipping the records which can't be decrypted would lose them all.
+                    Err(err @ Error::Decrypt { .. }) => return Err(err),
+                    // Only the last record of the log can be cut short.
+                    Err(_)
+                        if is_last
+                            && self.format.is_torn(&raw)
+                            && self.recovery != RecoveryPolicy::Strict =>
+                    {
+                        damage.skipped.push(raw);
+                        damage.torn_tail = Some(offset);
+                        break;
+                    }
+                    Err(_) if self.recovery.skips_corrupt() => {
+                        damage.skipped.push(raw.clone());
+                    }
+                    Err(err) => return Err(err),
+                }
+                offset += read as u64;
+            }
+        }
+
+        Ok((output, damage))
+    }
+}
+
+impl Drop for StoreInner {
+    fn drop(&mut self) {
+        // Writes made since the last interval elapsed would otherwise never be synced.
+        if self.sync != SyncPolicy::Never {
+            let _ = self.sync();
+        } else {
+            let _ = self.flush();
+        }
+        let _ = self.write_hint();
+        // Removed while the lock is still held, so as not to remove the record of the next owner.
+        // Segmented databases keep their lock file.
+        if let Some(path) = self
+            .path
+            .as_deref()
+            .filter(|_| self.lock.is_none() && !self.read_only)
+        {
+            let _ = std::fs::remove_file(lock_path(path));
+        }
+    }
+}
+
+impl<T: Serialize, C: Codec> Store<T, C> {
+    /// Sets the given key to the given value.
+    pub fn set(&self, key: &str, value: &T) -> Result<(), Error> {
+        let value = C::encode(value)?;
+        self.commit(Record::new(key, &value))
+    }
+
+    /// Sets the given key to the given value for a limited amount of time.
+    ///
+    /// Once the time to live has elapsed, the key is treated as absent by every read and is dropped
+    /// on the next compaction.
+    pub fn set_with_ttl(&self, key: &str, value: &T, ttl: Duration) -> Result<(), Error> {
+        let value = C::encode(value)?;
+        let ttl = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+        let record = Record::new(key, &value).with_expiry(Some(now_millis().saturating_add(ttl)));
+        self.commit(record)
+    }
+
+    /// Sets the key to the given value only if it holds none, and returns whether it did.
+    ///
+    /// The check and the write happen while holding the lock, so exactly one of several concurrent
+    /// calls through clones of the store sets the key. Expired keys count as holding no value.
+    pub fn set_if_absent(&self, key: &str, value: &T) -> Result<bool, Error> {
+        let value = C::encode(value)?;
+        let mut inner = self.0.write();
+        if inner.slot(key).is_some() {
+            return Ok(false);
+        }
+        inner.append(&[Record::new(key, &value)])?;
+        Ok(true)
+    }
+}
+
+impl<T, C> Store<T, C>
+where
+    T: for<'a> Deserialize<'a>,
+    C: Codec,
+{
+    /// Retrieves the value associated with a key.
+    ///
+    /// Keys which hold no value are found to be missing from the index alone, so only hits read a
+    /// record from the database.
+    pub fn get(&self, key: &str) -> Result<Option<T>, Error> {
+        match self.read().read_value(key)? {
+            Some(v) => C::decode(&v).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Retrieves the values of several keys at once, leaving out those which are unset.
+    ///
+    /// Every key is looked up in the index, then the values are read in the order in which they
+    /// appear in the file, all while holding the lock, so they are consistent with each other.
+    pub fn get_many(&self, keys: &[&str]) -> Result<FxHashMap<String, T>, Error> {
+        let inner = self.read();
+        let mut slots: Vec<(Location, &str)> = keys
+            .iter()
+            .filter_map(|&key| Some((inner.slot(key)?.location, key)))
+            .collect();
+        slots.sort_unstable();
+        slots.dedup();
+
+        let mut values = FxHashMap::default();
+        for (_, key) in slots {
+            if let Some(value) = inner.read_value(key)? {
+                values.insert(key.to_string(), C::decode(&value)?);
+            }
+        }
+        Ok(values)
+    }
+
+    /// Returns an iterator over the live entries of the database.
+    ///
+    /// Unlike [`Store::load_map`], values are read one at a time as the iterator advances, in the
+    /// order in which they appear in the file.
+    pub fn iter(&self) -> Iter<T, C> {
+        let mut keys: Vec<(Location, String)> = {
+            let inner = self.read();
+            inner
+                .live()
+                .map(|(k, slot)| (slot.location, k.clone()))
+                .collect()
+        };
+        keys.sort_unstable();
+
+        Iter::new(self.clone(), keys.into_iter().map(|(_, k)| k).collect())
+    }
+
+    /// Returns an iterator over the live entries whose key starts with the given prefix, in
+    /// lexicographic key order.
+    pub fn scan_prefix(&self, prefix: &str) -> Iter<T, C> {
+        let keys = self
+            .read()
+            .live_range((Bound::Included(prefix), Bound::Unbounded))
+            .map(|(k, _)| k)
+            .take_while(|k| k.starts_with(prefix))
+            .cloned()
+            .collect();
+        Iter::new(self.clone(), keys)
+    }
+
+    /// Returns an iterator over the live entries whose key falls within the given range, in
+    /// lexicographic key order.
+    ///
+    /// # Panics
+    ///
+    /// Like [`BTreeMap::range`], panics if the start of the range is greater than its end, or if
+    /// both bounds are equal and excluded.
+    pub fn range<'a, R: RangeBounds<&'a str>>(&self, range: R) -> Iter<T, C> {
+        let bounds = (
+            range.start_bound().map(|s| *s),
+            range.end_bound().map(|s| *s),
+        );
+        let keys = self
+            .read()
+            .live_range(bounds)
+            .map(|(k, _)| k.clone())
+            .collect();
+        Iter::new(self.clone(), keys)
+    }
+
+    /// Captures the current state of the database, so that several keys can be read as they were
+    /// at a single point in time.
+    ///
+    /// Taking a snapshot copies the in-memory index. As long as any snapshot is open, old segments
+    /// aren't deleted and [`Store::compact`] fails, since both would move the records it refers to.
+    pub fn snapshot(&self) -> Snapshot<T, C> {
+        let inner = self.read();
+        let index = inner
+            .live()
+            .map(|(k, slot)| (k.clone(), slot.location))
+            .collect();
+        Snapshot::new(self.clone(), index, inner.snapshots.clone())
+    }
+
+    /// Returns a view of the database as it was at the given time, ignoring the records written
+    /// after it.
+    ///
+    /// This scans the whole database, and relies on the timestamps of the records, with those
+    /// written before timestamps were recorded being considered older than any time. Overwritten
+    /// records are only kept until the next compaction or until their segment gets deleted, so
+    /// the view is only accurate for times after the last of those.
+    pub fn as_of(&self, time: SystemTime) -> Result<Snapshot<T, C>, Error> {
+        let time = unix_millis(time);
+        let inner = self.read();
+        let index = inner.scan(|location, record, index: &mut BTreeMap<_, _>| {
+            if record
+                .written_at
+                .is_some_and(|written_at| written_at > time)
+            {
+                return Ok(());
+            }
+            if record.is_tombstone() || record.is_expired(time) {
+                index.remove(&*record.key);
+            } else {
+                index.insert(record.key.to_string(), location);
+            }
+            Ok(())
+        })?;
+        Ok(Snapshot::new(self.clone(), index, inner.snapshots.clone()))
+    }
+
+    /// Returns a channel receiving an [`Event`] for every write made to a key starting with the
+    /// given prefix, through any clone of the store, in the order in which they happen.
+    ///
+    /// Dropping the receiver unsubscribes from the events. Expirations only produce events once
+    /// swept, with [`Store::sweep_expired`] or [`OpenOptions::sweep_interval`], and compactions
+    /// produce none.
+    pub fn watch(&self, prefix: &str) -> mpsc::Receiver<Event<T>>
+    where
+        T: Send + 'static,
+        C: 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let prefix = prefix.to_string();
+        let watcher = move |record: &Record| {
+            if !record.key.starts_with(&prefix) {
+                return true;
+            }
+            match Event::from_record::<C>(record) {
+                Some(event) => sender.send(event).is_ok(),
+                None => true,
+            }
+        };
+        self.0.write().watchers.push(Box::new(watcher));
+        receiver
+    }
+
+    /// Returns every value recorded for the key, oldest first, with `None` for the times it was
+    /// unset.
+    ///
+    /// This scans the whole database. Overwritten records are only kept until the next compaction
+    /// or until their segment gets deleted, so older values may be missing.
+    pub fn history(&self, key: &str) -> Result<Vec<Option<T>>, Error> {
+        let inner = self.read();
+        inner.scan(|_, record, history: &mut Vec<_>| {
+            if record.key == key {
+                let value = if record.is_tombstone() {
+                    None
+                } else {
+                    Some(C::decode(&inner.load(record)?.value)?)
+                };
+                history.push(value);
+            }
+            Ok(())
+        })
+    }
+
+    /// Loads the entire database in memory in the form of a hash map.
+    pub fn load_map(&self) -> Result<FxHashMap<String, T>, Error> {
+        let mut map = FxHashMap::default();
+        self.for_each(|key, value| {
+            map.insert(key.to_string(), value);
+        })?;
+        Ok(map)
+    }
+
+    /// Loads the entire database in memory in the form of a map sorted by key.
+    pub fn load_sorted(&self) -> Result<BTreeMap<String, T>, Error> {
+        let mut map = BTreeMap::new();
+        self.for_each(|key, value| {
+            map.insert(key.to_string(), value);
+        })?;
+        Ok(map)
+    }
+
+    /// Calls `f` with every live entry of the database, in the order in which they appear in the
+    /// file, without holding more than one value in memory at a time.
+    ///
+    /// This reads the database in a single pass, which is much faster than [`Store::iter`] for
+    /// visiting most of it. Only the latest record of every key gets deserialized. The lock is held
+    /// throughout, so `f` must not write to the store.
+    pub fn for_each<F>(&self, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&str, T),
+    {
+        let inner = self.read();
+        inner.scan(|location, record, _: &mut ()| {
+            if inner.is_latest(location, &record.key) {
+                let record = inner.load(record)?;
+                f(&record.key, C::decode(&record.value)?);
+            }
+            Ok(())
+        })
+    }
+
+    /// Unsets every key whose value `f` returns `false` for, and returns how many there were.
+    ///
+    /// The database is read in a single pass like with [`Store::for_each`], then the tombstones are
+    /// appended with a single write, all while holding the lock, so writes made concurrently
+    /// through clones of the store happen either before or after. `f` is called with the lock held,
+    /// so it must not use the store itself.
+    pub fn retain<F>(&self, mut f: F) -> Result<usize, Error>
+    where
+        F: FnMut(&str, &T) -> bool,
+    {
+        let mut inner = self.0.write();
+        let removed = inner.scan(|location, record, removed: &mut Vec<String>| {
+            if inner.is_latest(location, &record.key) {
+                let record = inner.load(record)?;
+                if !f(&record.key, &C::decode(&record.value)?) {
+                    removed.push(record.key.into_owned());
+                }
+            }
+            Ok(())
+        })?;
+        if removed.is_empty() {
+            return Ok(0);
+        }
+        let records: Vec<Record> = removed.iter().map(|k| Record::new(k, TOMBSTONE)).collect();
+        inner.append(&records)?;
+        Ok(removed.len())
+    }
+}
+
+impl<T> Store<T, JsonCodec> {
+    /// Retrieves the value associated with a key as the JSON it's stored as, without deserializing
+    /// it, for callers which only pass it on.
+    pub fn get_raw(&self, key: &str) -> Result<Option<Box<RawValue>>, Error> {
+        self.read().read_value(key)?.map(raw_json).transpose()
+    }
+
+    /// Loads the entire database in memory in the form of a hash map of the JSON of the values,
+    /// without deserializing them. See [`Store::get_raw`].
+    pub fn load_map_raw(&self) -> Result<FxHashMap<String, Box<RawValue>>, Error> {
+        let inner = self.read();
+        inner.scan(|location, record, map: &mut FxHashMap<_, _>| {
+            if inner.is_latest(location, &record.key) {
+                let record = inner.load(record)?;
+                map.insert(
+                    record.key.into_owned(),
+                    raw_json(record.value.into_owned())?,
+                );
+            }
+            Ok(())
+        })
+    }
+}
+
+impl<T, C> Store<T, C>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+    C: Codec,
+{
+    /// Returns the value of the key, or sets it to the one returned by `default` and returns that
+    /// if the key is unset.
+    ///
+    /// The read and the write happen while holding the lock, so concurrent calls through clones of
+    /// the store all return the same value, which only the first of them computes. `default` is
+    /// called with the lock held, so it must not use the store itself.
+    pub fn get_or_insert_with<F>(&self, key: &str, default: F) -> Result<T, Error>
+    where
+        F: FnOnce() -> T,
+    {
+        let mut inner = self.0.write();
+        if let Some(value) = inner.read_value(key)? {
+            return C::decode(&value);
+        }
+
+        let value = default();
+        inner.append(&[Record::new(key, &C::encode(&value)?)])?;
+        Ok(value)
+    }
+
+    /// Replaces the value of the key with the one returned by `f` from the current one, and
+    /// returns it, with `None` standing for the key being unset on both sides.
+    ///
+    /// The read and the write happen while holding the lock, so concurrent updates through clones
+    /// of the store are never lost. Nothing is written if the key is unset and stays so. `f` is
+    /// called with the lock held, so it must not use the store itself.
+    pub fn update<F>(&self, key: &str, f: F) -> Result<Option<T>, Error>
+    where
+        F: FnOnce(Option<T>) -> Option<T>,
+    {
+        let mut inner = self.0.write();
+        let current = match inner.read_value(key)? {
+            Some(v) => Some(C::decode(&v)?),
+            None => None,
+        };
+        let existed = current.is_some();
+
+        let new = f(current);
+        let value = match &new {
+            Some(new) => C::encode(new)?,
+            None if !existed => return Ok(None),
+            None => TOMBSTONE.to_vec(),
+        };
+        inner.append(&[Record::new(key, &value)])?;
+        Ok(new)
+    }
+
+    /// Unsets the key and returns the value it held, if any.
+    ///
+    /// The read and the write happen while holding the lock, so the value returned is exactly the
+    /// one which got removed. Unlike [`Store::unset`], nothing is written if the key is unset.
+    pub fn remove(&self, key: &str) -> Result<Option<T>, Error> {
+        let mut inner = self.0.write();
+        let Some(value) = inner.read_value(key)? else {
+            return Ok(None);
+        };
+        let old = C::decode(&value)?;
+        inner.append(&[Record::new(key, TOMBSTONE)])?;
+        Ok(Some(old))
+    }
+
+    /// Sets the key to the given value and returns the one it replaced, if any.
+    ///
+    /// The read and the write happen while holding the lock, so exactly one of several concurrent
+    /// calls through clones of the store sees the key as unset.
+    pub fn replace(&self, key: &str, value: &T) -> Result<Option<T>, Error> {
+        let value = C::encode(value)?;
+        let mut inner = self.0.write();
+        let old = match inner.read_value(key)? {
+            Some(v) => Some(C::decode(&v)?),
+            None => None,
+        };
+        inner.append(&[Record::new(key, &value)])?;
+        Ok(old)
+    }
+}
+
+impl<T, C> Store<T, C>
+where
+    T: Serialize + for<'a> Deserialize<'a> + PartialEq,
+    C: Codec,
+{
+    /// Sets the key to `new` only if its current value is equal to `expected`.
+    ///
+    /// `None` stands for the key being unset, both for `expected` and `new`. The comparison and the
+    /// write happen while holding the lock, so concurrent swaps through clones of the store can't
+    /// interleave. On mismatch nothing is written and the actual current value is returned in the
+    /// inner `Err`.
+    pub fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<&T>,
+        new: Option<&T>,
+    ) -> Result<Result<(), Option<T>>, Error> {
+        let mut inner = self.0.write();
+
+        let current: Option<T> = match inner.read_value(key)? {
+            Some(v) => Some(C::decode(&v)?),
+            None => None,
+        };
+        if current.as_ref() != expected {
+            return Ok(Err(current));
+        }
+
+        let value = match new {
+            Some(new) => C::encode(new)?,
+            None => TOMBSTONE.to_vec(),
+        };
+        inner.append(&[Record::new(key, &value)])?;
+        Ok(Ok(()))
+    }
+}
+
+impl<T, C> Store<T, C>
+where
+    T: Serialize + for<'a> Deserialize<'a> + Default,
+    C: Codec,
+{
+    /// Appends an item to the collection held by the key, starting from an empty one if the key is
+    /// unset.
+    ///
+    /// The read and the write happen while holding the lock, so concurrent pushes through clones of
+    /// the store are never lost. The whole collection is written again, so the space used by its
+    /// previous versions is only reclaimed by [`Store::compact`].
+    pub fn push<I>(&self, key: &str, item: I) -> Result<(), Error>
+    where
+        T: Extend<I>,
+    {
+        let mut inner = self.0.write();
+
+        let mut collection: T = match inner.read_value(key)? {
+            Some(v) => C::decode(&v)?,
+            None => T::default(),
+        };
+        collection.extend([item]);
+
+        let value = C::encode(&collection)?;
+        inner.append(&[Record::new(key, &value)])
+    }
+}
+
+impl<T, C> Store<T, C>
+where
+    T: Serialize + TryFrom<i64>,
+    C: Codec,
+{
+    /// Adds `delta` to the integer held by the key and returns the result, treating a missing key
+    /// as `0`.
+    ///
+    /// The read and the write happen while holding the lock, so concurrent increments through
+    /// clones of the store are never lost. Fails if the current value isn't an integer, or if the
+    /// result doesn't fit in an `i64` or in `T`, in which case nothing is written.
+    pub fn increment(&self, key: &str, delta: i64) -> Result<i64, Error> {
+        let mut inner = self.0.write();
+
+        let current: i64 = match inner.read_value(key)? {
+            Some(v) => C::decode(&v)?,
+            None => 0,
+        };
+        let out_of_range = || Error::Write(format!("Value of `{key}` would be out of range"));
+        let new = current.checked_add(delta).ok_or_else(out_of_range)?;
+        let value = C::encode(&T::try_from(new).map_err(|_| out_of_range())?)?;
+
+        inner.append(&[Record::new(key, &value)])?;
+        Ok(new)
+    }
+}
+
+fn open_file(path: &Path) -> io::Result<File> {
+    File::options()
+        .read(true)
+        .create(true)
+        .append(true)
+        .open(path)
+}
+
+/// Opens the existing segments of a database without write access, for a follower.
+fn follower_segments(path: &Path) -> Result<(Vec<Segment>, Follower), Error> {
+    let ids = list_segments(path)?;
+    if ids.is_empty() {
+        // Taken first, so that a file replaced in between gets noticed on the next read.
+        let metadata = std::fs::metadata(path)?;
+        let segment = Segment::open_read_only(0, path.to_path_buf())?;
+        let follower = Follower {
+            file_id: file_id(&metadata),
+        };
+        return Ok((vec![segment], follower));
+    }
+    let segments = ids
+        .into_iter()
+        .map(|id| Segment::open_read_only(id, segment_path(path, id)))
+        .collect::<io::Result<Vec<_>>>()?;
+    Ok((segments, Follower { file_id: None }))
+}
+
+/// Identifies the file with the given metadata, so that it can be told apart from one which
+/// replaced it.
+#[cfg(unix)]
+fn file_id(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Spawns the thread running the compactions requested through the returned channel.
+///
+/// The thread exits once the store is dropped, which also drops the sending end of the channel.
+fn spawn_compactor(inner: Weak<RwLock<StoreInner>>) -> mpsc::SyncSender<()> {
+    let (trigger, triggered) = mpsc::sync_channel(1);
+    std::thread::spawn(move || {
+        while triggered.recv().is_ok() {
+            let Some(inner) = inner.upgrade() else {
+                break;
+            };
+            // There is no one to report errors to, and the next trigger will try again anyway.
+            let _ = inner.write().compact();
+        }
+    });
+    trigger
+}
+
+/// Takes the exclusive lock guarding against concurrent writers, without blocking.
+fn lock_file(file: &File, path: &Path) -> Result<(), Error> {
+    file.try_lock().map_err(|err| lock_err(err, path))
+}
+
+/// Takes a shared lock, which only guards against writers, without blocking.
+fn lock_file_shared(file: &File, path: &Path) -> Result<(), Error> {
+    file.try_lock_shared().map_err(|err| lock_err(err, path))
+}
+
+/// Converts the failure to lock a file of the database at the given path.
+fn lock_err(err: TryLockError, path: &Path) -> Error {
+    match err {
+        TryLockError::WouldBlock => Error::Locked {
+            owner: read_owner(path),
+        },
+        TryLockError::Error(err) => err.into(),
+    }
+}
+
+fn compaction_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".compact");
+    path.with_file_name(file_name)
+}
+
+/// Where corrupt records are moved to under [`RecoveryPolicy::Quarantine`].
+fn quarantine_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".corrupt");
+    path.with_file_name(file_name)
+}
+
+/// Makes a rename within the database's directory durable.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Current time as a Unix timestamp in milliseconds.
+fn now_millis() -> u64 {
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    let now = SystemTime::now();
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    let now = UNIX_EPOCH
+        + web_time::SystemTime::now()
+            .duration_since(web_time::UNIX_EPOCH)
+            .unwrap_or_default();
+    unix_millis(now)
+}
+
+/// The given time as a Unix timestamp in milliseconds.
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    use rand::Rng;
+    use tempfile::NamedTempFile;
+
+    /// Returns the `key,value` part of every record in the file, skipping its header.
+    fn records(path: &Path) -> Vec<String> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .filter(|line| !line.starts_with("%kv\t"))
+            .map(|line| line.split('\t').next().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn fuzz_test() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u8>::open(f.path()).unwrap();
+        let mut map = HashMap::<String, u8>::new();
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..100_000 {
+            let key = format!("key{}", rng.gen::<u32>());
+            let value = rng.gen();
+
+            store.set(&key, &value).unwrap();
+            map.insert(key, value);
+        }
+
+        let store = store.load_map().unwrap();
+        for (key, value) in map {
+            assert_eq!(value, *store.get(&key).unwrap());
+        }
+    }
+
+    #[test]
+    fn unset() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<String>::open(f.path()).unwrap();
+        store.set("key", &"hello".to_string()).unwrap();
+        assert!(store.contains("key").unwrap());
+        store.unset("key").unwrap();
+        assert!(!store.contains("key").unwrap());
+    }
+
+    #[test]
+    fn unset_prefix() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u32>::open(f.path()).unwrap();
+        for key in ["session:1", "session:2", "sessions", "user:1"] {
+            store.set(key, &0).unwrap();
+        }
+        assert_eq!(2, store.unset_prefix("session:").unwrap());
+        assert_eq!(0, store.unset_prefix("session:").unwrap());
+        assert_eq!(vec!["sessions", "user:1"], store.keys().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn set_if_absent() {
+        let store = Store::<u32>::in_memory();
+        assert!(store.set_if_absent("a", &1).unwrap());
+        assert!(!store.set_if_absent("a", &2).unwrap());
+        assert_eq!(Some(1), store.get("a").unwrap());
+        store.unset("a").unwrap();
+        assert!(store.set_if_absent("a", &3).unwrap());
+
+        let winners = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..8)
+                .map(|i| {
+                    let store = store.clone();
+                    scope.spawn(move || store.set_if_absent("b", &i).unwrap())
+                })
+                .collect();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .filter(|&set| set)
+                .count()
+        });
+        assert_eq!(1, winners);
+    }
+
+    #[test]
+    fn rename() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u32>::open(f.path()).unwrap();
+        store.set("a", &1).unwrap();
+        store
+            .set_with_ttl("b", &2, Duration::from_secs(60))
+            .unwrap();
+        store.rename("b", "c", false).unwrap();
+        assert_eq!(vec!["a", "c"], store.keys().collect::<Vec<_>>());
+        assert_eq!(Some(2), store.get("c").unwrap());
+        assert!(store.metadata("c").unwrap().unwrap().expires.is_some());
+
+        for (old, new) in [("b", "d"), ("a", "c"), ("b", "b")] {
+            assert!(matches!(
+                store.rename(old, new, false),
+                Err(Error::Write(_))
+            ));
+        }
+        // Renaming a key to itself leaves it alone.
+        store.rename("a", "a", false).unwrap();
+        store.rename("a", "a", true).unwrap();
+        assert_eq!(Some(1), store.get("a").unwrap());
+        store.rename("a", "c", true).unwrap();
+        drop(store);
+        let store = Store::<u32>::open(f.path()).unwrap();
+        assert_eq!(vec!["c"], store.keys().collect::<Vec<_>>());
+        assert_eq!(Some(1), store.get("c").unwrap());
+    }
+
+    #[test]
+    fn retain() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u32>::open(f.path()).unwrap();
+        for (i, key) in ["a", "b", "c", "d"].into_iter().enumerate() {
+            store.set(key, &(i as u32)).unwrap();
+        }
+        store.set("a", &5).unwrap();
+        store.unset("d").unwrap();
+        assert_eq!(1, store.retain(|_, &value| value % 2 == 1).unwrap());
+        assert_eq!(0, store.retain(|key, _| key != "c").unwrap());
+        assert_eq!(vec!["a", "b"], store.keys().collect::<Vec<_>>());
+        assert_eq!(Some(5), store.get("a").unwrap());
+    }
+
+    #[test]
+    fn compact() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u8>::open(f.path()).unwrap();
+        for i in 0..10 {
+            store.set("overwritten", &i).unwrap();
+        }
+        store.set("removed", &1).unwrap();
+        store.unset("removed").unwrap();
+        store.set("kept", &2).unwrap();
+
+        store.compact().unwrap();
+
+        assert_eq!(vec!["kept,2", "overwritten,9"], records(f.path()));
+
+        store.set("after", &3).unwrap();
+        assert_eq!(Some(9), store.get("overwritten").unwrap());
+        assert_eq!(Some(3), store.get("after").unwrap());
+        assert_eq!(None, store.get("removed").unwrap());
+    }
+
+    #[test]
+    fn compact_into() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::<u32>::options()
+            .segment_size(64)
+            .open(&dir.path().join("db"))
+            .unwrap();
+        for i in 0..10 {
+            store.set("a", &i).unwrap();
+        }
+        store.set("b", &1).unwrap();
+        store.unset("b").unwrap();
+
+        let copy = dir.path().join("copy");
+        store.compact_into(&copy).unwrap();
+        assert_eq!(vec!["a,9"], records(&copy));
+        assert_eq!(Some(9), store.get("a").unwrap());
+        assert!(store.compact_into(&copy).is_err());
+
+        let copy = Store::<u32>::open(&copy).unwrap();
+        assert_eq!(Some(9), copy.get("a").unwrap());
+    }
+
+    #[test]
+    fn options() {
+        struct Raw;
+
+        impl Codec for Raw {
+            fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+                serde_json::to_vec(value)
+                    .map(|json| [b"raw:", &json[..]].concat())
+                    .map_err(encode_err)
+            }
+
+            fn decode<T: for<'a> Deserialize<'a>>(bytes: &[u8]) -> Result<T, Error> {
+                serde_json::from_slice(&bytes[4..]).map_err(decode_err)
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let mut options = Store::<u32>::options();
+        options.create(false);
+        assert!(matches!(
+            options.open(&path),
+            Err(Error::Io(err)) if err.kind() == io::ErrorKind::NotFound
+        ));
+        assert!(options.segment_size(64).open(&path).is_err());
+        assert!(std::fs::read_dir(dir.path()).unwrap().next().is_none());
+
+        let store = options.create(true).codec::<Raw>().open(&path).unwrap();
+        store.set("a", &1).unwrap();
+        drop(store);
+        let contents = std::fs::read_to_string(segment_path(&path, 1)).unwrap();
+        // Codecs without a name aren't recorded.
+        assert!(contents.starts_with("%kv\tversion=1\tflags=\tcrc="));
+        assert!(contents.lines().nth(1).unwrap().starts_with("a,raw:1\t"));
+        let store = options.create(false).codec::<Raw>().open(&path).unwrap();
+        assert_eq!(Some(1), store.get("a").unwrap());
+    }
+
+    #[test]
+    fn type_tag() {
+        for format in [Format::Text, Format::Binary] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("db");
+            let store = Store::<u32>::options()
+                .format(format)
+                .segment_size(64)
+                .type_tag("u32")
+                .open(&path)
+                .unwrap();
+            for i in 0..20 {
+                store.set(&format!("key {i}"), &i).unwrap();
+            }
+            store.compact().unwrap();
+            drop(store);
+
+            let mut tail = Tail::<u32>::open_at(&path, Position::default()).unwrap();
+            assert_eq!(20, tail.poll().unwrap().len());
+            let follower = Store::<u32>::options()
+                .type_tag("u32")
+                .open_follower(&path)
+                .unwrap();
+            assert_eq!(Some(19), follower.get("key 19").unwrap());
+            drop(follower);
+
+            assert!(matches!(
+                Store::<String>::options().type_tag("String").open(&path),
+                Err(Error::TypeMismatch { expected, found }) if expected == "String" && found == "u32"
+            ));
+            let untyped = Store::<serde_json::Value>::open(&path).unwrap();
+            assert_eq!(Some(serde_json::json!(3)), untyped.get("key 3").unwrap());
+            drop(untyped);
+            let store = Store::<u32>::options().type_tag("u32").open(&path).unwrap();
+            assert_eq!(Some(7), store.get("key 7").unwrap());
+        }
+
+        // Databases created without a tag can't be checked.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        Store::<u32>::open(&path).unwrap().set("a", &1).unwrap();
+        let store = Store::<u32>::options().type_tag("u32").open(&path).unwrap();
+        assert_eq!(Some(1), store.get("a").unwrap());
+    }
+
+    #[test]
+    fn in_memory() {
+        let store = Store::<u32>::options()
+            .format(Format::Binary)
+            .open_in_memory();
+        for i in 0..10 {
+            store.set("a", &i).unwrap();
+        }
+        store.set("b", &1).unwrap();
+        store.unset("b").unwrap();
+        assert_eq!(Some(9), store.get("a").unwrap());
+        assert_eq!(None, store.get("b").unwrap());
+
+        store.compact().unwrap();
+        assert_eq!(1, store.stats().records);
+        store.set("c", &3).unwrap();
+        assert_eq!(vec!["a", "c"], store.keys().collect::<Vec<_>>());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        store.compact_into(&path).unwrap();
+        let saved = Store::<u32>::open(&path).unwrap();
+        assert_eq!(Some(9), saved.get("a").unwrap());
+        assert_eq!(Some(3), saved.get("c").unwrap());
+    }
+
+    #[test]
+    fn backend() {
+        /// Memory which outlives the store, so that it can be reopened.
+        #[derive(Clone, Default)]
+        struct Shared(Arc<Mutex<Vec<u8>>>);
+
+        impl Backend for Shared {
+            fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+                self.0.lock().read_at(buf, offset)
+            }
+
+            fn append(&mut self, buf: &[u8]) -> io::Result<()> {
+                Backend::append(&mut *self.0.lock(), buf)
+            }
+
+            fn len(&self) -> io::Result<u64> {
+                Backend::len(&*self.0.lock())
+            }
+
+            fn truncate(&mut self, len: u64) -> io::Result<()> {
+                Backend::truncate(&mut *self.0.lock(), len)
+            }
+        }
+
+        let shared = Shared::default();
+        let store = Store::<u32>::options()
+            .open_backend(shared.clone())
+            .unwrap();
+        store.set("a", &1).unwrap();
+        store.set("a", &2).unwrap();
+        store.set("b", &3).unwrap();
+        store.compact().unwrap();
+        store.set("c", &4).unwrap();
+        drop(store);
+
+        let text = String::from_utf8(shared.0.lock().clone()).unwrap();
+        // Along with the header.
+        assert_eq!(4, text.lines().count());
+        let store = Store::<u32>::options().open_backend(shared).unwrap();
+        assert_eq!(Some(2), store.get("a").unwrap());
+        assert_eq!(Some(4), store.get("c").unwrap());
+    }
+
+    #[test]
+    fn misses() {
+        /// Memory which counts the reads made from it.
+        struct Counted(Vec<u8>, Arc<Mutex<usize>>);
+
+        impl Backend for Counted {
+            fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+                *self.1.lock() += 1;
+                self.0.read_at(buf, offset)
+            }
+
+            fn append(&mut self, buf: &[u8]) -> io::Result<()> {
+                Backend::append(&mut self.0, buf)
+            }
+
+            fn len(&self) -> io::Result<u64> {
+                Backend::len(&self.0)
+            }
+
+            fn truncate(&mut self, len: u64) -> io::Result<()> {
+                Backend::truncate(&mut self.0, len)
+            }
+        }
+
+        let reads = Arc::new(Mutex::new(0));
+        let store = Store::<u32>::options()
+            .write_buffer(0)
+            .cache(CacheConfig { max_entries: 0 })
+            .open_backend(Counted(Vec::new(), reads.clone()))
+            .unwrap();
+        for i in 0..100 {
+            store.set(&i.to_string(), &i).unwrap();
+        }
+        store.unset("7").unwrap();
+
+        let before = *reads.lock();
+        for key in ["7", "100", "missing"] {
+            assert_eq!(None, store.get(key).unwrap());
+            assert!(!store.contains(key).unwrap());
+        }
+        assert_eq!(before, *reads.lock());
+        assert_eq!(Some(8), store.get("8").unwrap());
+        assert!(*reads.lock() > before);
+    }
+
+    #[test]
+    fn read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        assert!(Store::<u32>::open_read_only(&path).is_err());
+        assert!(!path.exists());
+
+        let store = Store::<u32>::open(&path).unwrap();
+        store.set("a", &1).unwrap();
+        assert!(matches!(
+            Store::<u32>::open_read_only(&path),
+            Err(Error::Locked { .. })
+        ));
+        drop(store);
+        let mut file = File::options().append(true).open(&path).unwrap();
+        file.write_all(b"b,2\tcrc=").unwrap();
+        let len = file.metadata().unwrap().len();
+
+        assert!(Store::<u32>::open_read_only(&path).is_err());
+        let mut options = Store::<u32>::options();
+        options
+            .read_only(true)
+            .recovery(RecoveryPolicy::TruncateTail);
+        let reader = options.open(&path).unwrap();
+        let other = options.open(&path).unwrap();
+        assert_eq!(Some(1), reader.get("a").unwrap());
+        assert!(matches!(reader.set("a", &2), Err(Error::ReadOnly)));
+        assert!(matches!(reader.unset("a"), Err(Error::ReadOnly)));
+        assert!(matches!(reader.compact(), Err(Error::ReadOnly)));
+        assert!(matches!(
+            Store::<u32>::open(&path),
+            Err(Error::Locked { .. })
+        ));
+        drop((reader, other));
+        assert_eq!(len, std::fs::metadata(&path).unwrap().len());
+
+        let segmented = dir.path().join("segmented");
+        let store = Store::<u32>::options()
+            .segment_size(32)
+            .open(&segmented)
+            .unwrap();
+        for i in 0..10 {
+            store.set("b", &i).unwrap();
+        }
+        drop(store);
+        let reader = Store::<u32>::open_read_only(&segmented).unwrap();
+        assert_eq!(Some(9), reader.get("b").unwrap());
+    }
+
+    #[test]
+    fn follower() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let owner = Store::<u32>::options().write_buffer(0).open(&path).unwrap();
+        owner.set("a", &1).unwrap();
+
+        let follower = Store::<u32>::open_follower(&path).unwrap();
+        assert_eq!(Some(1), follower.get("a").unwrap());
+        assert!(matches!(follower.set("a", &2), Err(Error::ReadOnly)));
+        assert!(matches!(follower.compact(), Err(Error::ReadOnly)));
+
+        owner.set("a", &2).unwrap();
+        owner.set("b", &3).unwrap();
+        assert_eq!(Some(2), follower.get("a").unwrap());
+        assert_eq!(vec!["a", "b"], follower.keys().collect::<Vec<_>>());
+
+        owner.unset("b").unwrap();
+        owner.compact().unwrap();
+        owner.set("d", &5).unwrap();
+        assert_eq!(vec!["a", "d"], follower.keys().collect::<Vec<_>>());
+        assert_eq!(2, follower.stats().records);
+
+        // A write in progress is left for later.
+        drop(owner);
+        let mut file = File::options().append(true).open(&path).unwrap();
+        file.write_all(b"c,4").unwrap();
+        assert!(!follower.contains("c").unwrap());
+        file.write_all(b"\n").unwrap();
+        assert!(follower.contains("c").unwrap());
+
+        let segmented = dir.path().join("segmented");
+        let owner = Store::<u32>::options()
+            .write_buffer(0)
+            .segment_size(64)
+            .open(&segmented)
+            .unwrap();
+        owner.set("a", &1).unwrap();
+        let follower = Store::<u32>::open_follower(&segmented).unwrap();
+        for i in 0..20 {
+            owner.set("b", &i).unwrap();
+        }
+        assert_eq!(Some(19), follower.get("b").unwrap());
+        owner.compact().unwrap();
+        owner.unset("a").unwrap();
+        assert_eq!(vec!["b"], follower.keys().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn index_survives_reopen() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<String>::open(f.path()).unwrap();
+        store.set("a", &"first".to_string()).unwrap();
+        store.set("b", &"second".to_string()).unwrap();
+        store.set("a", &"third".to_string()).unwrap();
+        store.unset("b").unwrap();
+        drop(store);
+
+        let store = Store::<String>::open(f.path()).unwrap();
+        assert_eq!(Some("third".to_string()), store.get("a").unwrap());
+        assert_eq!(None, store.get("b").unwrap());
+        assert!(!store.contains("b").unwrap());
+
+        store.set("b", &"fourth".to_string()).unwrap();
+        assert_eq!(Some("fourth".to_string()), store.get("b").unwrap());
+    }
+
+    #[test]
+    fn keys() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u8>::open(f.path()).unwrap();
+        store.set("a", &1).unwrap();
+        store.set("b", &2).unwrap();
+        store.set("a", &3).unwrap();
+        store.set("c", &4).unwrap();
+        store.unset("b").unwrap();
+
+        let mut keys: Vec<String> = store.keys().collect();
+        keys.sort();
+        assert_eq!(vec!["a", "c"], keys);
+    }
+
+    #[test]
+    fn keys_matching() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u32>::open(f.path()).unwrap();
+        for key in ["user:1", "user:2:name", "users", "admin:1"] {
+            store.set(key, &0).unwrap();
+        }
+        assert_eq!(
+            vec!["user:1", "user:2:name"],
+            store.keys_matching("user:*").collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["admin:1", "user:1"],
+            store.keys_matching("*:?").collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn iter() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u8>::open(f.path()).unwrap();
+        store.set("a", &1).unwrap();
+        store.set("b", &2).unwrap();
+        store.set("c", &3).unwrap();
+        store.set("a", &4).unwrap();
+        store.unset("b").unwrap();
+
+        let mut iter = store.iter();
+        assert_eq!(("c".to_string(), 3), iter.next().unwrap().unwrap());
+        store.unset("a").unwrap();
+        assert!(iter.next().is_none());
+
+        let entries: Vec<_> = store.iter().map(Result::unwrap).collect();
+        assert_eq!(vec![("c".to_string(), 3)], entries);
+    }
+
+    #[test]
+    fn write_batch() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u8>::open(f.path()).unwrap();
+        store.set("a", &1).unwrap();
+
+        let mut batch = WriteBatch::new();
+        batch.set("b", &2).unwrap();
+        batch.set("c", &3).unwrap();
+        batch.unset("a").unwrap();
+        batch.set("b", &4).unwrap();
+        assert_eq!(4, batch.len());
+        store.apply(batch).unwrap();
+
+        assert_eq!(None, store.get("a").unwrap());
+        assert_eq!(Some(4), store.get("b").unwrap());
+        assert_eq!(Some(3), store.get("c").unwrap());
+
+        store.flush().unwrap();
+        assert_eq!(
+            vec!["a,1", "b,2", "c,3", "a,null", "b,4"],
+            records(f.path())
+        );
+    }
+
+    #[test]
+    fn transaction() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u8>::open(f.path()).unwrap();
+        store.set("a", &1).unwrap();
+
+        let result = store.transaction(|txn| {
+            txn.set("a", &2)?;
+            txn.set("b", &3)?;
+            assert_eq!(Some(2), txn.get("a")?);
+            Err::<(), _>(Error::Write("rollback".to_string()))
+        });
+        assert!(result.is_err());
+        assert_eq!(Some(1), store.get("a").unwrap());
+        assert!(!store.contains("b").unwrap());
+
+        let sum = store
+            .transaction(|txn| {
+                let a = txn.get("a")?.unwrap_or_default();
+                txn.unset("a")?;
+                txn.set("b", &(a + 2))?;
+                assert_eq!(None, txn.get("a")?);
+                Ok(a + 2)
+            })
+            .unwrap();
+        assert_eq!(3, sum);
+        assert_eq!(None, store.get("a").unwrap());
+        assert_eq!(Some(3), store.get("b").unwrap());
+    }
+
+    #[test]
+    fn get_many() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::<u32>::options()
+            .segment_size(32)
+            .open(&dir.path().join("db"))
+            .unwrap();
+        for i in 0..10 {
+            store.set(&format!("k{i}"), &i).unwrap();
+        }
+        store.unset("k3").unwrap();
+
+        let values = store
+            .get_many(&["k7", "k1", "k3", "k1", "missing"])
+            .unwrap();
+        let expected = FxHashMap::from_iter([("k1".to_string(), 1), ("k7".to_string(), 7)]);
+        assert_eq!(expected, values);
+        assert!(store.get_many(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn get_or_insert_with() {
+        let store = Store::<u32>::in_memory();
+        store.set("a", &1).unwrap();
+        assert_eq!(1, store.get_or_insert_with("a", || unreachable!()).unwrap());
+
+        let calls = std::sync::atomic::AtomicU32::new(0);
+        std::thread::scope(|scope| {
+            for i in 0..4 {
+                let (store, calls) = (store.clone(), &calls);
+                scope.spawn(move || {
+                    let value = store
+                        .get_or_insert_with("b", || {
+                            calls.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                            i
+                        })
+                        .unwrap();
+                    assert_eq!(Some(value), store.get("b").unwrap());
+                });
+            }
+        });
+        assert_eq!(1, calls.into_inner());
+        assert_eq!(2, store.stats().records);
+    }
+
+    #[test]
+    fn update() {
+        let store = Store::<Vec<u32>>::in_memory();
+        let push = |ids: Option<Vec<u32>>, id| {
+            let mut ids = ids.unwrap_or_default();
+            ids.push(id);
+            Some(ids)
+        };
+        assert_eq!(
+            Some(vec![1]),
+            store.update("a", |ids| push(ids, 1)).unwrap()
+        );
+        std::thread::scope(|scope| {
+            for id in 2..6 {
+                let store = store.clone();
+                scope.spawn(move || store.update("a", |ids| push(ids, id)).unwrap());
+            }
+        });
+        let mut ids = store.get("a").unwrap().unwrap();
+        ids.sort_unstable();
+        assert_eq!(vec![1, 2, 3, 4, 5], ids);
+
+        assert_eq!(None, store.update("a", |_| None).unwrap());
+        assert!(!store.contains("a").unwrap());
+        let records = store.stats().records;
+        assert_eq!(None, store.update("b", |ids| ids).unwrap());
+        assert_eq!(records, store.stats().records);
+    }
+
+    #[test]
+    fn remove() {
+        let store = Store::<u32>::in_memory();
+        store.set("a", &1).unwrap();
+        assert_eq!(Some(1), store.remove("a").unwrap());
+        assert!(!store.contains("a").unwrap());
+        assert_eq!(None, store.remove("a").unwrap());
+        assert_eq!(2, store.stats().records);
+
+        for i in 0..4 {
+            store.set(&i.to_string(), &i).unwrap();
+        }
+        let removed: Vec<_> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..8)
+                .map(|i| {
+                    let store = store.clone();
+                    scope.spawn(move || store.remove(&(i % 4).to_string()).unwrap())
+                })
+                .collect();
+            threads.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+        assert_eq!(4, removed.iter().flatten().count());
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn replace() {
+        let store = Store::<u32>::in_memory();
+        assert_eq!(None, store.replace("a", &1).unwrap());
+        assert_eq!(Some(1), store.replace("a", &2).unwrap());
+        assert_eq!(Some(2), store.get("a").unwrap());
+
+        let first = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..4)
+                .map(|i| {
+                    let store = store.clone();
+                    scope.spawn(move || store.replace("b", &i).unwrap())
+                })
+                .collect();
+            threads
+                .into_iter()
+                .map(|t| t.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(1, first.iter().filter(|old| old.is_none()).count());
+    }
+
+    #[test]
+    fn compare_and_swap() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u32>::open(f.path()).unwrap();
+
+        assert_eq!(
+            (),
+            store
+                .compare_and_swap("a", None, Some(&1))
+                .unwrap()
+                .unwrap()
+        );
+        assert_eq!(
+            Err(Some(1)),
+            store.compare_and_swap("a", None, Some(&2)).unwrap()
+        );
+        assert_eq!(
+            Err(Some(1)),
+            store.compare_and_swap("a", Some(&3), None).unwrap()
+        );
+        assert_eq!(
+            (),
+            store
+                .compare_and_swap("a", Some(&1), None)
+                .unwrap()
+                .unwrap()
+        );
+        assert_eq!(None, store.get("a").unwrap());
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let store = store.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        let mut current = store.get("counter").unwrap();
+                        loop {
+                            let new = current.unwrap_or_default() + 1;
+                            match store
+                                .compare_and_swap("counter", current.as_ref(), Some(&new))
+                                .unwrap()
+                            {
+                                Ok(()) => break,
+                                Err(actual) => current = actual,
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(Some(400), store.get("counter").unwrap());
+    }
+
+    #[test]
+    fn ttl() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u8>::open(f.path()).unwrap();
+        store.set_with_ttl("short", &1, Duration::ZERO).unwrap();
+        store
+            .set_with_ttl("long", &2, Duration::from_secs(3600))
+            .unwrap();
+
+        assert_eq!(None, store.get("short").unwrap());
+        assert!(!store.contains("short").unwrap());
+        assert_eq!(Some(2), store.get("long").unwrap());
+        assert_eq!(vec!["long".to_string()], store.keys().collect::<Vec<_>>());
+        assert_eq!(1, store.load_map().unwrap().len());
+
+        drop(store);
+        let store = Store::<u8>::open(f.path()).unwrap();
+        assert!(!store.contains("short").unwrap());
+        assert_eq!(Some(2), store.get("long").unwrap());
+
+        store.compact().unwrap();
+        let contents = std::fs::read_to_string(f.path()).unwrap();
+        assert!(contents.lines().nth(1).unwrap().starts_with("long,2\texp="));
+        assert_eq!(2, contents.lines().count());
+    }
+
+    #[test]
+    fn scan_prefix() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u8>::open(f.path()).unwrap();
+        store.set("user:1/name", &1).unwrap();
+        store.set("user:2/name", &2).unwrap();
+        store.set("user:1/age", &3).unwrap();
+        store.set("group:1/name", &4).unwrap();
+        store.unset("user:2/name").unwrap();
+
+        let entries: Vec<_> = store.scan_prefix("user:").map(Result::unwrap).collect();
+        assert_eq!(
+            vec![
+                ("user:1/age".to_string(), 3),
+                ("user:1/name".to_string(), 1)
+            ],
+            entries
+        );
+        assert_eq!(0, store.scan_prefix("nothing").count());
+    }
+
+    #[test]
+    fn range() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u8>::open(f.path()).unwrap();
+        for (i, key) in ["2024/03", "2024/01", "2023/12", "2024/02", "2024/04"]
+            .into_iter()
+            .enumerate()
+        {
+            store.set(key, &(i as u8)).unwrap();
+        }
+        store.unset("2024/02").unwrap();
+
+        let keys = |iter: Iter<u8>| -> Vec<String> { iter.map(|e| e.unwrap().0).collect() };
+        assert_eq!(
+            vec!["2024/01", "2024/03"],
+            keys(store.range("2024/01".."2024/04"))
+        );
+        assert_eq!(
+            vec!["2023/12", "2024/01", "2024/03", "2024/04"],
+            keys(store.range(..))
+        );
+        assert_eq!(vec!["2024/03", "2024/04"], keys(store.range("2024/02"..)));
+        assert_eq!(vec!["2023/12"], keys(store.range(..="2023/12")));
+    }
+
+    #[test]
+    fn sync_policy() {
+        for policy in [
+            SyncPolicy::Never,
+            SyncPolicy::EveryWrite,
+            SyncPolicy::Interval(Duration::from_millis(1)),
+        ] {
+            let f = NamedTempFile::new().unwrap();
+            let store = Store::<u8>::options().sync(policy).open(f.path()).unwrap();
+            store.set("a", &1).unwrap();
+            std::thread::sleep(Duration::from_millis(2));
+            store.set("b", &2).unwrap();
+            store.sync().unwrap();
+            drop(store);
+
+            let store = Store::<u8>::open(f.path()).unwrap();
+            assert_eq!(Some(1), store.get("a").unwrap());
+            assert_eq!(Some(2), store.get("b").unwrap());
+        }
+    }
+
+    #[test]
+    fn corrupt_record() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u8>::open(f.path()).unwrap();
+        store.set("a", &1).unwrap();
+        store.set("b", &2).unwrap();
+        drop(store);
+
+        let contents = std::fs::read_to_string(f.path()).unwrap();
+        std::fs::write(f.path(), contents.replacen("b,2", "b,3", 1)).unwrap();
+        let offset = contents.find("b,2").unwrap() as u64;
+        assert!(matches!(
+            Store::<u8>::open(f.path()),
+            Err(Error::Corrupt { offset: at, .. }) if at == offset
+        ));
+
+        // A write torn before its newline.
+        std::fs::write(f.path(), &contents[..contents.len() - 4]).unwrap();
+        assert!(matches!(
+            Store::<u8>::open(f.path()),
+            Err(Error::Corrupt { offset: at, .. }) if at == offset
+        ));
+    }
+
+    #[test]
+    fn recovery() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let store = Store::<u8>::open(&path).unwrap();
+        store.set("a", &1).unwrap();
+        store.set("b", &2).unwrap();
+        store.set("c", &3).unwrap();
+        drop(store);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let torn = &contents[..contents.len() - 4];
+        let open = |recovery| Store::<u8>::options().recovery(recovery).open(&path);
+
+        std::fs::write(&path, torn).unwrap();
+        assert!(open(RecoveryPolicy::Strict).is_err());
+        let store = open(RecoveryPolicy::TruncateTail).unwrap();
+        assert_eq!(None, store.get("c").unwrap());
+        store.set("c", &4).unwrap();
+        drop(store);
+        assert_eq!(Some(4), Store::<u8>::open(&path).unwrap().get("c").unwrap());
+
+        let damaged = contents.replacen("b,2", "b,5", 1);
+        std::fs::write(&path, &damaged[..damaged.len() - 4]).unwrap();
+        assert!(open(RecoveryPolicy::TruncateTail).is_err());
+        let store = open(RecoveryPolicy::SkipCorrupt).unwrap();
+        assert_eq!(Some(1), store.get("a").unwrap());
+        assert_eq!(None, store.get("b").unwrap());
+        assert_eq!(None, store.get("c").unwrap());
+        assert!(!quarantine_path(&path).exists());
+        drop(store);
+
+        std::fs::write(&path, &damaged).unwrap();
+        let store = open(RecoveryPolicy::Quarantine).unwrap();
+        assert_eq!(Some(3), store.get("c").unwrap());
+        let quarantined = std::fs::read_to_string(quarantine_path(&path)).unwrap();
+        assert!(quarantined.starts_with("b,5\t"));
+        assert_eq!(1, quarantined.lines().count());
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn msgpack() {
+        let f = NamedTempFile::new().unwrap();
+        let value = BTreeMap::from([("tags".to_string(), vec![1, 2, 3])]);
+        let store = Store::<BTreeMap<String, Vec<u32>>, MsgPackCodec>::open(f.path()).unwrap();
+        store.set("a", &value).unwrap();
+        drop(store);
+
+        let store = Store::<BTreeMap<String, Vec<u32>>, MsgPackCodec>::open(f.path()).unwrap();
+        assert_eq!(Some(value), store.get("a").unwrap());
+        // 0x81 0xa4 "tags" 0x93 1 2 3
+        assert_eq!("a,gaR0YWdzkwECAw==", records(f.path())[0]);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn compression() {
+        let f = NamedTempFile::new().unwrap();
+        let large = "abc".repeat(1000);
+        let store = Store::<String>::options()
+            .compression(Compression::Zstd {
+                threshold: 64,
+                level: 3,
+            })
+            .open(f.path())
+            .unwrap();
+        store.set("large", &large).unwrap();
+        store.set("small", &"abc".to_string()).unwrap();
+        drop(store);
+
+        let contents = std::fs::read_to_string(f.path()).unwrap();
+        let lines: Vec<_> = contents.lines().collect();
+        assert!(lines[0].contains("\tflags=zstd\t"));
+        assert!(lines[1].contains("\tcmp=zstd"));
+        assert!(lines[1].len() < 200);
+        assert_eq!(records(f.path())[1], "small,\"abc\"");
+
+        // Compressed values are read regardless of the current setting.
+        let store = Store::<String>::open(f.path()).unwrap();
+        assert_eq!(Some(large.clone()), store.get("large").unwrap());
+        store.compact().unwrap();
+        assert_eq!(Some(large), store.get("large").unwrap());
+        assert!(!std::fs::read_to_string(f.path())
+            .unwrap()
+            .contains("cmp=zstd"));
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encryption() {
+        let f = NamedTempFile::new().unwrap();
+        let key = [7; 32];
+        let store = Store::<String>::options()
+            .encryption_key(key)
+            .open(f.path())
+            .unwrap();
+        store.set("token", &"secret".to_string()).unwrap();
+        store.set("gone", &"secret".to_string()).unwrap();
+        store.unset("gone").unwrap();
+        drop(store);
+
+        let contents = std::fs::read_to_string(f.path()).unwrap();
+        assert!(!contents.contains("secret"));
+        assert!(contents.contains("\taead=xchacha20poly1305"));
+        assert_eq!(records(f.path())[2], "gone,null");
+
+        let store = Store::<String>::options()
+            .encryption_key(key)
+            .open(f.path())
+            .unwrap();
+        assert_eq!(Some("secret".to_string()), store.get("token").unwrap());
+        drop(store);
+
+        // Refused by the header without a key, and by the first record with the wrong one.
+        assert!(contents
+            .lines()
+            .next()
+            .unwrap()
+            .contains("\tflags=encrypted\t"));
+        let first = contents.find('\n').unwrap() as u64 + 1;
+        for (options, offset) in [
+            (Store::<String>::options(), 0),
+            (
+                Store::<String>::options()
+                    .encryption_key([8; 32])
+                    .recovery(RecoveryPolicy::SkipCorrupt)
+                    .clone(),
+                first,
+            ),
+        ] {
+            assert!(matches!(
+                options.open(f.path()),
+                Err(Error::Decrypt { offset: at }) if at == offset
+            ));
+        }
+    }
+
+    #[test]
+    fn ndjson_format() {
+        use serde_json::{json, Value};
+
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<Value>::options()
+            .format(Format::Ndjson)
+            .open(f.path())
+            .unwrap();
+        store.set("a", &json!({ "b": [1, 2] })).unwrap();
+        store.set("gone", &json!(1)).unwrap();
+        store.unset("gone").unwrap();
+        drop(store);
+
+        let contents = std::fs::read_to_string(f.path()).unwrap();
+        let lines: Vec<Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert!(lines[0].as_str().unwrap().starts_with("%kv\tversion=1\t"));
+        assert_eq!(json!({ "b": [1, 2] }), lines[1]["v"]);
+        assert_eq!(json!("gone"), lines[3]["k"]);
+        assert_eq!(Value::Null, lines[3]["v"]);
+
+        // The format of existing databases is detected, regardless of the options.
+        let store = Store::<Value>::open(f.path()).unwrap();
+        assert_eq!(Some(json!({ "b": [1, 2] })), store.get("a").unwrap());
+        assert_eq!(None, store.get("gone").unwrap());
+        store.compact().unwrap();
+        drop(store);
+        let compacted = std::fs::read_to_string(f.path()).unwrap();
+        assert_eq!(2, compacted.lines().count());
+        let header: String = serde_json::from_str(compacted.lines().next().unwrap()).unwrap();
+        assert!(header.starts_with("%kv\tversion=1\t"));
+        assert!(header.contains("\tgeneration=1\t"));
+    }
+
+    #[test]
+    fn binary_format() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<String>::options()
+            .format(Format::Binary)
+            .open(f.path())
+            .unwrap();
+        store.set("a", &"line\nbreak".to_string()).unwrap();
+        store.set("b", &"b".to_string()).unwrap();
+        store.unset("b").unwrap();
+        store.set("c", &"c".to_string()).unwrap();
+        drop(store);
+
+        let contents = std::fs::read(f.path()).unwrap();
+        assert!(contents.starts_with(b"%kv\tversion=1\tcodec=json\t"));
+        let line_len = contents.iter().position(|&b| b == b'\n').unwrap() + 1;
+        assert!(contents[line_len..].starts_with(b"%kv\x02"));
+
+        // The format of existing databases is detected, regardless of the options.
+        let store = Store::<String>::open(f.path()).unwrap();
+        assert_eq!(Some("line\nbreak".to_string()), store.get("a").unwrap());
+        assert_eq!(None, store.get("b").unwrap());
+        store.compact().unwrap();
+        assert_eq!(vec!["a", "c"], store.keys().collect::<Vec<_>>());
+        drop(store);
+        let compacted = std::fs::read(f.path()).unwrap();
+        let line_len = compacted.iter().position(|&b| b == b'\n').unwrap() + 1;
+        let header = std::str::from_utf8(&compacted[..line_len]).unwrap();
+        assert!(header.starts_with("%kv\tversion=1\tcodec=json\t"));
+        assert!(header.contains("\tgeneration=1\t"));
+        assert!(compacted[line_len..].starts_with(b"%kv\x02"));
+
+        std::fs::write(f.path(), &contents[..contents.len() - 1]).unwrap();
+        assert!(Store::<String>::open(f.path()).is_err());
+        let store = Store::<String>::options()
+            .recovery(RecoveryPolicy::TruncateTail)
+            .open(f.path())
+            .unwrap();
+        assert_eq!(None, store.get("c").unwrap());
+        assert_eq!(Some("line\nbreak".to_string()), store.get("a").unwrap());
+    }
+
+    #[test]
+    fn segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let open = || Store::<u32>::options().segment_size(64).open(&path);
+        let segment_files = || {
+            let mut names: Vec<_> = std::fs::read_dir(dir.path())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .filter(|name| name != "db.lock" && name != "db.idx")
+                .collect();
+            names.sort();
+            names
+        };
+
+        let store = open().unwrap();
+        assert!(matches!(open(), Err(Error::Locked { .. })));
+        for i in 0..10 {
+            store.set(&format!("key{i}"), &i).unwrap();
+        }
+        store.unset("key3").unwrap();
+        assert!(segment_files().len() > 2);
+        assert!(!path.exists());
+        drop(store);
+
+        // Segments are detected without being asked for.
+        let store = Store::<u32>::open(&path).unwrap();
+        assert_eq!(Some(9), store.get("key9").unwrap());
+        assert_eq!(None, store.get("key3").unwrap());
+        assert_eq!(9, store.keys().count());
+        drop(store);
+
+        // Overwriting the same keys leaves the oldest segments dead, which get deleted.
+        let store = open().unwrap();
+        for i in 0..10 {
+            store.set(&format!("key{i}"), &(i + 10)).unwrap();
+        }
+        assert!(!segment_files().contains(&"db.0001".to_string()));
+
+        let before = segment_files();
+        store.compact().unwrap();
+        let after = segment_files();
+        assert!(after.iter().all(|name| !before.contains(name)));
+        assert_eq!(Some(13), store.get("key3").unwrap());
+        drop(store);
+
+        let store = Store::<u32>::open(&path).unwrap();
+        let map = store.load_map().unwrap();
+        assert_eq!(10, map.len());
+        assert_eq!(Some(&19), map.get("key9"));
+    }
+
+    #[test]
+    fn concurrent_reads() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u32>::open(f.path()).unwrap();
+        for i in 0..100 {
+            store.set(&i.to_string(), &i).unwrap();
+        }
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                let store = store.clone();
+                scope.spawn(move || {
+                    for i in 0..100 {
+                        let value = store.get(&i.to_string()).unwrap().unwrap();
+                        assert!(value == i || value == i + 100);
+                    }
+                });
+            }
+            for i in 0..100 {
+                store.set(&i.to_string(), &(i + 100)).unwrap();
+            }
+        });
+
+        assert_eq!(Some(199), store.get("99").unwrap());
+    }
+
+    #[test]
+    fn write_buffer() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u32>::open(f.path()).unwrap();
+        store.set("a", &1).unwrap();
+        assert!(records(f.path()).is_empty());
+        assert_eq!(Some(1), store.get("a").unwrap());
+        store.flush().unwrap();
+        assert_eq!(vec!["a,1"], records(f.path()));
+        store.set("b", &2).unwrap();
+        drop(store);
+        assert_eq!(vec!["a,1", "b,2"], records(f.path()));
+
+        let store = Store::<u32>::options()
+            .write_buffer(0)
+            .open(f.path())
+            .unwrap();
+        store.set("c", &3).unwrap();
+        assert_eq!(vec!["a,1", "b,2", "c,3"], records(f.path()));
+
+        // Records bigger than the buffer are written right away.
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<String>::options()
+            .write_buffer(16)
+            .open(f.path())
+            .unwrap();
+        store.set("a", &"a".repeat(16)).unwrap();
+        assert_eq!(1, records(f.path()).len());
+    }
+
+    #[test]
+    fn auto_compact() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u32>::options()
+            .auto_compact(4.0)
+            .write_buffer(0)
+            .open(f.path())
+            .unwrap();
+        for i in 0..100 {
+            store.set("a", &i).unwrap();
+            store.set("b", &i).unwrap();
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while records(f.path()).len() > 10 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(records(f.path()).len() <= 10);
+        assert_eq!(Some(99), store.get("a").unwrap());
+        assert_eq!(Some(99), store.get("b").unwrap());
+    }
+
+    #[test]
+    fn compaction_policy() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u32>::options()
+            .compaction_policy(CompactionPolicy {
+                max_dead_ratio: 0.5,
+                min_file_size: 1024,
+            })
+            .write_buffer(0)
+            .open(f.path())
+            .unwrap();
+        // Mostly dead, but too small to be worth compacting.
+        for i in 0..20 {
+            store.set("a", &i).unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(20, records(f.path()).len());
+
+        for i in 0..100 {
+            store.set("a", &i).unwrap();
+        }
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while store.stats().dead_ratio > 0.5 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(store.stats().dead_ratio <= 0.5);
+        assert!(records(f.path()).len() < 120);
+        assert_eq!(Some(99), store.get("a").unwrap());
+    }
+
+    #[test]
+    fn max_file_size() {
+        let f = NamedTempFile::new().unwrap();
+        let open = |policy| {
+            Store::<u32>::options()
+                .max_file_size(512, policy)
+                .write_buffer(0)
+                .open(f.path())
+                .unwrap()
+        };
+
+        let store = open(FullPolicy::Fail);
+        let mut written = 0;
+        let err = loop {
+            match store.set("a", &written) {
+                Ok(()) => written += 1,
+                Err(err) => break err,
+            }
+        };
+        assert!(matches!(err, Error::Full));
+        assert!(std::fs::metadata(f.path()).unwrap().len() <= 512);
+        assert_eq!(Some(written - 1), store.get("a").unwrap());
+        drop(store);
+
+        // Overwrites keep fitting once compacted.
+        let store = open(FullPolicy::Compact);
+        for i in 0..100 {
+            store.set("a", &i).unwrap();
+        }
+        assert!(std::fs::metadata(f.path()).unwrap().len() <= 512);
+        assert_eq!(Some(99), store.get("a").unwrap());
+        for i in 0..100 {
+            if let Err(err) = store.set(&format!("key {i}"), &i) {
+                assert!(matches!(err, Error::Full));
+                break;
+            }
+        }
+        drop(store);
+
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        let unset = Arc::clone(&evicted);
+        let store = Store::<u32>::options()
+            .max_file_size(512, FullPolicy::EvictOldest)
+            .write_buffer(0)
+            .on_unset(move |key| unset.lock().push(key.to_string()))
+            .open(f.path())
+            .unwrap();
+        for i in 0..100 {
+            store.set(&format!("key {i}"), &i).unwrap();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(std::fs::metadata(f.path()).unwrap().len() <= 512);
+        // Evictions are reported like any other unset.
+        let evicted = evicted.lock();
+        assert_eq!(["a", "key 0"], evicted[..2]);
+        assert_eq!(101 - store.keys().count(), evicted.len());
+        assert_eq!(Some(99), store.get("key 99").unwrap());
+        assert_eq!(None, store.get("key 0").unwrap());
+        assert_eq!(None, store.get("a").unwrap());
+        let keys: Vec<_> = store.keys().collect();
+        assert!(keys.len() > 2);
+        assert!(keys.iter().all(|key| key.starts_with("key ")));
+        drop(store);
+
+        // A write which can never fit evicts nothing.
+        let store = Store::<String>::options()
+            .max_file_size(512, FullPolicy::EvictOldest)
+            .open(f.path())
+            .unwrap();
+        assert!(matches!(
+            store.set("huge", &"x".repeat(1000)),
+            Err(Error::Full)
+        ));
+        assert_eq!(keys, store.keys().collect::<Vec<_>>());
+        drop(store);
+        assert_eq!(keys.len(), Store::<u32>::open(f.path()).unwrap().len());
+    }
+
+    #[test]
+    fn max_live_keys() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u32>::options()
+            .max_live_keys(3)
+            .open(f.path())
+            .unwrap();
+        for key in ["a", "b", "c"] {
+            store.set(key, &1).unwrap();
+        }
+        // Reading `a` makes `b` the least recently used key.
+        assert_eq!(Some(1), store.get("a").unwrap());
+        store.set("d", &1).unwrap();
+        assert_eq!(vec!["a", "c", "d"], store.keys().collect::<Vec<_>>());
+        store.unset("c").unwrap();
+        store.set("e", &1).unwrap();
+        assert_eq!(vec!["a", "d", "e"], store.keys().collect::<Vec<_>>());
+        drop(store);
+
+        // Reopening with a lower cap evicts the keys written the longest ago.
+        let store = Store::<u32>::options()
+            .max_live_keys(2)
+            .open(f.path())
+            .unwrap();
+        assert_eq!(vec!["d", "e"], store.keys().collect::<Vec<_>>());
+        drop(store);
+        let store = Store::<u32>::open(f.path()).unwrap();
+        assert_eq!(vec!["d", "e"], store.keys().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn key_policy() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u32>::open(f.path()).unwrap();
+        store.set("a,b", &1).unwrap();
+        drop(store);
+
+        let store = Store::<u32>::options()
+            .key_policy(KeyPolicy::Strict)
+            .open(f.path())
+            .unwrap();
+        for key in ["user-1", "first_last", "ada@example.com", "a/b:c"] {
+            store.set(key, &1).unwrap();
+        }
+        for key in ["", "a,b", "100%", "line\nbreak"] {
+            assert!(matches!(store.set(key, &1), Err(Error::InvalidKey(k)) if k == key));
+        }
+        // Batches are refused as a whole.
+        let mut batch = WriteBatch::new();
+        batch.set("ok", &1).unwrap();
+        batch.set("not,ok", &1).unwrap();
+        assert!(store.apply(batch).is_err());
+        assert!(!store.contains("ok").unwrap());
+        store.unset("a,b").unwrap();
+        drop(store);
+
+        let store = Store::<u32>::options()
+            .key_policy(KeyPolicy::Custom(|key| key.starts_with("user:")))
+            .open(f.path())
+            .unwrap();
+        store.set("user:1", &1).unwrap();
+        assert!(store.set("admin", &1).is_err());
+    }
+
+    #[test]
+    fn max_value_size() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<String>::options()
+            .max_value_size(8)
+            .open(f.path())
+            .unwrap();
+        // The limit applies to the encoded value, quotes included.
+        store.set("a", &"123456".to_string()).unwrap();
+        assert!(matches!(
+            store.set("b", &"1234567".to_string()),
+            Err(Error::ValueTooLarge { key, size: 9, max: 8 }) if key == "b"
+        ));
+        let mut batch = WriteBatch::new();
+        batch.set("c", &"c".to_string()).unwrap();
+        batch.set("d", &"d".repeat(100)).unwrap();
+        assert!(store.apply(batch).is_err());
+        assert_eq!(vec!["a"], store.keys().collect::<Vec<_>>());
+        store.unset("a").unwrap();
+    }
+
+    #[test]
+    fn watch() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u32>::open(f.path()).unwrap();
+        let events = store.watch("user:");
+        let all = store.watch("");
+
+        store.set("user:1", &1).unwrap();
+        store.clone().set("other", &2).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.set("user:2", &3).unwrap();
+        batch.unset("user:1").unwrap();
+        store.apply(batch).unwrap();
+        drop(all);
+        store.set("user:3", &4).unwrap();
+
+        let received: Vec<_> = events.try_iter().collect();
+        assert_eq!(
+            vec![
+                Event::Set {
+                    key: "user:1".to_string(),
+                    value: 1
+                },
+                Event::Set {
+                    key: "user:2".to_string(),
+                    value: 3
+                },
+                Event::Unset {
+                    key: "user:1".to_string()
+                },
+                Event::Set {
+                    key: "user:3".to_string(),
+                    value: 4
+                },
+            ],
+            received
+        );
+        assert_eq!(1, store.0.read().watchers.len());
+    }
+
+    #[test]
+    fn hooks() {
+        let f = NamedTempFile::new().unwrap();
+        let (sender, events) = mpsc::channel();
+        let (set, unset, compacted) = (sender.clone(), sender.clone(), sender);
+        let store = Store::<u32>::options()
+            .on_set(move |key, value| set.send(format!("set {key} {value}")).unwrap())
+            .on_unset(move |key| unset.send(format!("unset {key}")).unwrap())
+            .on_compact(move || compacted.send("compact".to_string()).unwrap())
+            .open(f.path())
+            .unwrap();
+
+        store.set("a", &1).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.set("b", &2).unwrap();
+        batch.unset("a").unwrap();
+        store.apply(batch).unwrap();
+        // Refused writes run no hooks.
+        assert!(store.clone().rename("a", "c", false).is_err());
+        store.compact().unwrap();
+        assert_eq!(
+            vec!["set a 1", "set b 2", "unset a", "compact"],
+            events.try_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn hooks_order() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let (set, unset) = (calls.clone(), calls.clone());
+        let store = Store::<u32>::options()
+            .on_set(move |key, value| set.lock().push(format!("set {key} {value}")))
+            .on_unset(move |key| unset.lock().push(format!("unset {key}")))
+            .open_in_memory();
+        let mut batch = WriteBatch::new();
+        batch.unset("a").unwrap();
+        batch.set("a", &1).unwrap();
+        store.apply(batch).unwrap();
+        assert_eq!(vec!["unset a", "set a 1"], *calls.lock());
+    }
+
+    #[test]
+    fn increment() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u8>::open(f.path()).unwrap();
+        assert_eq!(5, store.increment("a", 5).unwrap());
+        assert_eq!(2, store.increment("a", -3).unwrap());
+        assert!(store.increment("a", -3).is_err());
+        assert!(store.increment("a", 254).is_err());
+        assert_eq!(Some(2), store.get("a").unwrap());
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                let store = store.clone();
+                scope.spawn(move || {
+                    for _ in 0..10 {
+                        store.increment("b", 1).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(Some(40), store.get("b").unwrap());
+
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<serde_json::Value>::open(f.path()).unwrap();
+        store.set("s", &"text".into()).unwrap();
+        assert!(store.increment("s", 1).is_err());
+    }
+
+    #[test]
+    fn push() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<Vec<u32>>::open(f.path()).unwrap();
+        store.push("a", 1).unwrap();
+        store.push("a", 2).unwrap();
+        assert_eq!(Some(vec![1, 2]), store.get("a").unwrap());
+
+        std::thread::scope(|scope| {
+            for i in 0..4 {
+                let store = store.clone();
+                scope.spawn(move || store.push("b", i).unwrap());
+            }
+        });
+        let mut b = store.get("b").unwrap().unwrap();
+        b.sort();
+        assert_eq!(vec![0, 1, 2, 3], b);
+    }
+
+    #[test]
+    fn len() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u32>::open(f.path()).unwrap();
+        assert!(store.is_empty());
+        store.set("a", &1).unwrap();
+        store.set("a", &2).unwrap();
+        store.set("b", &3).unwrap();
+        store
+            .set_with_ttl("c", &4, Duration::from_millis(0))
+            .unwrap();
+        store.unset("b").unwrap();
+        assert_eq!(1, store.len());
+        assert!(!store.is_empty());
+    }
+
+    #[test]
+    fn stats() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u32>::open(f.path()).unwrap();
+        store.set("a", &1).unwrap();
+        store.set("a", &2).unwrap();
+        store.set("b", &3).unwrap();
+        store.unset("b").unwrap();
+        store.flush().unwrap();
+
+        let stats = store.stats();
+        assert_eq!(4, stats.records);
+        assert_eq!(1, stats.live_keys);
+        assert_eq!(1, stats.tombstones);
+        assert_eq!(0.75, stats.dead_ratio);
+        assert_eq!(std::fs::metadata(f.path()).unwrap().len(), stats.size);
+
+        drop(store);
+        let store = Store::<u32>::open(f.path()).unwrap();
+        assert_eq!(stats, store.stats());
+        store.compact().unwrap();
+        assert_eq!(0, store.stats().tombstones);
+        assert_eq!(0.0, store.stats().dead_ratio);
+    }
+
+    #[test]
+    fn metadata() {
+        let f = NamedTempFile::new().unwrap();
+        let before = SystemTime::now() - Duration::from_millis(1);
+        let store = Store::<u32>::open(f.path()).unwrap();
+        store.set("a", &1).unwrap();
+        store
+            .set_with_ttl("b", &2, Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(None, store.metadata("c").unwrap());
+
+        let a = store.metadata("a").unwrap().unwrap();
+        let modified = a.modified.unwrap();
+        assert!(before <= modified && modified <= SystemTime::now());
+        assert_eq!(None, a.expires);
+        assert_eq!((1, 1, 0), (a.size, a.versions, a.segment));
+        let b = store.metadata("b").unwrap().unwrap();
+        assert!(b.modified.unwrap() >= modified);
+        assert!(b.expires.unwrap() > b.modified.unwrap());
+        assert!(b.offset > a.offset);
+
+        store.set("a", &10).unwrap();
+        store.unset("b").unwrap();
+        store.set("b", &2).unwrap();
+        let a = store.metadata("a").unwrap().unwrap();
+        assert_eq!((2, 2), (a.size, a.versions));
+        assert_eq!(3, store.metadata("b").unwrap().unwrap().versions);
+        assert_eq!(
+            vec![("a".to_string(), 2), ("b".to_string(), 1)],
+            store.value_sizes().unwrap()
+        );
+
+        // Timestamps survive compaction, and legacy records simply have none.
+        store.compact().unwrap();
+        let compacted = store.metadata("a").unwrap().unwrap();
+        assert_eq!(
+            (a.modified, a.expires),
+            (compacted.modified, compacted.expires)
+        );
+        assert_eq!(1, compacted.versions);
+        drop(store);
+        std::fs::write(f.path(), "a,1\n").unwrap();
+        let store = Store::<u32>::open(f.path()).unwrap();
+        assert_eq!(None, store.metadata("a").unwrap().unwrap().modified);
+    }
+
+    #[test]
+    fn for_each() {
+        let store = Store::<u32>::in_memory();
+        store.set("b", &1).unwrap();
+        store.set("a", &2).unwrap();
+        store.set("b", &3).unwrap();
+        store.set("c", &4).unwrap();
+        store.unset("c").unwrap();
+
+        let mut visited = Vec::new();
+        store
+            .for_each(|key, value| visited.push((key.to_string(), value)))
+            .unwrap();
+        assert_eq!(vec![("a".to_string(), 2), ("b".to_string(), 3)], visited);
+        assert_eq!(
+            BTreeMap::from_iter(visited.clone()),
+            store.load_sorted().unwrap()
+        );
+        assert_eq!(FxHashMap::from_iter(visited), store.load_map().unwrap());
+    }
+
+    #[test]
+    fn raw_json() {
+        use serde_json::{json, Value};
+
+        let store = Store::<Value>::in_memory();
+        store.set("a", &json!({ "b": [1, "c"] })).unwrap();
+        store.set("gone", &json!(1)).unwrap();
+        store.unset("gone").unwrap();
+        assert_eq!(
+            r#"{"b":[1,"c"]}"#,
+            store.get_raw("a").unwrap().unwrap().get()
+        );
+        assert!(store.get_raw("gone").unwrap().is_none());
+        let map = store.load_map_raw().unwrap();
+        assert_eq!(vec!["a"], map.keys().collect::<Vec<_>>());
+        assert_eq!(r#"{"b":[1,"c"]}"#, map["a"].get());
+    }
+
+    #[test]
+    fn history() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u32>::open(f.path()).unwrap();
+        store.set("a", &1).unwrap();
+        store.set("b", &2).unwrap();
+        store.unset("a").unwrap();
+        store.set("a", &3).unwrap();
+        assert_eq!(vec![Some(1), None, Some(3)], store.history("a").unwrap());
+        assert!(store.history("c").unwrap().is_empty());
+
+        store.compact().unwrap();
+        assert_eq!(vec![Some(3)], store.history("a").unwrap());
+    }
+
+    #[test]
+    fn cache() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u32>::options()
+            .cache(CacheConfig { max_entries: 1 })
+            .open(f.path())
+            .unwrap();
+        store.set("a", &1).unwrap();
+        store.set("b", &2).unwrap();
+        store.flush().unwrap();
+        assert_eq!(Some(1), store.get("a").unwrap());
+
+        // A cached value is served without reading the file, which gets truncated to prove it.
+        let len = std::fs::metadata(f.path()).unwrap().len();
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(f.path())
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+        assert_eq!(Some(1), store.get("a").unwrap());
+        assert!(store.get("b").is_err());
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(f.path())
+            .unwrap()
+            .set_len(len)
+            .unwrap();
+
+        store.set("a", &3).unwrap();
+        assert_eq!(Some(3), store.get("a").unwrap());
+        store.unset("a").unwrap();
+        assert_eq!(None, store.get("a").unwrap());
+    }
+
+    #[test]
+    fn locked() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u8>::open(f.path()).unwrap();
+        let owner = Some(std::process::id());
+        assert!(
+            matches!(Store::<u8>::open(f.path()), Err(Error::Locked { owner: o }) if o == owner)
+        );
+
+        // The lock must carry over to the file written by the compaction.
+        store.set("a", &1).unwrap();
+        store.compact().unwrap();
+        assert!(matches!(
+            Store::<u8>::open(f.path()),
+            Err(Error::Locked { .. })
+        ));
+
+        drop(store);
+        // The record left behind by a previous owner doesn't prevent taking over.
+        std::fs::write(lock_path(f.path()), "pid=1\n").unwrap();
+        let store = Store::<u8>::open(f.path()).unwrap();
+        assert_eq!(Some(1), store.get("a").unwrap());
+        assert_eq!(owner, read_owner(f.path()));
+        drop(store);
+        assert!(!lock_path(f.path()).exists());
+    }
+
+    #[test]
+    fn arbitrary_keys() {
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u8>::open(f.path()).unwrap();
+        let keys = [
+            "",
+            "this,is,a,key",
+            "line\nbreak\ttab",
+            "100%",
+            "user@example.com",
+            "https://example.com/?a=b&c=d",
+            "héllo wörld 🦀",
+        ];
+        for (i, key) in keys.iter().enumerate() {
+            store.set(key, &(i as u8)).unwrap();
+        }
+        drop(store);
+
+        assert_eq!(keys.len(), records(f.path()).len());
+        let store = Store::<u8>::open(f.path()).unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(Some(i as u8), store.get(key).unwrap());
+        }
+        let mut expected: Vec<_> = keys.iter().map(|k| k.to_string()).collect();
+        expected.sort();
+        assert_eq!(expected, store.keys().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn custom_codec() {
+        /// Stores integers as raw little-endian bytes.
+        struct Le;
+
+        impl Codec for Le {
+            fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+                let value: u32 = serde_json::from_value(serde_json::to_value(value).unwrap())
+                    .map_err(encode_err)?;
+                Ok(value.to_le_bytes().to_vec())
+            }
+
+            fn decode<T: for<'a> Deserialize<'a>>(bytes: &[u8]) -> Result<T, Error> {
+                let bytes = bytes.try_into().map_err(decode_err)?;
+                serde_json::from_value(u32::from_le_bytes(bytes).into()).map_err(decode_err)
+            }
+        }
+
+        let f = NamedTempFile::new().unwrap();
+        let store = Store::<u32, Le>::open(f.path()).unwrap();
+        store.set("a", &0x0a09_00ff).unwrap();
+        store.set("b", &7).unwrap();
+        store.unset("b").unwrap();
+        drop(store);
+
+        let store = Store::<u32, Le>::open(f.path()).unwrap();
+        assert_eq!(Some(0x0a09_00ff), store.get("a").unwrap());
+        assert_eq!(None, store.get("b").unwrap());
+        assert_eq!(
+            vec![("a".to_string(), 0x0a09_00ff)],
+            store.iter().collect::<Result<Vec<_>, _>>().unwrap()
+        );
+        assert!(records(f.path())[0].starts_with("a,/wAJCg=="));
     }
 }
@@ -0,0 +1,91 @@
+//! Enforcement of the maximum size of a database, as set with
+//! [`OpenOptions::max_file_size`](crate::OpenOptions::max_file_size).
+
+use crate::record::{Record, TOMBSTONE};
+use crate::{Error, FullPolicy, StoreInner};
+
+impl StoreInner {
+    /// Makes room for a write of `len` bytes under the maximum size of the database, as far as its
+    /// [`FullPolicy`] allows, failing with [`Error::Full`] if there still isn't enough.
+    pub(crate) fn make_room(&mut self, len: usize) -> Result<(), Error> {
+        let Some((max_size, policy)) = self.max_size else {
+            return Ok(());
+        };
+        let fits = |inner: &Self| {
+            // Starting a new segment also writes its header.
+            let header = if inner.is_full(inner.active().len, len) {
+                inner.header.len()
+            } else {
+                0
+            };
+            inner.size() + (len + header) as u64 <= max_size
+        };
+        if fits(self) {
+            return Ok(());
+        }
+        // Not even an empty database would have room for it.
+        if (len + self.header.len()) as u64 > max_size {
+            return Err(Error::Full);
+        }
+        if policy != FullPolicy::Fail {
+            self.compact()?;
+        }
+        if policy == FullPolicy::EvictOldest && !fits(self) {
+            let excess = (self.size() + len as u64).saturating_sub(max_size);
+            self.evict_oldest(excess)?;
+        }
+        if fits(self) {
+            Ok(())
+        } else {
+            Err(Error::Full)
+        }
+    }
+
+    /// Unsets the keys written the longest ago, until their records add up to at least `excess`
+    /// bytes, by compacting the database without them, so that no tombstones are written and
+    /// nothing is unset if the compaction fails.
+    ///
+    /// Watchers and hooks are still told of the evictions, as of any other unset.
+    fn evict_oldest(&mut self, excess: u64) -> Result<(), Error> {
+        let mut line = Vec::new();
+        let mut live = self.scan(|location, record, live: &mut Vec<_>| {
+            if self.is_latest(location, &record.key) {
+                line.clear();
+                self.encode(&record, &mut line)?;
+                let written_at = record.written_at.unwrap_or(0);
+                live.push((
+                    written_at,
+                    location,
+                    line.len() as u64,
+                    record.key.to_string(),
+                ));
+            }
+            Ok(())
+        })?;
+        live.sort_unstable();
+
+        let mut freed = 0;
+        let mut evicted = Vec::new();
+        for (_, _, len, key) in live {
+            if freed >= excess {
+                break;
+            }
+            freed += len;
+            evicted.push(key);
+        }
+        self.compact_without(&evicted)?;
+
+        for key in evicted {
+            if let Some(cache) = &self.cache {
+                cache.lock().pop(&key);
+            }
+            if let Some(recency) = &self.recency {
+                recency.forget(&key);
+            }
+            let tombstone = Record::new(&key, TOMBSTONE);
+            self.watchers.retain_mut(|watcher| watcher(&tombstone));
+            self.hooks.written(&[tombstone]);
+        }
+        Ok(())
+    }
+}
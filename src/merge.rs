@@ -0,0 +1,98 @@
+use std::sync::Arc;
+
+use crate::record::OwnedRecord;
+use crate::{Error, Store};
+
+/// Which value is kept when merging a key which both databases hold.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Prefer {
+    /// The value of the database being merged in.
+    #[default]
+    Source,
+    /// The value already in the database being merged into.
+    Destination,
+    /// Whichever value was written last, according to the timestamps of the records. Values
+    /// written before timestamps were recorded lose to those which have one.
+    Newest,
+}
+
+impl<T, C> Store<T, C> {
+    /// Copies the live entries of another database into this one, and returns how many keys were
+    /// written.
+    ///
+    /// Values are copied as they are encoded, along with their expiration and timestamp, without
+    /// being deserialized, and those held in blobs are read from them. Keys which only this
+    /// database holds are left alone, while those held by both are settled by `prefer`. The
+    /// entries are appended with a single write.
+    pub fn merge_from(&self, other: &Store<T, C>, prefer: Prefer) -> Result<usize, Error> {
+        if Arc::ptr_eq(&self.0, &other.0) {
+            return Ok(0);
+        }
+        // Read before taking the write lock, so that two stores merging into each other can't
+        // deadlock.
+        let live = {
+            let other = other.read();
+            other
+                .live_records()?
+                .into_values()
+                .map(|record| other.load(record.as_record()).map(OwnedRecord::from))
+                .collect::<Result<Vec<_>, Error>>()?
+        };
+
+        let mut inner = self.0.write();
+        let mut records = Vec::new();
+        for record in &live {
+            let keep = match (prefer, inner.slot(&record.key)) {
+                (_, None) | (Prefer::Source, _) => true,
+                (Prefer::Destination, Some(_)) => false,
+                (Prefer::Newest, Some(slot)) => {
+                    record.written_at > inner.read_record(slot.location)?.written_at
+                }
+            };
+            if keep {
+                records.push(record.as_record());
+            }
+        }
+        if !records.is_empty() {
+            inner.append(&records)?;
+        }
+        Ok(records.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_from() {
+        let source = Store::<u32>::in_memory();
+        source.set("a", &1).unwrap();
+        source.set("b", &2).unwrap();
+        source.set("gone", &3).unwrap();
+        source.unset("gone").unwrap();
+
+        let dest = Store::<u32>::in_memory();
+        dest.set("b", &20).unwrap();
+        dest.set("c", &30).unwrap();
+        assert_eq!(1, dest.merge_from(&source, Prefer::Destination).unwrap());
+        assert_eq!(Some(1), dest.get("a").unwrap());
+        assert_eq!(Some(20), dest.get("b").unwrap());
+
+        assert_eq!(2, dest.merge_from(&source, Prefer::Source).unwrap());
+        assert_eq!(Some(2), dest.get("b").unwrap());
+        assert_eq!(Some(30), dest.get("c").unwrap());
+        assert_eq!(None, dest.get("gone").unwrap());
+        assert_eq!(0, dest.merge_from(&dest.clone(), Prefer::Source).unwrap());
+
+        // The timestamps are copied, so the newer side wins whichever way the merge goes.
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        dest.set("a", &10).unwrap();
+        source.set("c", &3).unwrap();
+        assert_eq!(1, source.merge_from(&dest, Prefer::Newest).unwrap());
+        assert_eq!(Some(10), source.get("a").unwrap());
+        assert_eq!(Some(3), source.get("c").unwrap());
+        assert_eq!(1, dest.merge_from(&source, Prefer::Newest).unwrap());
+        assert_eq!(Some(3), dest.get("c").unwrap());
+    }
+}
@@ -0,0 +1,22 @@
+use std::time::SystemTime;
+
+/// What is known about the current value of a key, as returned by
+/// [`Store::metadata`](crate::Store::metadata).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecordMeta {
+    /// When the value was written, unless it was by a version of kv which didn't record it.
+    pub modified: Option<SystemTime>,
+    /// When the key expires, if it was set with a time to live.
+    pub expires: Option<SystemTime>,
+    /// Length of the value, as encoded by the codec of the store.
+    pub size: usize,
+    /// Number of records of the key still in the database, including the current one and those
+    /// which unset it. Overwritten records are only kept until the next compaction or until their
+    /// segment gets deleted.
+    pub versions: usize,
+    /// Id of the segment holding the current value, which is always `0` for databases which
+    /// aren't segmented.
+    pub segment: u32,
+    /// Offset of the record of the current value in its segment.
+    pub offset: u64,
+}
@@ -0,0 +1,228 @@
+//! Counters of the operations of a store, kept with the `metrics` feature and exported through
+//! [`Store::metrics_snapshot`].
+
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::{Instant, Stats, Store};
+
+/// Operations of a [`Store`] since it was opened, as returned by [`Store::metrics_snapshot`].
+///
+/// Counters only ever grow, and are shared by all the clones of the store.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Metrics {
+    /// Number of lookups of a key, whether it held a value or not.
+    pub reads: u64,
+    /// Number of lookups which found no value.
+    pub misses: u64,
+    /// Total time spent in lookups.
+    pub read_time: Duration,
+    /// Number of writes, each of which holds one or more records, as batches do.
+    pub writes: u64,
+    /// Number of records written, tombstones included.
+    pub records_written: u64,
+    /// Number of bytes of records written.
+    pub bytes_written: u64,
+    /// Total time spent in writes.
+    pub write_time: Duration,
+    /// Number of compactions, whether requested or automatic.
+    pub compactions: u64,
+    /// Total time spent compacting.
+    pub compaction_time: Duration,
+    /// Current state of the database, including its dead ratio.
+    pub stats: Stats,
+}
+
+impl Metrics {
+    /// Renders the metrics in the Prometheus text exposition format, with names starting with
+    /// `kv_`.
+    pub fn prometheus(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: f64| {
+            let _ = writeln!(out, "# HELP kv_{name} {help}");
+            let _ = writeln!(out, "# TYPE kv_{name} {kind}");
+            let _ = writeln!(out, "kv_{name} {value}");
+        };
+        let reads = self.reads as f64;
+        metric("reads_total", "counter", "Lookups of a key.", reads);
+        let misses = self.misses as f64;
+        metric(
+            "misses_total",
+            "counter",
+            "Lookups which found no value.",
+            misses,
+        );
+        let read_time = self.read_time.as_secs_f64();
+        metric(
+            "read_seconds_total",
+            "counter",
+            "Time spent in lookups.",
+            read_time,
+        );
+        metric("writes_total", "counter", "Writes.", self.writes as f64);
+        let records = self.records_written as f64;
+        metric(
+            "records_written_total",
+            "counter",
+            "Records written.",
+            records,
+        );
+        let bytes = self.bytes_written as f64;
+        metric(
+            "bytes_written_total",
+            "counter",
+            "Bytes of records written.",
+            bytes,
+        );
+        let write_time = self.write_time.as_secs_f64();
+        metric(
+            "write_seconds_total",
+            "counter",
+            "Time spent in writes.",
+            write_time,
+        );
+        let compactions = self.compactions as f64;
+        metric("compactions_total", "counter", "Compactions.", compactions);
+        let compaction_time = self.compaction_time.as_secs_f64();
+        metric(
+            "compaction_seconds_total",
+            "counter",
+            "Time spent compacting.",
+            compaction_time,
+        );
+        let size = self.stats.size as f64;
+        metric("size_bytes", "gauge", "Size of the database.", size);
+        let live_keys = self.stats.live_keys as f64;
+        metric("live_keys", "gauge", "Keys holding a value.", live_keys);
+        let dead_ratio = self.stats.dead_ratio;
+        metric(
+            "dead_ratio",
+            "gauge",
+            "Fraction of the records which are dead.",
+            dead_ratio,
+        );
+        out
+    }
+}
+
+/// Counters behind [`Metrics`], updated through a shared reference since reads only take a read
+/// lock on the store.
+#[derive(Default)]
+pub(crate) struct Counters {
+    reads: AtomicU64,
+    misses: AtomicU64,
+    read_nanos: AtomicU64,
+    writes: AtomicU64,
+    records_written: AtomicU64,
+    bytes_written: AtomicU64,
+    write_nanos: AtomicU64,
+    compactions: AtomicU64,
+    compaction_nanos: AtomicU64,
+}
+
+impl Counters {
+    /// Counts a lookup, timed until the returned guard is dropped.
+    pub fn read(&self) -> Timer<'_> {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        Timer::new(&self.read_nanos)
+    }
+
+    pub fn miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a write of the given number of records and bytes, timed until the returned guard is
+    /// dropped.
+    pub fn write(&self, records: usize, bytes: usize) -> Timer<'_> {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        self.records_written
+            .fetch_add(records as u64, Ordering::Relaxed);
+        self.bytes_written
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        Timer::new(&self.write_nanos)
+    }
+
+    /// Counts a compaction, timed until the returned guard is dropped.
+    pub fn compaction(&self) -> Timer<'_> {
+        self.compactions.fetch_add(1, Ordering::Relaxed);
+        Timer::new(&self.compaction_nanos)
+    }
+}
+
+/// Adds the time elapsed since its creation to a counter when dropped.
+pub(crate) struct Timer<'a> {
+    nanos: &'a AtomicU64,
+    start: Instant,
+}
+
+impl<'a> Timer<'a> {
+    fn new(nanos: &'a AtomicU64) -> Self {
+        Self {
+            nanos,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Timer<'_> {
+    fn drop(&mut self) {
+        let elapsed = u64::try_from(self.start.elapsed().as_nanos()).unwrap_or(u64::MAX);
+        self.nanos.fetch_add(elapsed, Ordering::Relaxed);
+    }
+}
+
+impl<T, C> Store<T, C> {
+    /// Returns the operations performed through the store since it was opened, along with its
+    /// current [`Stats`], for export to a monitoring system.
+    pub fn metrics_snapshot(&self) -> Metrics {
+        let stats = self.stats();
+        let inner = self.read();
+        let counters = &inner.metrics;
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let time = |nanos: &AtomicU64| Duration::from_nanos(load(nanos));
+        Metrics {
+            reads: load(&counters.reads),
+            misses: load(&counters.misses),
+            read_time: time(&counters.read_nanos),
+            writes: load(&counters.writes),
+            records_written: load(&counters.records_written),
+            bytes_written: load(&counters.bytes_written),
+            write_time: time(&counters.write_nanos),
+            compactions: load(&counters.compactions),
+            compaction_time: time(&counters.compaction_nanos),
+            stats,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics() {
+        let store = Store::<u32>::in_memory();
+        store.set("a", &1).unwrap();
+        store.set("a", &2).unwrap();
+        store.unset("a").unwrap();
+        assert_eq!(None, store.get("a").unwrap());
+        store.set("b", &3).unwrap();
+        assert_eq!(Some(3), store.get("b").unwrap());
+        store.compact().unwrap();
+
+        let metrics = store.clone().metrics_snapshot();
+        assert_eq!(2, metrics.reads);
+        assert_eq!(1, metrics.misses);
+        assert_eq!(4, metrics.writes);
+        assert_eq!(4, metrics.records_written);
+        assert!(metrics.bytes_written > 0);
+        assert!(metrics.write_time > Duration::ZERO);
+        assert_eq!(1, metrics.compactions);
+        assert_eq!(1, metrics.stats.live_keys);
+
+        let exported = metrics.prometheus();
+        assert!(exported.contains("# TYPE kv_reads_total counter\nkv_reads_total 2\n"));
+        assert!(exported.contains("\nkv_dead_ratio 0\n"));
+    }
+}
@@ -0,0 +1,142 @@
+//! Migrations of values between versions of their schema.
+//!
+//! Once a migration is registered, records are written with the version of the schema their value
+//! follows, which is the latest version any migration leads to. Records from earlier versions are
+//! migrated as they are read, and rewritten at the latest version by compaction. Records written
+//! before any migration was registered are taken to be at version 1.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use crate::record::Record;
+use crate::{Codec, Error, Store};
+
+/// Turns the encoded value of a record into that of the next version.
+type Migrate = Box<dyn Fn(&[u8]) -> Result<Vec<u8>, Error> + Send + Sync>;
+
+/// Version of the records written before any migration was registered.
+const INITIAL_VERSION: u32 = 1;
+
+#[derive(Default)]
+pub(crate) struct Migrations {
+    /// Registered migrations, keyed by the version they start from, with the one they lead to.
+    steps: BTreeMap<u32, (u32, Migrate)>,
+}
+
+impl Migrations {
+    /// Version with which records are written, or `None` if no migration was registered.
+    pub fn current(&self) -> Option<u32> {
+        self.steps.values().map(|(to, _)| *to).max()
+    }
+
+    /// Migrates the value of the record to the current version, one migration after the other.
+    pub fn migrate<'a>(&self, mut record: Record<'a>) -> Result<Record<'a>, Error> {
+        let Some(current) = self.current() else {
+            return Ok(record);
+        };
+        // Values held in blobs are only migrated once read from them.
+        if record.is_tombstone() || record.blob {
+            return Ok(record);
+        }
+        let mut version = record.version.unwrap_or(INITIAL_VERSION);
+        while version < current {
+            let Some((to, migrate)) = self.steps.get(&version) else {
+                return Err(Error::Read(format!(
+                    "No migration from version {version} of the value of `{}`",
+                    record.key
+                )));
+            };
+            record.value = Cow::Owned(migrate(&record.value)?);
+            version = *to;
+        }
+        record.version = Some(version);
+        Ok(record)
+    }
+}
+
+impl<T, C: Codec + 'static> Store<T, C> {
+    /// Registers a migration of values from version `from` of their schema to version `to`, as
+    /// done by the given function on their JSON representation, whatever the codec is.
+    ///
+    /// Values are migrated as they are read, going through as many migrations as needed to reach
+    /// the latest version, and are only rewritten by the next compaction. New records are written
+    /// with the latest version, and those written before any migration was registered are taken
+    /// to be at version 1. Migrations should be registered right after opening the store, before
+    /// anything is written.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `to` isn't greater than `from`.
+    pub fn register_migration<F>(&self, from: u32, to: u32, migrate: F)
+    where
+        F: Fn(Value) -> Value + Send + Sync + 'static,
+    {
+        assert!(from < to, "migrations must lead to a later version");
+        let migrate: Migrate = Box::new(move |value| C::encode(&migrate(C::decode(value)?)));
+        let mut inner = self.0.write();
+        inner.migrations.steps.insert(from, (to, migrate));
+        // Values read before would skip the new migration.
+        if let Some(cache) = &inner.cache {
+            cache.lock().clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::Format;
+
+    #[test]
+    fn migrate() {
+        for format in [Format::Text, Format::Binary] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("db");
+            {
+                let store = Store::<Value>::options()
+                    .format(format)
+                    .open(&path)
+                    .unwrap();
+                store.set("ada", &json!("Ada Lovelace")).unwrap();
+                store.set("gone", &json!("Alan Turing")).unwrap();
+                store.unset("gone").unwrap();
+            }
+
+            // Names were first stored as a single string, then split, then given an age.
+            let store = Store::<(String, String, u32)>::options()
+                .format(format)
+                .open(&path)
+                .unwrap();
+            assert!(store.get("ada").is_err());
+            store.register_migration(2, 3, |name| json!([name[0], name[1], 36]));
+            assert!(matches!(store.get("ada"), Err(Error::Read(_))));
+            store.register_migration(1, 2, |name| {
+                let name = name.as_str().unwrap_or_default();
+                let (first, last) = name.split_once(' ').unwrap_or((name, ""));
+                json!([first, last])
+            });
+
+            let ada = ("Ada".to_string(), "Lovelace".to_string(), 36);
+            assert_eq!(Some(&ada), store.get("ada").unwrap().as_ref());
+            assert_eq!(None, store.get("gone").unwrap());
+            let grace = ("Grace".to_string(), "Hopper".to_string(), 85);
+            store.set("grace", &grace).unwrap();
+            assert_eq!(Some(&grace), store.get("grace").unwrap().as_ref());
+
+            // Compaction rewrites the values at the latest version, which no longer need the
+            // migrations to be read.
+            store.compact().unwrap();
+            drop(store);
+            let store = Store::<(String, String, u32)>::options()
+                .format(format)
+                .open(&path)
+                .unwrap();
+            assert_eq!(Some(ada), store.get("ada").unwrap());
+            assert_eq!(Some(grace), store.get("grace").unwrap());
+        }
+    }
+}
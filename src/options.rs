@@ -0,0 +1,526 @@
+use std::borrow::Cow;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::crypto::Cipher;
+use crate::record::encode_key;
+use crate::watch::Hooks;
+use crate::{Backend, Codec, Error, JsonCodec, Store};
+
+/// When writes are flushed to the underlying storage device.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Leave it to the operating system. Writes can be lost on power failure.
+    #[default]
+    Never,
+    /// Sync after every write, before it returns.
+    EveryWrite,
+    /// Sync after a write if the last sync is older than the given duration.
+    Interval(Duration),
+}
+
+/// How to handle corrupt records found while opening a database.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RecoveryPolicy {
+    /// Refuse to open the database.
+    #[default]
+    Strict,
+    /// Truncate a partially written record at the end of the file, as left behind by a crash
+    /// mid-write, but refuse to open the database if any other record is corrupt.
+    TruncateTail,
+    /// Truncate a partially written trailing record and ignore corrupt records anywhere else.
+    SkipCorrupt,
+    /// Like [`RecoveryPolicy::SkipCorrupt`], but also copy the corrupt records to a `.corrupt`
+    /// file next to the database for later inspection.
+    Quarantine,
+}
+
+/// How records are laid out in the database file.
+///
+/// The format only applies to new databases: existing ones are always opened in the format they
+/// were created with, which is detected from the start of the file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// One line of text per record, which can be inspected and edited with standard tools.
+    #[default]
+    Text,
+    /// Length-prefixed binary records following a magic header. Values are stored as-is rather than
+    /// in base64, and reading a record doesn't involve searching for its end.
+    Binary,
+    /// One JSON object per line, such as `{"k":"key","v":1,"ts":1690000000000}`, which tools like
+    /// `jq` can process line by line. More verbose than [`Format::Text`].
+    Ndjson,
+}
+
+/// Whether values are compressed before being written.
+///
+/// Values which are compressed are flagged as such, so the setting can be changed at any time:
+/// existing records are read regardless, and get rewritten according to the new setting on the
+/// next compaction.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Store values as-is.
+    #[default]
+    None,
+    /// Compress the values of at least `threshold` bytes with zstd at the given level, unless doing
+    /// so doesn't make them any smaller.
+    #[cfg(feature = "zstd")]
+    Zstd { threshold: usize, level: i32 },
+}
+
+/// What a write does when it would take the database past the size set with
+/// [`OpenOptions::max_file_size`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FullPolicy {
+    /// Fail with [`Error::Full`] without writing anything.
+    #[default]
+    Fail,
+    /// Compact the database first, and only fail if it's still too large.
+    Compact,
+    /// Compact the database first, then unset the keys written the longest ago until there is
+    /// room. Only fails if the write is larger than the whole database can be.
+    EvictOldest,
+}
+
+/// Which keys can be set, as checked by every write.
+///
+/// Any key can be stored, with the characters which would break the text format percent-encoded,
+/// so policies only exist to keep keys tidy. Keys can always be unset, so that those written under
+/// another policy can be cleaned up.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum KeyPolicy {
+    /// Accept any key, escaping the characters which need it.
+    #[default]
+    Escaped,
+    /// Only accept non-empty keys which are written as-is, without `%`, `,` or control characters,
+    /// so that the database can be processed by tools which know nothing of the escaping.
+    Strict,
+    /// Only accept the keys for which the function returns `true`.
+    Custom(fn(&str) -> bool),
+}
+
+impl KeyPolicy {
+    pub(crate) fn allows(self, key: &str) -> bool {
+        match self {
+            Self::Escaped => true,
+            Self::Strict => !key.is_empty() && matches!(encode_key(key), Cow::Borrowed(_)),
+            Self::Custom(allows) => allows(key),
+        }
+    }
+}
+
+/// Settings of the in-memory cache of recently read values.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// Number of values past which the least recently used ones get evicted. No cache is kept if
+    /// this is `0`.
+    pub max_entries: usize,
+}
+
+/// When a store compacts the database on its own, as set with [`OpenOptions::compaction_policy`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CompactionPolicy {
+    /// Fraction of the records which don't hold the current value of a key, as reported by
+    /// [`Stats::dead_ratio`](crate::Stats::dead_ratio), past which the database is compacted.
+    pub max_dead_ratio: f64,
+    /// Size in bytes below which the database is left alone whatever its dead ratio, since
+    /// compacting small databases reclaims little.
+    pub min_file_size: u64,
+}
+
+impl Default for CompactionPolicy {
+    fn default() -> Self {
+        Self {
+            max_dead_ratio: 0.5,
+            min_file_size: 1024 * 1024,
+        }
+    }
+}
+
+impl RecoveryPolicy {
+    pub(crate) fn skips_corrupt(self) -> bool {
+        matches!(self, Self::SkipCorrupt | Self::Quarantine)
+    }
+}
+
+/// Options for opening a [`Store`], obtained through [`Store::options`].
+pub struct OpenOptions<T, C = JsonCodec> {
+    pub(crate) sync: SyncPolicy,
+    pub(crate) recovery: RecoveryPolicy,
+    pub(crate) format: Format,
+    pub(crate) compression: Compression,
+    pub(crate) segment_size: Option<u64>,
+    pub(crate) cache: CacheConfig,
+    pub(crate) write_buffer: usize,
+    pub(crate) compaction: Option<CompactionPolicy>,
+    pub(crate) max_file_size: Option<(u64, FullPolicy)>,
+    pub(crate) max_live_keys: Option<usize>,
+    pub(crate) blob_threshold: Option<usize>,
+    pub(crate) sweep_interval: Option<Duration>,
+    pub(crate) key_policy: KeyPolicy,
+    pub(crate) max_value_size: Option<usize>,
+    pub(crate) read_only: bool,
+    pub(crate) create: bool,
+    pub(crate) type_tag: Option<String>,
+    pub(crate) hooks: Hooks,
+    #[cfg(feature = "encryption")]
+    encryption_key: Option<[u8; 32]>,
+    _phantom: PhantomData<fn() -> (T, C)>,
+}
+
+impl<T, C> Clone for OpenOptions<T, C> {
+    fn clone(&self) -> Self {
+        Self {
+            sync: self.sync,
+            recovery: self.recovery,
+            format: self.format,
+            compression: self.compression,
+            segment_size: self.segment_size,
+            cache: self.cache,
+            write_buffer: self.write_buffer,
+            compaction: self.compaction,
+            max_file_size: self.max_file_size,
+            max_live_keys: self.max_live_keys,
+            blob_threshold: self.blob_threshold,
+            sweep_interval: self.sweep_interval,
+            key_policy: self.key_policy,
+            max_value_size: self.max_value_size,
+            read_only: self.read_only,
+            create: self.create,
+            type_tag: self.type_tag.clone(),
+            hooks: self.hooks.clone(),
+            #[cfg(feature = "encryption")]
+            encryption_key: self.encryption_key,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T, C> Default for OpenOptions<T, C> {
+    fn default() -> Self {
+        Self {
+            sync: SyncPolicy::default(),
+            recovery: RecoveryPolicy::default(),
+            format: Format::default(),
+            compression: Compression::default(),
+            segment_size: None,
+            cache: CacheConfig::default(),
+            write_buffer: 8 * 1024,
+            compaction: None,
+            max_file_size: None,
+            max_live_keys: None,
+            blob_threshold: None,
+            sweep_interval: None,
+            key_policy: KeyPolicy::default(),
+            max_value_size: None,
+            read_only: false,
+            create: true,
+            type_tag: None,
+            hooks: Hooks::default(),
+            #[cfg(feature = "encryption")]
+            encryption_key: None,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T, C> OpenOptions<T, C> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets when writes are synced to disk. Defaults to [`SyncPolicy::Never`].
+    pub fn sync(&mut self, policy: SyncPolicy) -> &mut Self {
+        self.sync = policy;
+        self
+    }
+
+    /// Sets how corrupt records are handled. Defaults to [`RecoveryPolicy::Strict`].
+    pub fn recovery(&mut self, policy: RecoveryPolicy) -> &mut Self {
+        self.recovery = policy;
+        self
+    }
+
+    /// Sets which keys can be set, with writes of other keys failing with [`Error::InvalidKey`].
+    /// Defaults to [`KeyPolicy::Escaped`], which accepts any key.
+    pub fn key_policy(&mut self, policy: KeyPolicy) -> &mut Self {
+        self.key_policy = policy;
+        self
+    }
+
+    /// Caps the length in bytes of values as encoded by the codec, with writes of longer ones
+    /// failing with [`Error::ValueTooLarge`](crate::Error::ValueTooLarge) before anything is
+    /// written. Defaults to no limit.
+    pub fn max_value_size(&mut self, max: usize) -> &mut Self {
+        self.max_value_size = Some(max);
+        self
+    }
+
+    /// Sets the format in which to create the database if it doesn't exist yet. Defaults to
+    /// [`Format::Text`].
+    pub fn format(&mut self, format: Format) -> &mut Self {
+        self.format = format;
+        self
+    }
+
+    /// Sets whether values are compressed. Defaults to [`Compression::None`].
+    pub fn compression(&mut self, compression: Compression) -> &mut Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Splits the database into segments of roughly the given size in bytes, rather than keeping it
+    /// in a single file. See [`Store::compact`] for how segments are reclaimed.
+    ///
+    /// Databases which already exist as segments are detected and opened as such regardless, but
+    /// only start new segments when opened with a size. Writes are never split across segments, so
+    /// a segment can exceed the size by up to the length of a single write.
+    pub fn segment_size(&mut self, size: u64) -> &mut Self {
+        self.segment_size = Some(size);
+        self
+    }
+
+    /// Keeps the most recently read values in memory, so that reading them again doesn't touch the
+    /// file. Defaults to no cache.
+    ///
+    /// Values are cached in their encoded form, so they still get deserialized on every read.
+    pub fn cache(&mut self, config: CacheConfig) -> &mut Self {
+        self.cache = config;
+        self
+    }
+
+    /// Sets how many bytes of records are buffered in memory before being written to the file.
+    /// Defaults to 8 KiB.
+    ///
+    /// Buffered records are visible to reads through the store, but not to other processes, and
+    /// are lost if the process crashes before they get flushed. Setting this to `0` writes every
+    /// record as soon as it is set, while syncing always flushes the buffer first.
+    pub fn write_buffer(&mut self, capacity: usize) -> &mut Self {
+        self.write_buffer = capacity;
+        self
+    }
+
+    /// Unsets the keys whose time to live has elapsed in a background thread, at the given
+    /// interval. Defaults to leaving them in the index until the next compaction. See
+    /// [`Store::sweep_expired`].
+    ///
+    /// Ignored on targets without threads, such as browsers.
+    pub fn sweep_interval(&mut self, interval: Duration) -> &mut Self {
+        self.sweep_interval = Some(interval);
+        self
+    }
+
+    /// Compacts the database in a background thread whenever a write leaves it past the thresholds
+    /// of the given policy. Defaults to never compacting on its own.
+    ///
+    /// Writes wait for the compaction to finish when they happen while it runs. Errors are ignored,
+    /// and the compaction is retried on the next write.
+    pub fn compaction_policy(&mut self, policy: CompactionPolicy) -> &mut Self {
+        self.compaction = Some(policy);
+        self
+    }
+
+    /// Caps the size in bytes of the database, across all its segments, with writes which would
+    /// exceed it handled according to the given policy. Defaults to no limit.
+    ///
+    /// Compactions and evictions happen synchronously, as part of the write which needs the room.
+    pub fn max_file_size(&mut self, size: u64, policy: FullPolicy) -> &mut Self {
+        self.max_file_size = Some((size, policy));
+        self
+    }
+
+    /// Makes the store behave like a persistent cache holding at most the given number of keys, by
+    /// unsetting the least recently used ones when a write goes past it. Defaults to no limit.
+    ///
+    /// Both reads and writes count as uses. Since reads aren't persisted, keys are taken to have
+    /// last been used when they were written when opening the database, which evicts the oldest
+    /// ones right away if there are too many.
+    pub fn max_live_keys(&mut self, max: usize) -> &mut Self {
+        self.max_live_keys = Some(max);
+        self
+    }
+
+    /// Stores the values whose encoding is at least the given number of bytes long in blob files
+    /// of their own, in a `.blobs` directory next to the database, leaving only their hash in the
+    /// log. Defaults to keeping every value in the log.
+    ///
+    /// This keeps large values out of the way of scans and compactions. Identical values share the
+    /// same blob, and blobs are deleted by the compaction following the last write referring to
+    /// them. Databases without a path, like in-memory ones, keep every value in the log.
+    pub fn blob_threshold(&mut self, threshold: usize) -> &mut Self {
+        self.blob_threshold = Some(threshold);
+        self
+    }
+
+    /// Compacts the database in a background thread whenever the number of records which no longer
+    /// hold the current value of a key exceeds `threshold` times the number of live keys, whatever
+    /// its size. Shorthand for the equivalent [`OpenOptions::compaction_policy`].
+    pub fn auto_compact(&mut self, threshold: f64) -> &mut Self {
+        self.compaction_policy(CompactionPolicy {
+            max_dead_ratio: threshold / (1.0 + threshold),
+            min_file_size: 0,
+        })
+    }
+
+    /// Sets whether the database is created if it doesn't exist yet, rather than failing to open.
+    /// Defaults to `true`.
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    /// Opens the database without write access. See [`Store::open_read_only`]. Defaults to
+    /// `false`.
+    ///
+    /// Settings which only affect writes are ignored.
+    pub fn read_only(&mut self, read_only: bool) -> &mut Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Names the type of the values, so that the database can't be opened by mistake as a store of
+    /// another type. Defaults to no tag.
+    ///
+    /// The tag is written at the start of the files of a database created with it, and opening
+    /// such a database with another tag fails with [`Error::TypeMismatch`]. Stores opened without
+    /// a tag skip the check, which gives untyped access to any database, e.g. as a
+    /// `Store<serde_json::Value>`. Databases created without a tag are never checked.
+    pub fn type_tag(&mut self, type_tag: &str) -> &mut Self {
+        self.type_tag = Some(type_tag.to_string());
+        self
+    }
+
+    /// Encrypts values with XChaCha20-Poly1305 under the given key. Defaults to storing values in
+    /// plaintext.
+    ///
+    /// Keys and expirations remain in plaintext. Values which were written without encryption are
+    /// still read, and get encrypted on the next compaction. Opening a database holding values
+    /// encrypted under another key fails with [`Error::Decrypt`].
+    #[cfg(feature = "encryption")]
+    pub fn encryption_key(&mut self, key: [u8; 32]) -> &mut Self {
+        self.encryption_key = Some(key);
+        self
+    }
+
+    /// Calls `hook` with every key which gets unset, including by [`Store::sweep_expired`], once
+    /// the write succeeded. See [`OpenOptions::on_set`].
+    pub fn on_unset<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        self.hooks.on_write(move |record| {
+            if record.is_tombstone() {
+                hook(&record.key);
+            }
+        });
+        self
+    }
+
+    /// Calls `hook` after every compaction which succeeded, including those made in the background
+    /// with [`OpenOptions::compaction_policy`].
+    ///
+    /// The hook is called with the lock of the store held, so it must not use the store itself.
+    pub fn on_compact<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.hooks.on_compact(hook);
+        self
+    }
+
+    /// Returns the same options for a store whose values are serialized with another [`Codec`], for
+    /// when naming the type of the store isn't convenient.
+    ///
+    /// Hooks aren't carried over, since those of [`OpenOptions::on_set`] decode values with the
+    /// codec they were registered under.
+    pub fn codec<D: Codec>(&self) -> OpenOptions<T, D> {
+        OpenOptions {
+            sync: self.sync,
+            recovery: self.recovery,
+            format: self.format,
+            compression: self.compression,
+            segment_size: self.segment_size,
+            cache: self.cache,
+            write_buffer: self.write_buffer,
+            compaction: self.compaction,
+            max_file_size: self.max_file_size,
+            max_live_keys: self.max_live_keys,
+            blob_threshold: self.blob_threshold,
+            sweep_interval: self.sweep_interval,
+            key_policy: self.key_policy,
+            max_value_size: self.max_value_size,
+            read_only: self.read_only,
+            create: self.create,
+            type_tag: self.type_tag.clone(),
+            hooks: Hooks::default(),
+            #[cfg(feature = "encryption")]
+            encryption_key: self.encryption_key,
+            _phantom: PhantomData,
+        }
+    }
+
+    pub(crate) fn cipher(&self) -> Option<Cipher> {
+        #[cfg(feature = "encryption")]
+        return self.encryption_key.as_ref().map(Cipher::new);
+        #[cfg(not(feature = "encryption"))]
+        None
+    }
+}
+
+impl<T, C: Codec> OpenOptions<T, C> {
+    /// Calls `hook` with every key which gets set and its new value, once the write succeeded, in
+    /// the order of the writes. Defaults to no hooks.
+    ///
+    /// Hooks run for the writes made through any clone of the store, and for a follower, for those
+    /// of the owner of the database as it catches up with them. They're called with the lock of
+    /// the store held, so they must not use the store itself, and should hand anything slow off to
+    /// another thread. Values which can't be decoded are skipped.
+    pub fn on_set<F>(&mut self, hook: F) -> &mut Self
+    where
+        T: for<'a> Deserialize<'a>,
+        F: Fn(&str, T) + Send + Sync + 'static,
+    {
+        self.hooks.on_write(move |record| {
+            if record.is_tombstone() {
+                return;
+            }
+            if let Ok(value) = C::decode(&record.value) {
+                hook(&record.key, value);
+            }
+        });
+        self
+    }
+
+    /// Opens the database at the given path with these options.
+    pub fn open(&self, path: &Path) -> Result<Store<T, C>, Error> {
+        Store::open_with(path, self)
+    }
+
+    /// Opens the database at the given path as a read-only follower with these options. See
+    /// [`Store::open_follower`].
+    ///
+    /// Settings which only affect writes are ignored.
+    pub fn open_follower(&self, path: &Path) -> Result<Store<T, C>, Error> {
+        Store::open_follower_with(path, self)
+    }
+
+    /// Creates an empty in-memory database with these options. See [`Store::in_memory`].
+    ///
+    /// The segment size is ignored, since segments are separate files.
+    pub fn open_in_memory(&self) -> Store<T, C> {
+        self.open_backend(Vec::new())
+            .expect("an empty in-memory database can always be opened")
+    }
+
+    /// Opens the database held by the given backend with these options, creating it if the backend
+    /// is empty.
+    ///
+    /// The segment size is ignored, and no lock is taken, so the backend must not be shared with
+    /// another store. Compactions rewrite the backend through [`Backend::replace`].
+    pub fn open_backend<B: Backend + 'static>(&self, backend: B) -> Result<Store<T, C>, Error> {
+        Store::open_backend_with(Box::new(backend), self)
+    }
+}
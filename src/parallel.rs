@@ -0,0 +1,54 @@
+//! Loading of the database with values deserialized on several threads, behind the `rayon`
+//! feature.
+
+use rayon::prelude::*;
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+
+use crate::{Codec, Error, Store};
+
+impl<T, C> Store<T, C>
+where
+    T: for<'a> Deserialize<'a> + Send,
+    C: Codec,
+{
+    /// Same as [`Store::load_map`], but with the values deserialized in parallel on the global
+    /// Rayon thread pool.
+    ///
+    /// The database is still read in a single pass, which only keeps the latest record of every
+    /// key, before the values are deserialized. Their encoded form is held in memory in the
+    /// meantime, so this is only worth it when deserialization dominates, as with large or nested
+    /// values.
+    pub fn par_load_map(&self) -> Result<FxHashMap<String, T>, Error> {
+        let inner = self.read();
+        let live = inner.scan(|location, record, live: &mut Vec<_>| {
+            if inner.is_latest(location, &record.key) {
+                let record = inner.load(record)?;
+                live.push((record.key.into_owned(), record.value.into_owned()));
+            }
+            Ok(())
+        })?;
+        drop(inner);
+        live.into_par_iter()
+            .map(|(key, value)| Ok((key, C::decode(&value)?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Store;
+
+    #[test]
+    fn par_load_map() {
+        let store = Store::<Vec<u32>>::in_memory();
+        for i in 0..1000 {
+            store.set(&format!("key{}", i % 100), &vec![i; 10]).unwrap();
+        }
+        store.unset("key0").unwrap();
+        let map = store.par_load_map().unwrap();
+        assert_eq!(store.load_map().unwrap(), map);
+        assert_eq!(99, map.len());
+        assert_eq!(vec![999; 10], map["key99"]);
+    }
+}
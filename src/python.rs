@@ -0,0 +1,154 @@
+//! Python bindings, with the `python` feature, exposing stores of JSON values as a `kv.Store`
+//! class whose values are converted to and from Python objects through the `json` module:
+//!
+//! ```python
+//! import kv
+//!
+//! store = kv.Store("db")
+//! store.set("user:1", {"name": "Ada", "tags": ["admin"]})
+//! dict(store.items())
+//! ```
+//!
+//! The extension module is built with [maturin](https://www.maturin.rs), e.g. with
+//! `maturin develop --features python,pyo3/extension-module`.
+
+use std::path::PathBuf;
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use serde_json::Value;
+
+use crate::{decode_err, Error, Store};
+
+create_exception!(kv, KvError, PyException, "Error raised by a store.");
+
+impl From<Error> for PyErr {
+    fn from(err: Error) -> Self {
+        KvError::new_err(err.to_string())
+    }
+}
+
+/// A store of JSON values, opened from Python.
+#[pyclass(name = "Store", module = "kv", frozen)]
+struct PyStore(Store<Value>);
+
+#[pymethods]
+impl PyStore {
+    /// Opens the database at the given path, creating it if it doesn't exist yet unless it's opened
+    /// read-only.
+    #[new]
+    #[pyo3(signature = (path, read_only = false))]
+    fn new(path: PathBuf, read_only: bool) -> PyResult<Self> {
+        let store = if read_only {
+            Store::open_read_only(&path)?
+        } else {
+            Store::open(&path)?
+        };
+        Ok(Self(store))
+    }
+
+    /// Returns the value of the key, or `None` if it holds none.
+    fn get<'py>(&self, py: Python<'py>, key: &str) -> PyResult<Option<Bound<'py, PyAny>>> {
+        self.0
+            .get(key)?
+            .map(|value| to_python(py, &value))
+            .transpose()
+    }
+
+    /// Sets the key to the given value, which must be serializable to JSON.
+    fn set(&self, key: &str, value: &Bound<'_, PyAny>) -> PyResult<()> {
+        Ok(self.0.set(key, &from_python(value)?)?)
+    }
+
+    fn unset(&self, key: &str) -> PyResult<()> {
+        Ok(self.0.unset(key)?)
+    }
+
+    /// Returns the live keys in lexicographic order.
+    fn keys(&self) -> Vec<String> {
+        self.0.keys().collect()
+    }
+
+    /// Returns the live entries as `(key, value)` pairs, in lexicographic key order.
+    fn items<'py>(&self, py: Python<'py>) -> PyResult<Vec<(String, Bound<'py, PyAny>)>> {
+        let mut items = Vec::new();
+        for entry in self.0.scan_prefix("") {
+            let (key, value) = entry?;
+            items.push((key, to_python(py, &value)?));
+        }
+        Ok(items)
+    }
+
+    fn __len__(&self) -> usize {
+        self.0.len()
+    }
+
+    fn __contains__(&self, key: &str) -> PyResult<bool> {
+        Ok(self.0.contains(key)?)
+    }
+}
+
+fn to_python<'py>(py: Python<'py>, value: &Value) -> PyResult<Bound<'py, PyAny>> {
+    py.import("json")?
+        .call_method1("loads", (value.to_string(),))
+}
+
+fn from_python(value: &Bound<'_, PyAny>) -> PyResult<Value> {
+    let json: String = value
+        .py()
+        .import("json")?
+        .call_method1("dumps", (value,))?
+        .extract()?;
+    Ok(serde_json::from_str(&json).map_err(decode_err)?)
+}
+
+/// The `kv` Python module.
+#[pymodule]
+fn kv(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyStore>()?;
+    m.add("Error", m.py().get_type::<KvError>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn python() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        Python::initialize();
+        Python::attach(|py| {
+            let store = PyStore::new(path.clone(), false).unwrap();
+            let user = py.eval(c"{'name': 'Ada', 'tags': ['admin'], 'age': 36}", None, None);
+            let user = user.unwrap();
+            store.set("user:1", &user).unwrap();
+            store
+                .set("count", &3u32.into_pyobject(py).unwrap())
+                .unwrap();
+            store.unset("count").unwrap();
+
+            let read = store.get(py, "user:1").unwrap().unwrap();
+            assert!(read.eq(&user).unwrap());
+            assert!(store.get(py, "count").unwrap().is_none());
+            assert_eq!(1, store.__len__());
+            assert!(store.__contains__("user:1").unwrap());
+            assert_eq!(vec!["user:1"], store.keys());
+            let items = store.items(py).unwrap();
+            assert_eq!("user:1", items[0].0);
+
+            let not_json = py.eval(c"object()", None, None).unwrap();
+            assert!(store.set("bad", &not_json).is_err());
+            drop(store);
+            assert!(PyStore::new(path.clone(), false).is_ok());
+            assert!(PyStore::new(dir.path().join("missing"), true).is_err());
+        });
+
+        let store = Store::<Value>::open(&path).unwrap();
+        assert_eq!(
+            Some(serde_json::json!({ "name": "Ada", "tags": ["admin"], "age": 36 })),
+            store.get("user:1").unwrap()
+        );
+    }
+}
@@ -0,0 +1,363 @@
+//! Filtering of entries on the content of their values, as done by [`Store::query`].
+
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{encode_err, Codec, Error, Store};
+
+impl<T, C> Store<T, C>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+    C: Codec,
+{
+    /// Returns the live entries whose value matches the given query, in lexicographic key order.
+    ///
+    /// A query compares fields inside the values, seen as JSON, to literals:
+    ///
+    /// ```text
+    /// $.address.city == 'Paris' && ($.age >= 18 || $.tags[0] == "admin")
+    /// ```
+    ///
+    /// Paths start with `$`, standing for the whole value, followed by any number of `.field`,
+    /// `['field']` or `[index]` accessors. They can be compared with `==`, `!=`, `<`, `<=`, `>` and
+    /// `>=` to strings in single or double quotes, numbers, `true`, `false` and `null`, and
+    /// comparisons can be combined with `&&`, `||` and parentheses. A path on its own matches
+    /// values in which it leads to anything but `null`. Ordering comparisons only match numbers
+    /// with numbers and strings with strings, so `$.age > 18` skips values without a numeric age.
+    ///
+    /// The whole database is read in a single pass through [`Store::for_each`], and values are
+    /// converted to JSON whatever the codec of the store is. Fails with [`Error::Query`] if the
+    /// query is invalid, before anything is read.
+    pub fn query(&self, query: &str) -> Result<Vec<(String, T)>, Error> {
+        let query = Query::parse(query)?;
+        let mut matches = Vec::new();
+        let mut error = None;
+        self.for_each(|key, value| match serde_json::to_value(&value) {
+            Ok(json) if query.matches(&json) => matches.push((key.to_string(), value)),
+            Ok(_) => {}
+            Err(err) => {
+                error.get_or_insert(err);
+            }
+        })?;
+        if let Some(err) = error {
+            return Err(encode_err(err));
+        }
+        matches.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(matches)
+    }
+}
+
+/// A parsed query.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Query {
+    Or(Box<Query>, Box<Query>),
+    And(Box<Query>, Box<Query>),
+    Compare(Vec<Step>, Op, Value),
+    Exists(Vec<Step>),
+}
+
+/// Accessor within a path.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Step {
+    Field(String),
+    Index(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Query {
+    pub fn parse(query: &str) -> Result<Self, Error> {
+        let mut parser = Parser { query, pos: 0 };
+        let parsed = parser.or()?;
+        parser.skip_whitespace();
+        if parser.pos < query.len() {
+            return Err(parser.error("unexpected input"));
+        }
+        Ok(parsed)
+    }
+
+    pub fn matches(&self, value: &Value) -> bool {
+        match self {
+            Query::Or(a, b) => a.matches(value) || b.matches(value),
+            Query::And(a, b) => a.matches(value) && b.matches(value),
+            Query::Exists(path) => resolve(value, path).is_some_and(|found| !found.is_null()),
+            Query::Compare(path, op, literal) => {
+                let found = resolve(value, path).unwrap_or(&Value::Null);
+                match op {
+                    Op::Eq => equal(found, literal),
+                    Op::Ne => !equal(found, literal),
+                    Op::Lt => compare(found, literal).is_some_and(Ordering::is_lt),
+                    Op::Le => compare(found, literal).is_some_and(Ordering::is_le),
+                    Op::Gt => compare(found, literal).is_some_and(Ordering::is_gt),
+                    Op::Ge => compare(found, literal).is_some_and(Ordering::is_ge),
+                }
+            }
+        }
+    }
+}
+
+fn resolve<'a>(value: &'a Value, path: &[Step]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |value, step| match step {
+        Step::Field(field) => value.get(field),
+        Step::Index(index) => value.get(index),
+    })
+}
+
+/// Compares numbers by value, so that `1` and `1.0` are equal.
+fn equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+        _ => a == b,
+    }
+}
+
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+/// Recursive descent parser, with `||` binding looser than `&&`.
+struct Parser<'a> {
+    query: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn or(&mut self) -> Result<Query, Error> {
+        let mut query = self.and()?;
+        while self.eat("||") {
+            query = Query::Or(Box::new(query), Box::new(self.and()?));
+        }
+        Ok(query)
+    }
+
+    fn and(&mut self) -> Result<Query, Error> {
+        let mut query = self.primary()?;
+        while self.eat("&&") {
+            query = Query::And(Box::new(query), Box::new(self.primary()?));
+        }
+        Ok(query)
+    }
+
+    fn primary(&mut self) -> Result<Query, Error> {
+        if self.eat("(") {
+            let query = self.or()?;
+            if !self.eat(")") {
+                return Err(self.error("expected `)`"));
+            }
+            return Ok(query);
+        }
+
+        let path = self.path()?;
+        // Longest operators first, so that `<=` isn't read as `<`.
+        let ops = [
+            ("==", Op::Eq),
+            ("!=", Op::Ne),
+            ("<=", Op::Le),
+            (">=", Op::Ge),
+            ("<", Op::Lt),
+            (">", Op::Gt),
+        ];
+        match ops.into_iter().find(|(token, _)| self.eat(token)) {
+            Some((_, op)) => Ok(Query::Compare(path, op, self.literal()?)),
+            None => Ok(Query::Exists(path)),
+        }
+    }
+
+    fn path(&mut self) -> Result<Vec<Step>, Error> {
+        if !self.eat("$") {
+            return Err(self.error("expected a path starting with `$`"));
+        }
+        let mut path = Vec::new();
+        loop {
+            if self.rest().starts_with('.') {
+                self.pos += 1;
+                let len = self
+                    .rest()
+                    .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+                    .unwrap_or(self.rest().len());
+                if len == 0 {
+                    return Err(self.error("expected a field name"));
+                }
+                path.push(Step::Field(self.rest()[..len].to_string()));
+                self.pos += len;
+            } else if self.rest().starts_with('[') {
+                self.pos += 1;
+                if self.rest().starts_with(['\'', '"']) {
+                    path.push(Step::Field(self.string()?));
+                } else {
+                    let len = self
+                        .rest()
+                        .find(|c: char| !c.is_ascii_digit())
+                        .unwrap_or(self.rest().len());
+                    let index = self.rest()[..len]
+                        .parse()
+                        .map_err(|_| self.error("expected an index or a quoted field name"))?;
+                    path.push(Step::Index(index));
+                    self.pos += len;
+                }
+                if !self.rest().starts_with(']') {
+                    return Err(self.error("expected `]`"));
+                }
+                self.pos += 1;
+            } else {
+                return Ok(path);
+            }
+        }
+    }
+
+    fn literal(&mut self) -> Result<Value, Error> {
+        self.skip_whitespace();
+        if self.rest().starts_with(['\'', '"']) {
+            return self.string().map(Value::String);
+        }
+        for (keyword, value) in [
+            ("true", Value::Bool(true)),
+            ("false", Value::Bool(false)),
+            ("null", Value::Null),
+        ] {
+            if self.eat(keyword) {
+                return Ok(value);
+            }
+        }
+        let len = self
+            .rest()
+            .find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c)))
+            .unwrap_or(self.rest().len());
+        let number = serde_json::from_str(&self.rest()[..len])
+            .ok()
+            .filter(Value::is_number)
+            .ok_or_else(|| self.error("expected a string, a number, `true`, `false` or `null`"))?;
+        self.pos += len;
+        Ok(number)
+    }
+
+    /// Reads a string in single or double quotes, in which a backslash escapes the next character.
+    fn string(&mut self) -> Result<String, Error> {
+        let mut chars = self.rest().char_indices();
+        let (_, quote) = chars.next().expect("called on an opening quote");
+        let mut string = String::new();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some((_, escaped)) => string.push(escaped),
+                    None => break,
+                },
+                c if c == quote => {
+                    self.pos += i + 1;
+                    return Ok(string);
+                }
+                c => string.push(c),
+            }
+        }
+        Err(self.error("unterminated string"))
+    }
+
+    /// Skips whitespace, then consumes the given token if it comes next.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        let found = self.rest().starts_with(token);
+        if found {
+            self.pos += token.len();
+        }
+        found
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.pos = self.query.len() - self.rest().trim_start().len();
+    }
+
+    fn rest(&self) -> &str {
+        &self.query[self.pos..]
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::Query(format!("{message} at position {}", self.pos))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let matches = |query: &str, value: Value| Query::parse(query).unwrap().matches(&value);
+        let person = json!({
+            "name": "Ada",
+            "age": 36,
+            "address": { "city": "Paris", "zip code": "75001" },
+            "tags": ["admin", "dev"],
+            "retired": null,
+        });
+
+        assert!(matches("$.address.city == 'Paris'", person.clone()));
+        assert!(matches(
+            "$.address['zip code'] == \"75001\"",
+            person.clone()
+        ));
+        assert!(matches("$.age >= 36 && $.age < 40.5", person.clone()));
+        assert!(matches("$.age == 36.0", person.clone()));
+        assert!(matches("$.tags[1] == 'dev'", person.clone()));
+        assert!(matches("$.name > 'A' && $.name <= 'Ada'", person.clone()));
+        assert!(matches(
+            "$.age > 100 || ($.tags[0] == 'admin' && $.name != 'Bob')",
+            person.clone()
+        ));
+        assert!(matches("$.address", person.clone()));
+        assert!(!matches("$.retired", person.clone()));
+        assert!(!matches("$.missing.field", person.clone()));
+        assert!(matches("$.missing == null", person.clone()));
+        assert!(!matches("$.name > 1", person.clone()));
+        assert!(matches("$ == 'it\\'s'", json!("it's")));
+
+        for invalid in [
+            "",
+            "address.city == 'Paris'",
+            "$.city == Paris",
+            "$.city == 'Paris",
+            "$.tags[x]",
+            "($.a",
+            "$.a == 1 extra",
+            "$.",
+        ] {
+            assert!(
+                matches!(Query::parse(invalid), Err(Error::Query(_))),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn query() {
+        let store = Store::<Value>::in_memory();
+        store.set("c", &json!({ "city": "Paris" })).unwrap();
+        store.set("a", &json!({ "city": "Paris" })).unwrap();
+        store.set("b", &json!({ "city": "Lyon" })).unwrap();
+        store.set("d", &json!(1)).unwrap();
+
+        let keys = |query| {
+            let entries = store.query(query).unwrap();
+            entries.into_iter().map(|(key, _)| key).collect::<Vec<_>>()
+        };
+        assert_eq!(vec!["a", "c"], keys("$.city == 'Paris'"));
+        assert_eq!(vec!["a", "b", "c"], keys("$.city"));
+        assert_eq!(vec!["d"], keys("$ > 0"));
+        assert!(store.query("city").is_err());
+    }
+}
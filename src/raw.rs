@@ -0,0 +1,196 @@
+use std::fmt::Write as _;
+use std::marker::PhantomData;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::record::{Record, TOMBSTONE};
+use crate::{Codec, Error, Store};
+
+/// A view over a [`Store`] whose keys and values are raw bytes, which don't go through a codec.
+///
+/// Created by [`RawStore::open`] for a database of its own, or by [`Store::raw`] over any
+/// database. Keys are stored in hexadecimal, which keeps them in the same order as the bytes, and
+/// values are stored as-is, so in base64 by the text format unless they happen to be valid UTF-8,
+/// and without any overhead by the binary one.
+pub struct RawStore {
+    store: Store<Vec<u8>, RawCodec>,
+}
+
+impl Clone for RawStore {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+        }
+    }
+}
+
+/// Codec recorded in the header of the databases created by [`RawStore::open`], so that they
+/// can't be mistaken for databases of encoded values. Values never actually go through it.
+pub(crate) struct RawCodec;
+
+impl Codec for RawCodec {
+    const NAME: Option<&'static str> = Some("raw");
+
+    fn encode<T: Serialize + ?Sized>(_: &T) -> Result<Vec<u8>, Error> {
+        Err(Error::Write("Raw databases only hold bytes".to_string()))
+    }
+
+    fn decode<T: for<'a> Deserialize<'a>>(_: &[u8]) -> Result<T, Error> {
+        Err(Error::Read("Raw databases only hold bytes".to_string()))
+    }
+}
+
+impl RawStore {
+    pub(crate) fn new<T, C>(store: &Store<T, C>) -> Self {
+        Self {
+            store: Store(store.0.clone(), store.1.clone(), PhantomData),
+        }
+    }
+
+    /// Opens the raw database at the given path, creating it if it doesn't exist yet. See
+    /// [`Store::open`].
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        Ok(Self {
+            store: Store::open(path)?,
+        })
+    }
+
+    /// Creates an empty raw database which is only kept in memory. See [`Store::in_memory`].
+    pub fn in_memory() -> Self {
+        Self {
+            store: Store::in_memory(),
+        }
+    }
+
+    /// Returns the value of the key, or `None` if it holds none.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        self.store.read().read_value(&encode_key(key))
+    }
+
+    /// Sets the key to the given value.
+    ///
+    /// Fails with [`Error::Write`] for the value `null`, which is how the database marks unset
+    /// keys.
+    pub fn set(&self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        if value == TOMBSTONE {
+            return Err(Error::Write(
+                "The value `null` is reserved for unset keys".to_string(),
+            ));
+        }
+        let key = encode_key(key);
+        self.store.commit(Record::new(&key, value))
+    }
+
+    /// See [`Store::unset`].
+    pub fn unset(&self, key: &[u8]) -> Result<(), Error> {
+        self.store.unset(&encode_key(key))
+    }
+
+    /// See [`Store::contains`].
+    pub fn contains(&self, key: &[u8]) -> Result<bool, Error> {
+        self.store.contains(&encode_key(key))
+    }
+
+    /// Returns the keys which currently hold a value, in order, skipping those which weren't
+    /// written as bytes.
+    pub fn keys(&self) -> impl Iterator<Item = Vec<u8>> {
+        self.store.keys().filter_map(|key| decode_key(&key))
+    }
+
+    /// Returns an iterator over the live entries, in key order, skipping those whose keys weren't
+    /// written as bytes. Values are read one at a time as the iterator advances.
+    pub fn iter(&self) -> impl Iterator<Item = Result<(Vec<u8>, Vec<u8>), Error>> {
+        let store = self.clone();
+        self.keys().filter_map(move |key| match store.get(&key) {
+            Ok(value) => value.map(|value| Ok((key, value))),
+            Err(err) => Some(Err(err)),
+        })
+    }
+}
+
+impl<T, C> Store<T, C> {
+    /// Returns a view over the database with raw bytes as keys and values. See [`RawStore`] for
+    /// how they're stored.
+    pub fn raw(&self) -> RawStore {
+        RawStore::new(self)
+    }
+}
+
+fn encode_key(key: &[u8]) -> String {
+    let mut hex = String::with_capacity(key.len() * 2);
+    for byte in key {
+        let _ = write!(hex, "{byte:02x}");
+    }
+    hex
+}
+
+fn decode_key(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Format;
+
+    #[test]
+    fn raw() {
+        for format in [Format::Text, Format::Binary] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("db");
+            let store = Store::<Vec<u8>>::options()
+                .format(format)
+                .open(&path)
+                .unwrap()
+                .raw();
+            let hash = [0xde, 0xad, 0xbe, 0xef];
+            let blob = [0x08, 0x96, 0x01, 0xff, b'\n'];
+            store.set(&hash, &blob).unwrap();
+            store.set(b"\x00", b"").unwrap();
+            store.set(b"gone", b"x").unwrap();
+            store.unset(b"gone").unwrap();
+            assert!(matches!(store.set(b"gone", b"null"), Err(Error::Write(_))));
+
+            assert_eq!(Some(blob.to_vec()), store.get(&hash).unwrap());
+            assert_eq!(Some(Vec::new()), store.get(b"\x00").unwrap());
+            assert_eq!(None, store.get(b"gone").unwrap());
+            assert!(store.contains(&hash).unwrap());
+            assert_eq!(
+                vec![(vec![0], vec![]), (hash.to_vec(), blob.to_vec())],
+                store.iter().collect::<Result<Vec<_>, _>>().unwrap()
+            );
+            drop(store);
+
+            let store = Store::<Vec<u8>>::open(&path).unwrap();
+            assert!(store.contains("deadbeef").unwrap());
+            assert_eq!(Some(blob.to_vec()), store.raw().get(&hash).unwrap());
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        RawStore::open(&path).unwrap().set(b"a", b"b").unwrap();
+        assert!(matches!(
+            Store::<String>::open(&path),
+            Err(Error::CodecMismatch { .. })
+        ));
+        assert_eq!(
+            Some(b"b".to_vec()),
+            RawStore::open(&path).unwrap().get(b"a").unwrap()
+        );
+        assert_eq!(vec![b"b".to_vec()], {
+            let store = RawStore::in_memory();
+            store.set(b"a", b"b").unwrap();
+            store
+                .iter()
+                .map(|entry| entry.unwrap().1)
+                .collect::<Vec<_>>()
+        });
+    }
+}
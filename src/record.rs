@@ -0,0 +1,892 @@
+//! Encoding of the records making up the database file.
+//!
+//! Every record is a single line made of the key and the encoded value separated by a comma,
+//! followed by tab-separated `name=value` metadata fields, the last of which is a CRC32 of
+//! everything preceding it:
+//!
+//! ```text
+//! some key,"some value"<TAB>exp=1700000000000<TAB>ts=1690000000000<TAB>v=2<TAB>crc=4f2a1c3b
+//! ```
+//!
+//! The `exp` field holds the time from which the record is to be considered absent, and `ts` the
+//! time at which it was written, both as Unix timestamps in milliseconds. The `v` field holds the
+//! version of the schema of the value, for stores with migrations. Any of them can be missing.
+//!
+//! Values which aren't UTF-8 or which contain raw tabs or newlines, as binary codecs are wont to
+//! produce, are written in base64 and flagged with an `enc=b64` field, so that neither can be
+//! mistaken for a separator. Serialized JSON never needs it. Unknown metadata fields are ignored
+//! so that older versions can still read newer files, and records without a checksum are accepted
+//! so that files predating it remain readable.
+//!
+//! Keys are percent-encoded where needed so that any string can be used as a key: `%`, `,` and
+//! control characters are escaped, everything else is written as-is.
+//!
+//! Databases in the [`Format::Binary`] format instead start with the `%kv` magic followed by a
+//! version byte, which can't be mistaken for a text record since `%k` isn't a valid escape. Every
+//! record is then laid out as follows, with integers in little-endian:
+//!
+//! ```text
+//! body length: u32 | CRC32 of the body: u32 | body
+//! body = flags: u8 | [expiry: u64] | [timestamp: u64] | [version: u32] | key length: u32 | key | value
+//! ```
+//!
+//! The expiry, the timestamp and the version are only present if the corresponding bits of the
+//! flags are set.
+//!
+//! Compressed values are flagged with a `cmp=zstd` field in the text format, and with another bit
+//! of the flags in the binary one. Likewise, encrypted values are flagged with an
+//! `aead=xchacha20poly1305` field or another bit of the flags, and values held in a blob file,
+//! which are replaced with its hash, with a `blob=sha256` field or another bit of the flags.
+//!
+//! In the [`Format::Ndjson`] format, every record is a JSON object on a line of its own, with the
+//! same fields as the text format under the same names, except for the key, the value and the
+//! version which are under `k`, `v` and `ver`:
+//!
+//! ```text
+//! {"k":"some key","v":"some value","exp":1700000000000,"ts":1690000000000,"crc":"4f2a1c3b"}
+//! ```
+//!
+//! Values are embedded as-is when they are single-line JSON, and as base64 strings flagged with
+//! `"enc":"b64"` otherwise, except for the hashes of blobs which are plain strings. The checksum
+//! covers the line up to the comma preceding it.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, Read, Write};
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use serde_json::value::RawValue;
+
+use crate::crypto::Cipher;
+use crate::{line_error, Compression, Error, Format};
+
+/// Value of the records which unset their key.
+pub(crate) const TOMBSTONE: &[u8] = b"null";
+
+const MAGIC: &[u8] = b"%kv";
+const BINARY_HEADER: &[u8] = b"%kv\x02";
+/// Length of the fixed part of binary records, i.e. the body length and checksum.
+const BINARY_PREFIX_LEN: usize = 8;
+const FLAG_EXPIRES: u8 = 1;
+const FLAG_ZSTD: u8 = 2;
+const FLAG_ENCRYPTED: u8 = 4;
+const FLAG_TIMESTAMP: u8 = 8;
+const FLAG_VERSION: u8 = 16;
+const FLAG_BLOB: u8 = 32;
+
+impl Format {
+    /// Detects the format of a database from its first bytes, returning `None` if it's empty.
+    pub(crate) fn detect(start: &[u8]) -> Result<Option<Self>, Error> {
+        if start.is_empty() {
+            Ok(None)
+        } else if start == BINARY_HEADER {
+            Ok(Some(Format::Binary))
+        } else if start.starts_with(MAGIC) {
+            Err(Error::Read(
+                "Unsupported database format version".to_string(),
+            ))
+        } else {
+            Ok(Some(Format::Text))
+        }
+    }
+
+    /// Bytes with which a database in this format starts.
+    pub(crate) fn header(self) -> &'static [u8] {
+        match self {
+            Format::Text | Format::Ndjson => b"",
+            Format::Binary => BINARY_HEADER,
+        }
+    }
+
+    /// Appends the encoded record to the buffer, compressing and encrypting its value if called
+    /// for.
+    pub(crate) fn encode(
+        self,
+        record: &Record,
+        compression: Compression,
+        cipher: Option<&Cipher>,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), Error> {
+        let compressed = compress(record, compression)?;
+        let record = compressed.as_ref().unwrap_or(record);
+        let encrypted = encrypt(record, cipher)?;
+        let record = encrypted.as_ref().unwrap_or(record);
+        match self {
+            Format::Text => {
+                record.encode(buf);
+                Ok(())
+            }
+            Format::Binary => record.encode_binary(buf),
+            Format::Ndjson => {
+                record.encode_json(buf);
+                Ok(())
+            }
+        }
+    }
+
+    /// Reads the raw bytes of the next record into the (empty) buffer, returning how many were
+    /// read. Fewer bytes than the record's length are read if the file ends early.
+    pub(crate) fn read<R: BufRead>(self, reader: &mut R, buf: &mut Vec<u8>) -> io::Result<usize> {
+        match self {
+            Format::Text | Format::Ndjson => reader.read_until(b'\n', buf),
+            Format::Binary => {
+                let read = reader
+                    .by_ref()
+                    .take(BINARY_PREFIX_LEN as u64)
+                    .read_to_end(buf)?;
+                match binary_len(buf) {
+                    Some(len) => Ok(read + reader.take(len as u64).read_to_end(buf)?),
+                    None => Ok(read),
+                }
+            }
+        }
+    }
+
+    /// Checks whether the raw record was cut short, as happens when its write is interrupted.
+    pub(crate) fn is_torn(self, raw: &[u8]) -> bool {
+        match self {
+            Format::Text | Format::Ndjson => !raw.ends_with(b"\n"),
+            Format::Binary => binary_len(raw).is_none_or(|len| raw.len() < BINARY_PREFIX_LEN + len),
+        }
+    }
+
+    /// Parses raw bytes read with [`Format::read`], decrypting and decompressing the value if
+    /// needed.
+    pub(crate) fn decode<'a>(
+        self,
+        raw: &'a [u8],
+        offset: u64,
+        cipher: Option<&Cipher>,
+    ) -> Result<Record<'a>, Error> {
+        let mut record = match self {
+            Format::Text => Record::from_line(raw, offset)?,
+            Format::Binary => Record::from_binary(raw, offset)?,
+            Format::Ndjson => Record::from_json(raw, offset)?,
+        };
+        if record.encrypted {
+            let cipher = cipher.ok_or(Error::Decrypt { offset })?;
+            record.value = Cow::Owned(cipher.decrypt(&record.value, offset)?);
+            record.encrypted = false;
+        }
+        if record.compressed {
+            record.value = Cow::Owned(decompress(&record.value, offset)?);
+            record.compressed = false;
+        }
+        Ok(record)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Record<'a> {
+    /// The key as given by the user, i.e. not escaped.
+    pub key: Cow<'a, str>,
+    /// The value as produced by the codec, i.e. not base64-encoded.
+    pub value: Cow<'a, [u8]>,
+    /// Unix timestamp, in milliseconds, from which the record is to be considered absent.
+    pub expires_at: Option<u64>,
+    /// Unix timestamp, in milliseconds, at which the record was written. Missing from records
+    /// written before timestamps were, and from those which haven't been written yet.
+    pub written_at: Option<u64>,
+    /// Version of the schema of the value, as set by the migrations of the store. Missing from
+    /// records written without any migration registered.
+    pub version: Option<u32>,
+    /// Whether the value is compressed, which is only ever the case while encoding or decoding.
+    pub compressed: bool,
+    /// Whether the value is encrypted, which is likewise only the case while encoding or decoding.
+    pub encrypted: bool,
+    /// Whether the value is the hash of the blob holding the actual value, which is then neither
+    /// compressed nor encrypted itself.
+    pub blob: bool,
+}
+
+impl<'a> Record<'a> {
+    pub fn new(key: &'a str, value: &'a [u8]) -> Self {
+        Self {
+            key: Cow::Borrowed(key),
+            value: Cow::Borrowed(value),
+            expires_at: None,
+            written_at: None,
+            version: None,
+            compressed: false,
+            encrypted: false,
+            blob: false,
+        }
+    }
+
+    pub fn with_expiry(mut self, expires_at: Option<u64>) -> Self {
+        self.expires_at = expires_at;
+        self
+    }
+
+    pub fn with_timestamp(mut self, written_at: Option<u64>) -> Self {
+        self.written_at = written_at;
+        self
+    }
+
+    pub fn with_version(mut self, version: Option<u32>) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Parses a line read from the database, including its trailing newline.
+    ///
+    /// A missing newline means the write of the record was torn, and invalid UTF-8 can only come
+    /// from damage to the file, so both are reported as corruption.
+    pub fn from_line(line: &'a [u8], offset: u64) -> Result<Self, Error> {
+        let line = line
+            .strip_suffix(b"\n")
+            .ok_or(Error::Corrupt { offset, line: None })?;
+        let line = std::str::from_utf8(line).map_err(|_| Error::Corrupt { offset, line: None })?;
+        Self::parse(line, offset)
+    }
+
+    /// Parses a line of the database, without its trailing newline.
+    pub fn parse(line: &'a str, offset: u64) -> Result<Self, Error> {
+        let line = verify_checksum(line, offset)?;
+
+        let mut fields = line.split('\t');
+        let (key, value) = split_key_value(fields.next().unwrap_or_default(), offset)?;
+        let key = decode_key(key).ok_or_else(|| line_error(offset, line))?;
+
+        let mut record = Record {
+            key,
+            value: Cow::Borrowed(value.as_bytes()),
+            expires_at: None,
+            written_at: None,
+            version: None,
+            compressed: false,
+            encrypted: false,
+            blob: false,
+        };
+        for field in fields {
+            let (name, field_value) = field
+                .split_once('=')
+                .ok_or_else(|| line_error(offset, line))?;
+            match name {
+                "exp" => {
+                    let expires_at = field_value.parse().map_err(|_| line_error(offset, line))?;
+                    record.expires_at = Some(expires_at);
+                }
+                "ts" => {
+                    let written_at = field_value.parse().map_err(|_| line_error(offset, line))?;
+                    record.written_at = Some(written_at);
+                }
+                "v" => {
+                    let version = field_value.parse().map_err(|_| line_error(offset, line))?;
+                    record.version = Some(version);
+                }
+                "enc" if field_value == "b64" => {
+                    let value = BASE64_STANDARD
+                        .decode(value)
+                        .map_err(|_| line_error(offset, line))?;
+                    record.value = Cow::Owned(value);
+                }
+                "cmp" if field_value == "zstd" => record.compressed = true,
+                "aead" if field_value == "xchacha20poly1305" => record.encrypted = true,
+                "blob" if field_value == "sha256" => record.blob = true,
+                // An encoding this version doesn't know of can't be read correctly.
+                "enc" | "cmp" | "aead" | "blob" => return Err(line_error(offset, line)),
+                _ => {}
+            }
+        }
+
+        Ok(record)
+    }
+
+    /// Parses a record of the binary format, including its length and checksum.
+    pub fn from_binary(raw: &'a [u8], offset: u64) -> Result<Self, Error> {
+        let corrupt = || Error::Corrupt { offset, line: None };
+
+        let (prefix, body) = raw
+            .split_at_checked(BINARY_PREFIX_LEN)
+            .ok_or_else(corrupt)?;
+        let crc = u32::from_le_bytes(prefix[4..].try_into().unwrap());
+        if binary_len(prefix) != Some(body.len()) || crc != crc32fast::hash(body) {
+            return Err(corrupt());
+        }
+
+        let (&flags, mut body) = body.split_first().ok_or_else(corrupt)?;
+        let known =
+            FLAG_EXPIRES | FLAG_ZSTD | FLAG_ENCRYPTED | FLAG_TIMESTAMP | FLAG_VERSION | FLAG_BLOB;
+        if flags & !known != 0 {
+            return Err(Error::Read(format!(
+                "Unsupported record flags at offset {offset}: {flags:#04x}"
+            )));
+        }
+        let mut expires_at = None;
+        if flags & FLAG_EXPIRES != 0 {
+            let (expiry, rest) = body.split_at_checked(8).ok_or_else(corrupt)?;
+            expires_at = Some(u64::from_le_bytes(expiry.try_into().unwrap()));
+            body = rest;
+        }
+        let mut written_at = None;
+        if flags & FLAG_TIMESTAMP != 0 {
+            let (timestamp, rest) = body.split_at_checked(8).ok_or_else(corrupt)?;
+            written_at = Some(u64::from_le_bytes(timestamp.try_into().unwrap()));
+            body = rest;
+        }
+        let mut version = None;
+        if flags & FLAG_VERSION != 0 {
+            let (bytes, rest) = body.split_at_checked(4).ok_or_else(corrupt)?;
+            version = Some(u32::from_le_bytes(bytes.try_into().unwrap()));
+            body = rest;
+        }
+        let (key_len, body) = body.split_at_checked(4).ok_or_else(corrupt)?;
+        let key_len = u32::from_le_bytes(key_len.try_into().unwrap()) as usize;
+        let (key, value) = body.split_at_checked(key_len).ok_or_else(corrupt)?;
+        let key = std::str::from_utf8(key).map_err(|_| corrupt())?;
+
+        let mut record = Record::new(key, value)
+            .with_expiry(expires_at)
+            .with_timestamp(written_at)
+            .with_version(version);
+        record.compressed = flags & FLAG_ZSTD != 0;
+        record.encrypted = flags & FLAG_ENCRYPTED != 0;
+        record.blob = flags & FLAG_BLOB != 0;
+        Ok(record)
+    }
+
+    /// Parses a line of the NDJSON format, including its trailing newline.
+    pub fn from_json(line: &'a [u8], offset: u64) -> Result<Self, Error> {
+        let line = line
+            .strip_suffix(b"\n")
+            .ok_or(Error::Corrupt { offset, line: None })?;
+        let line = std::str::from_utf8(line).map_err(|_| Error::Corrupt { offset, line: None })?;
+        if let Some((body, crc)) = line
+            .strip_suffix("\"}")
+            .and_then(|rest| rest.rsplit_once(",\"crc\":\""))
+        {
+            match u32::from_str_radix(crc, 16) {
+                Ok(crc) if crc == crc32fast::hash(body.as_bytes()) => {}
+                _ => return Err(line_error(offset, line)),
+            }
+        }
+
+        let fields: BTreeMap<Cow<str>, &'a RawValue> =
+            serde_json::from_str(line).map_err(|_| line_error(offset, line))?;
+        let parse = |raw: &RawValue| raw.get().parse().map_err(|_| line_error(offset, line));
+        let string = |raw: &RawValue| {
+            serde_json::from_str::<String>(raw.get()).map_err(|_| line_error(offset, line))
+        };
+        let version = |raw: &RawValue| raw.get().parse().map_err(|_| line_error(offset, line));
+
+        let mut key = None;
+        let mut value = None;
+        let mut record = Record::new("", b"");
+        let mut base64 = false;
+        for (name, field) in fields {
+            match &*name {
+                "k" => key = Some(string(field)?),
+                "v" => value = Some(field.get().as_bytes()),
+                "exp" => record.expires_at = Some(parse(field)?),
+                "ts" => record.written_at = Some(parse(field)?),
+                "ver" => record.version = Some(version(field)?),
+                "enc" if string(field)? == "b64" => base64 = true,
+                "cmp" if string(field)? == "zstd" => record.compressed = true,
+                "aead" if string(field)? == "xchacha20poly1305" => record.encrypted = true,
+                "blob" if string(field)? == "sha256" => record.blob = true,
+                // An encoding this version doesn't know of can't be read correctly.
+                "enc" | "cmp" | "aead" | "blob" => return Err(line_error(offset, line)),
+                _ => {}
+            }
+        }
+        let (Some(key), Some(value)) = (key, value) else {
+            return Err(line_error(offset, line));
+        };
+        record.key = Cow::Owned(key);
+        record.value = if base64 {
+            let value = serde_json::from_slice::<String>(value)
+                .ok()
+                .and_then(|value| BASE64_STANDARD.decode(value).ok())
+                .ok_or_else(|| line_error(offset, line))?;
+            Cow::Owned(value)
+        } else if record.blob {
+            let hash = serde_json::from_slice::<String>(value);
+            Cow::Owned(hash.map_err(|_| line_error(offset, line))?.into_bytes())
+        } else {
+            Cow::Borrowed(value)
+        };
+        Ok(record)
+    }
+
+    /// Appends the line for this record, including the trailing newline, to the buffer.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        buf.extend_from_slice(encode_key(&self.key).as_bytes());
+        buf.push(b',');
+        let text = std::str::from_utf8(&self.value)
+            .ok()
+            .filter(|text| !text.contains(['\t', '\n']));
+        match text {
+            Some(text) => buf.extend_from_slice(text.as_bytes()),
+            None => buf.extend_from_slice(BASE64_STANDARD.encode(&self.value).as_bytes()),
+        }
+        if let Some(expires_at) = self.expires_at {
+            let _ = write!(buf, "\texp={expires_at}");
+        }
+        if let Some(written_at) = self.written_at {
+            let _ = write!(buf, "\tts={written_at}");
+        }
+        if let Some(version) = self.version {
+            let _ = write!(buf, "\tv={version}");
+        }
+        if text.is_none() {
+            buf.extend_from_slice(b"\tenc=b64");
+        }
+        if self.compressed {
+            buf.extend_from_slice(b"\tcmp=zstd");
+        }
+        if self.encrypted {
+            buf.extend_from_slice(b"\taead=xchacha20poly1305");
+        }
+        if self.blob {
+            buf.extend_from_slice(b"\tblob=sha256");
+        }
+        let crc = crc32fast::hash(&buf[start..]);
+        let _ = writeln!(buf, "\tcrc={crc:08x}");
+    }
+
+    /// Appends the line for this record in the NDJSON format, including the trailing newline, to
+    /// the buffer.
+    pub fn encode_json(&self, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        buf.extend_from_slice(b"{\"k\":");
+        // Writing a string to a vector can't fail.
+        let _ = serde_json::to_writer(&mut *buf, &*self.key);
+        buf.extend_from_slice(b",\"v\":");
+        let json = serde_json::from_slice::<&RawValue>(&self.value)
+            .is_ok_and(|raw| raw.get().len() == self.value.len() && !raw.get().contains('\n'));
+        if self.blob {
+            let _ = serde_json::to_writer(&mut *buf, &*String::from_utf8_lossy(&self.value));
+        } else if json {
+            buf.extend_from_slice(&self.value);
+        } else {
+            let _ = write!(buf, "\"{}\"", BASE64_STANDARD.encode(&self.value));
+        }
+        if let Some(expires_at) = self.expires_at {
+            let _ = write!(buf, ",\"exp\":{expires_at}");
+        }
+        if let Some(written_at) = self.written_at {
+            let _ = write!(buf, ",\"ts\":{written_at}");
+        }
+        if let Some(version) = self.version {
+            let _ = write!(buf, ",\"ver\":{version}");
+        }
+        if !json && !self.blob {
+            buf.extend_from_slice(b",\"enc\":\"b64\"");
+        }
+        if self.compressed {
+            buf.extend_from_slice(b",\"cmp\":\"zstd\"");
+        }
+        if self.encrypted {
+            buf.extend_from_slice(b",\"aead\":\"xchacha20poly1305\"");
+        }
+        if self.blob {
+            buf.extend_from_slice(b",\"blob\":\"sha256\"");
+        }
+        let crc = crc32fast::hash(&buf[start..]);
+        let _ = writeln!(buf, ",\"crc\":\"{crc:08x}\"}}");
+    }
+
+    /// Appends this record in the binary format to the buffer.
+    pub fn encode_binary(&self, buf: &mut Vec<u8>) -> Result<(), Error> {
+        let too_large = |_| Error::Write(format!("Record for key `{}` is too large", self.key));
+
+        let start = buf.len();
+        buf.extend_from_slice(&[0; BINARY_PREFIX_LEN]);
+        let mut flags = 0;
+        if self.expires_at.is_some() {
+            flags |= FLAG_EXPIRES;
+        }
+        if self.compressed {
+            flags |= FLAG_ZSTD;
+        }
+        if self.encrypted {
+            flags |= FLAG_ENCRYPTED;
+        }
+        if self.written_at.is_some() {
+            flags |= FLAG_TIMESTAMP;
+        }
+        if self.version.is_some() {
+            flags |= FLAG_VERSION;
+        }
+        if self.blob {
+            flags |= FLAG_BLOB;
+        }
+        buf.push(flags);
+        if let Some(expires_at) = self.expires_at {
+            buf.extend_from_slice(&expires_at.to_le_bytes());
+        }
+        if let Some(written_at) = self.written_at {
+            buf.extend_from_slice(&written_at.to_le_bytes());
+        }
+        if let Some(version) = self.version {
+            buf.extend_from_slice(&version.to_le_bytes());
+        }
+        let key_len = u32::try_from(self.key.len()).map_err(too_large)?;
+        buf.extend_from_slice(&key_len.to_le_bytes());
+        buf.extend_from_slice(self.key.as_bytes());
+        buf.extend_from_slice(&self.value);
+
+        let body = &buf[start + BINARY_PREFIX_LEN..];
+        let len = u32::try_from(body.len()).map_err(too_large)?;
+        let crc = crc32fast::hash(body);
+        buf[start..start + 4].copy_from_slice(&len.to_le_bytes());
+        buf[start + 4..start + BINARY_PREFIX_LEN].copy_from_slice(&crc.to_le_bytes());
+        Ok(())
+    }
+
+    pub fn is_tombstone(&self) -> bool {
+        *self.value == *TOMBSTONE
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// Owned counterpart of [`Record`], for records which are staged before being written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct OwnedRecord {
+    pub key: String,
+    pub value: Vec<u8>,
+    pub expires_at: Option<u64>,
+    pub written_at: Option<u64>,
+    pub version: Option<u32>,
+    pub blob: bool,
+}
+
+impl From<Record<'_>> for OwnedRecord {
+    fn from(record: Record) -> Self {
+        Self {
+            key: record.key.into_owned(),
+            value: record.value.into_owned(),
+            expires_at: record.expires_at,
+            written_at: record.written_at,
+            version: record.version,
+            blob: record.blob,
+        }
+    }
+}
+
+impl OwnedRecord {
+    pub fn as_record(&self) -> Record<'_> {
+        let mut record = Record::new(&self.key, &self.value)
+            .with_expiry(self.expires_at)
+            .with_timestamp(self.written_at)
+            .with_version(self.version);
+        record.blob = self.blob;
+        record
+    }
+}
+
+/// Returns the record with its value compressed, if the settings call for it and it's worth it.
+#[cfg_attr(not(feature = "zstd"), allow(unused_variables))]
+fn compress<'a>(
+    record: &Record<'a>,
+    compression: Compression,
+) -> Result<Option<Record<'a>>, Error> {
+    match compression {
+        Compression::None => Ok(None),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd { threshold, level } => {
+            if record.is_tombstone() || record.blob || record.value.len() < threshold {
+                return Ok(None);
+            }
+            let value = zstd::encode_all(&*record.value, level)?;
+            if value.len() >= record.value.len() {
+                return Ok(None);
+            }
+            Ok(Some(Record {
+                value: Cow::Owned(value),
+                compressed: true,
+                ..record.clone()
+            }))
+        }
+    }
+}
+
+/// Returns the record with its value encrypted, if there is a cipher. Tombstones are left as-is so
+/// that they can be recognized without the key, and so are references to blobs, which are
+/// encrypted themselves.
+fn encrypt<'a>(record: &Record<'a>, cipher: Option<&Cipher>) -> Result<Option<Record<'a>>, Error> {
+    match cipher {
+        Some(cipher) if !record.is_tombstone() && !record.blob => Ok(Some(Record {
+            value: Cow::Owned(cipher.encrypt(&record.value)?),
+            encrypted: true,
+            ..record.clone()
+        })),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(feature = "zstd")]
+fn decompress(value: &[u8], _offset: u64) -> Result<Vec<u8>, Error> {
+    Ok(zstd::decode_all(value)?)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decompress(_value: &[u8], offset: u64) -> Result<Vec<u8>, Error> {
+    Err(Error::Read(format!(
+        "Record at offset {offset} is compressed with zstd, which requires the `zstd` feature"
+    )))
+}
+
+/// Length of the body of a binary record, read from its prefix.
+fn binary_len(raw: &[u8]) -> Option<usize> {
+    let len = raw.get(..4)?;
+    Some(u32::from_le_bytes(len.try_into().unwrap()) as usize)
+}
+
+fn needs_escape(c: char) -> bool {
+    matches!(c, '%' | ',') || c.is_control()
+}
+
+/// Percent-encodes the characters of the key which can't be written as-is.
+pub(crate) fn encode_key(key: &str) -> Cow<'_, str> {
+    if !key.contains(needs_escape) {
+        return Cow::Borrowed(key);
+    }
+
+    let mut encoded = String::with_capacity(key.len() + 8);
+    for c in key.chars() {
+        if needs_escape(c) {
+            let mut bytes = [0; 4];
+            for byte in c.encode_utf8(&mut bytes).bytes() {
+                let _ = write!(encoded, "%{byte:02X}");
+            }
+        } else {
+            encoded.push(c);
+        }
+    }
+    Cow::Owned(encoded)
+}
+
+/// Reverses [`encode_key`], returning `None` if the key isn't validly encoded.
+pub(crate) fn decode_key(key: &str) -> Option<Cow<'_, str>> {
+    if !key.contains('%') {
+        return Some(Cow::Borrowed(key));
+    }
+
+    let mut bytes = Vec::with_capacity(key.len());
+    let mut rest = key.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail
+                .get(..2)
+                .and_then(|hex| std::str::from_utf8(hex).ok())?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok().map(Cow::Owned)
+}
+
+/// Checks the trailing checksum field of the line, if there is one, and strips it.
+pub(crate) fn verify_checksum(line: &str, offset: u64) -> Result<&str, Error> {
+    let Some((body, crc)) = line.rsplit_once("\tcrc=") else {
+        return Ok(line);
+    };
+
+    match u32::from_str_radix(crc, 16) {
+        Ok(crc) if crc == crc32fast::hash(body.as_bytes()) => Ok(body),
+        _ => Err(Error::Corrupt {
+            offset,
+            line: Some(line.to_string()),
+        }),
+    }
+}
+
+fn split_key_value(line: &str, offset: u64) -> Result<(&str, &str), Error> {
+    let mut split = line.splitn(2, ',');
+    let k = split.next().ok_or_else(|| line_error(offset, line))?;
+    let v = split.next().ok_or_else(|| line_error(offset, line))?;
+
+    Ok((k, v))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes the record as a line of the text format.
+    fn line(record: &Record) -> String {
+        let mut buf = Vec::new();
+        record.encode(&mut buf);
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn separator_test() {
+        assert_eq!(("a", "b"), split_key_value("a,b", 0).unwrap());
+        assert_eq!(("a", "b,c"), split_key_value("a,b,c", 0).unwrap());
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut record = Record::new("key", br#"{"a":"b,c"}"#);
+        let buf = line(&record);
+        assert_eq!("key,{\"a\":\"b,c\"}\tcrc=892b436f\n", buf);
+        assert_eq!(record.clone(), Record::parse(buf.trim_end(), 0).unwrap());
+
+        record.expires_at = Some(1234);
+        let buf = line(&record);
+        assert!(buf.starts_with("key,{\"a\":\"b,c\"}\texp=1234\tcrc="));
+        assert_eq!(record.clone(), Record::parse(buf.trim_end(), 0).unwrap());
+
+        assert_eq!(
+            record,
+            Record::parse("key,{\"a\":\"b,c\"}\tfoo=bar\texp=1234", 0).unwrap()
+        );
+        assert!(Record::parse("key,1\texp=soon", 0).is_err());
+        assert!(Record::parse("key", 0).is_err());
+    }
+
+    #[test]
+    fn checksum() {
+        let buf = line(&Record::new("key", b"1234"));
+        let line = buf.trim_end();
+        assert!(Record::parse(line, 0).is_ok());
+
+        let flipped = line.replace("1234", "1235");
+        assert!(matches!(
+            Record::parse(&flipped, 7),
+            Err(Error::Corrupt { offset: 7, .. })
+        ));
+        let truncated = &line[..line.len() - 1];
+        assert!(matches!(
+            Record::parse(truncated, 7),
+            Err(Error::Corrupt { offset: 7, .. })
+        ));
+
+        // Records written before checksums were introduced.
+        assert_eq!(
+            Record::new("key", b"1234"),
+            Record::parse("key,1234", 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn key_escaping() {
+        assert_eq!("key with spaces", encode_key("key with spaces"));
+        assert_eq!("user@example.com", encode_key("user@example.com"));
+        assert_eq!("a%2Cb%25c%0A", encode_key("a,b%c\n"));
+        assert_eq!("héllo", encode_key("héllo"));
+
+        for key in ["", "key", "a,b%c\n", "\t%%,", "héllo\u{85}"] {
+            assert_eq!(Some(Cow::Borrowed(key)), decode_key(&encode_key(key)));
+        }
+        assert_eq!(None, decode_key("%"));
+        assert_eq!(None, decode_key("%zz"));
+        assert_eq!(None, decode_key("%FF"));
+
+        let buf = line(&Record::new("a,b", b"1"));
+        assert!(buf.starts_with("a%2Cb,1\t"));
+        assert_eq!("a,b", Record::parse(buf.trim_end(), 0).unwrap().key);
+    }
+
+    #[test]
+    fn binary_values() {
+        let record = Record::new("key", b"\x00\xff\ta\n");
+        let buf = line(&record);
+        assert!(buf.starts_with("key,AP8JYQo=\tenc=b64\tcrc="));
+        assert_eq!(record, Record::parse(buf.trim_end(), 0).unwrap());
+
+        assert!(Record::parse("key,AP8JYQo=\tenc=zstd", 0).is_err());
+        assert!(Record::parse("key,not base64\tenc=b64", 0).is_err());
+    }
+
+    #[test]
+    fn binary_format() {
+        let record = Record::new("key", b"\x00\xff\ta\n").with_expiry(Some(1234));
+        let mut buf = Vec::new();
+        record.encode_binary(&mut buf).unwrap();
+        Record::new("k2", TOMBSTONE)
+            .encode_binary(&mut buf)
+            .unwrap();
+
+        let mut reader = &buf[..];
+        let mut raw = Vec::new();
+        assert_eq!(29, Format::Binary.read(&mut reader, &mut raw).unwrap());
+        assert!(!Format::Binary.is_torn(&raw));
+        assert_eq!(record, Format::Binary.decode(&raw, 0, None).unwrap());
+
+        raw.clear();
+        Format::Binary.read(&mut reader, &mut raw).unwrap();
+        assert!(Format::Binary.decode(&raw, 0, None).unwrap().is_tombstone());
+
+        raw.truncate(raw.len() - 1);
+        assert!(Format::Binary.is_torn(&raw));
+        assert!(matches!(
+            Format::Binary.decode(&raw, 3, None),
+            Err(Error::Corrupt { offset: 3, .. })
+        ));
+        let mut flipped = buf[..29].to_vec();
+        flipped[20] ^= 1;
+        assert!(matches!(
+            Format::Binary.decode(&flipped, 0, None),
+            Err(Error::Corrupt { offset: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn ndjson_format() {
+        fn decode(line: &str) -> Result<Record<'_>, Error> {
+            Format::Ndjson.decode(line.as_bytes(), 5, None)
+        }
+        let record = Record::new("a,\"b\"", br#"{"c": [1, 2]}"#)
+            .with_expiry(Some(1234))
+            .with_timestamp(Some(1000))
+            .with_version(Some(2));
+        let mut buf = Vec::new();
+        Format::Ndjson
+            .encode(&record, Compression::None, None, &mut buf)
+            .unwrap();
+        let line = String::from_utf8(buf).unwrap();
+        assert!(line.starts_with(
+            r#"{"k":"a,\"b\"","v":{"c": [1, 2]},"exp":1234,"ts":1000,"ver":2,"crc":""#
+        ));
+        assert!(serde_json::from_str::<serde_json::Value>(&line).is_ok());
+        assert_eq!(record, decode(&line).unwrap());
+
+        let record = Record::new("key", b"\x00\xff\ta\n");
+        let mut buf = Vec::new();
+        record.encode_json(&mut buf);
+        let line = String::from_utf8(buf).unwrap();
+        assert!(line.starts_with(r#"{"k":"key","v":"AP8JYQo=","enc":"b64","crc":"#));
+        assert_eq!(record, decode(&line).unwrap());
+
+        // Fields can come in any order, and the checksum can be left out.
+        assert_eq!(
+            Record::new("key", b"null").with_timestamp(Some(1)),
+            decode("{\"ts\":1,\"v\":null,\"k\":\"key\",\"other\":[]}\n").unwrap()
+        );
+        for invalid in [
+            &line.replace("AP8", "AP9"),
+            "{\"k\":\"key\"}\n",
+            "{\"k\":1,\"v\":1}\n",
+            "{\"k\":\"key\",\"v\":1,\"cmp\":\"lz4\"}\n",
+            "{\"k\":\"key\",\"v\":1}",
+        ] {
+            assert!(
+                matches!(decode(invalid), Err(Error::Corrupt { offset: 5, .. })),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn detect_format() {
+        assert_eq!(None, Format::detect(b"").unwrap());
+        assert_eq!(Some(Format::Text), Format::detect(b"key,").unwrap());
+        assert_eq!(
+            Some(Format::Binary),
+            Format::detect(Format::Binary.header()).unwrap()
+        );
+        assert!(Format::detect(b"%kv\x03").is_err());
+    }
+}
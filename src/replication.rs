@@ -0,0 +1,181 @@
+//! Log shipping from a database to a standby copy of it.
+//!
+//! A [`Replicator`] follows the log of a database like a [`Tail`] does, and sends every record it
+//! reads to a follower, which appends them to its own database through [`Store::replicate_from`].
+//! Records are sent as lines of JSON, with their value encoded as by the codec of the database in
+//! base64:
+//!
+//! ```text
+//! {"key":"a","value":"MQ==","exp":1700000000000,"ts":1690000000000}
+//! {"key":"b","value":null,"ts":1690000000000}
+//! ```
+//!
+//! where a `null` value unsets the key, `ts` is the time at which the record was written, `exp` is
+//! only present for keys set with a time to live, and `v` for records written with migrations.
+//! Records are never changed once written, and replaying them in order always yields the same
+//! result, so resuming from a [`Position`] saved a little too early is harmless.
+
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use serde_json::{json, Value};
+
+use crate::record::{OwnedRecord, TOMBSTONE};
+use crate::tail::Position;
+use crate::{Error, Store, Tail};
+
+/// Ships the records appended to a database to a follower, as described in the [module
+/// documentation](self).
+///
+/// Like a [`Tail`], it doesn't need the database to be opened as a [`Store`], so it can run next to
+/// the process writing to it, and can't ship an encrypted database.
+pub struct Replicator<W: Write> {
+    tail: Tail<()>,
+    writer: BufWriter<W>,
+}
+
+impl<W: Write> Replicator<W> {
+    /// Starts shipping the records of the database at the given path to the writer, typically a
+    /// `TcpStream` connected to the follower, from the given position or from the start.
+    pub fn new(path: &Path, from: Option<Position>, writer: W) -> Result<Self, Error> {
+        Ok(Self {
+            tail: Tail::open_at(path, from.unwrap_or_default())?,
+            writer: BufWriter::new(writer),
+        })
+    }
+
+    /// Returns how far the records were shipped, from which a new replicator can resume.
+    pub fn position(&self) -> Position {
+        self.tail.position()
+    }
+
+    /// Sends the records appended since the last call, without blocking on new ones, and returns
+    /// how many there were.
+    pub fn ship(&mut self) -> Result<usize, Error> {
+        let records = self.tail.poll_records()?;
+        for record in &records {
+            let mut frame = json!({ "key": record.key, "value": Value::Null });
+            if record.value != TOMBSTONE {
+                frame["value"] = BASE64_STANDARD.encode(&record.value).into();
+            }
+            if let Some(written_at) = record.written_at {
+                frame["ts"] = written_at.into();
+            }
+            if let Some(expires_at) = record.expires_at {
+                frame["exp"] = expires_at.into();
+            }
+            if let Some(version) = record.version {
+                frame["v"] = version.into();
+            }
+            writeln!(self.writer, "{frame}")?;
+        }
+        self.writer.flush()?;
+        Ok(records.len())
+    }
+
+    /// Ships the records as they get appended, checking for new ones at the given interval, until
+    /// an error occurs.
+    pub fn run(&mut self, interval: Duration) -> Result<(), Error> {
+        loop {
+            self.ship()?;
+            std::thread::sleep(interval);
+        }
+    }
+}
+
+impl<T, C> Store<T, C> {
+    /// Appends the records shipped by a [`Replicator`] to the database as they are read, until the
+    /// reader reaches its end, and returns how many there were.
+    ///
+    /// The database should be written to by nothing else, so that it remains a faithful copy.
+    pub fn replicate_from<R: Read>(&self, reader: R) -> Result<u64, Error> {
+        let mut count = 0;
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = parse_frame(&line)
+                .ok_or_else(|| Error::Read(format!("Invalid replication frame: {line}")))?;
+            self.0.write().append(&[record.as_record()])?;
+            count += 1;
+        }
+        self.flush()?;
+        Ok(count)
+    }
+}
+
+fn parse_frame(line: &str) -> Option<OwnedRecord> {
+    let frame: Value = serde_json::from_str(line).ok()?;
+    let value = match &frame["value"] {
+        Value::Null => TOMBSTONE.to_vec(),
+        value => BASE64_STANDARD.decode(value.as_str()?).ok()?,
+    };
+    let expires_at = match &frame["exp"] {
+        Value::Null => None,
+        exp => Some(exp.as_u64()?),
+    };
+    let written_at = match &frame["ts"] {
+        Value::Null => None,
+        ts => Some(ts.as_u64()?),
+    };
+    let version = match &frame["v"] {
+        Value::Null => None,
+        v => Some(u32::try_from(v.as_u64()?).ok()?),
+    };
+    Some(OwnedRecord {
+        key: frame["key"].as_str()?.to_string(),
+        value,
+        expires_at,
+        written_at,
+        version,
+        blob: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn replicate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let leader = Store::<u32>::options().write_buffer(0).open(&path).unwrap();
+        leader.set("a", &1).unwrap();
+        leader
+            .set_with_ttl("b", &2, Duration::from_secs(60))
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut replicator = Replicator::new(&path, None, stream).unwrap();
+        assert_eq!(2, replicator.ship().unwrap());
+        leader.unset("a").unwrap();
+        leader.set("c", &3).unwrap();
+        assert_eq!(2, replicator.ship().unwrap());
+        let position = replicator.position();
+        drop(replicator);
+
+        let follower = Store::<u32>::in_memory();
+        let (stream, _) = listener.accept().unwrap();
+        assert_eq!(4, follower.replicate_from(stream).unwrap());
+        assert_eq!(vec!["b", "c"], follower.keys().collect::<Vec<_>>());
+        assert_eq!(Some(2), follower.get("b").unwrap());
+
+        leader.set("d", &4).unwrap();
+        let mut shipped = Vec::new();
+        let mut replicator = Replicator::new(&path, Some(position), &mut shipped).unwrap();
+        assert_eq!(1, replicator.ship().unwrap());
+        drop(replicator);
+        assert_eq!(1, follower.replicate_from(&shipped[..]).unwrap());
+        assert_eq!(Some(4), follower.get("d").unwrap());
+
+        assert!(follower.replicate_from(&b"{\"key\":1}\n"[..]).is_err());
+    }
+}
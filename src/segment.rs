@@ -0,0 +1,243 @@
+//! Splitting of the log into segments.
+//!
+//! A segmented database is made of files named after the database followed by a sequence number,
+//! e.g. `db.0001`, `db.0002`, and so on, which are read in order. Writes only ever go to the last
+//! segment, and a new one is started once it grows past the configured size. Since older segments
+//! are never written to again, they can be deleted as a whole once none of their records hold the
+//! current value of a key.
+//!
+//! A database which isn't segmented is represented as a single segment with id `0`, whose path is
+//! that of the database itself. So is a database held by a [`Backend`], whose segment has no path.
+
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::header::Header;
+use crate::{Backend, Error};
+
+pub(crate) struct Segment {
+    pub id: u32,
+    pub path: PathBuf,
+    pub storage: Box<dyn Backend>,
+    /// Length of the segment, including the pending bytes, which is also the offset at which the
+    /// next record will be written.
+    pub len: u64,
+    /// Records which were appended but not yet written to the file.
+    pub pending: Vec<u8>,
+}
+
+impl Segment {
+    pub fn open(id: u32, path: PathBuf) -> io::Result<Self> {
+        let file = crate::open_file(&path)?;
+        Self::new(id, path, Box::new(file))
+    }
+
+    /// Opens an existing segment without write access, for a follower, which indexes it from the
+    /// start.
+    pub fn open_read_only(id: u32, path: PathBuf) -> io::Result<Self> {
+        let file = std::fs::File::open(&path)?;
+        Ok(Self {
+            id,
+            path,
+            storage: Box::new(file),
+            len: 0,
+            pending: Vec::new(),
+        })
+    }
+
+    pub fn new(id: u32, path: PathBuf, storage: Box<dyn Backend>) -> io::Result<Self> {
+        let len = storage.len()?;
+        Ok(Self {
+            id,
+            path,
+            storage,
+            len,
+            pending: Vec::new(),
+        })
+    }
+
+    /// Appends bytes to the segment, buffering them in memory until there are at least `capacity`
+    /// of them. The bytes are always written to the file with a single write.
+    pub fn append(&mut self, buf: &[u8], capacity: usize) -> io::Result<()> {
+        if buf.len() >= capacity {
+            // Written along with the pending bytes, with a single system call where possible.
+            self.storage.append_vectored(&[&self.pending, buf])?;
+            self.pending.clear();
+        } else {
+            if self.pending.len() + buf.len() > capacity {
+                self.flush()?;
+            }
+            self.pending.extend_from_slice(buf);
+        }
+        self.len += buf.len() as u64;
+        Ok(())
+    }
+
+    /// Writes the pending bytes to the file.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if !self.pending.is_empty() {
+            self.storage.append(&self.pending)?;
+            self.pending.clear();
+        }
+        Ok(())
+    }
+
+    /// Returns a reader starting at the given offset, which also sees the pending bytes.
+    ///
+    /// Reads are positional and leave the cursor of the file alone, so any number of readers can
+    /// be used at once, including while records are being appended.
+    pub fn reader_at(&self, offset: u64) -> ReadAt<'_> {
+        ReadAt {
+            storage: &*self.storage,
+            offset,
+            file_len: self.len - self.pending.len() as u64,
+            pending: &self.pending,
+        }
+    }
+
+    /// Reads the header the segment starts with, including the bytes not indexed yet by a
+    /// follower. See [`Header::read`].
+    pub fn read_header(&self) -> Result<Option<(Header, Vec<u8>)>, Error> {
+        let reader = ReadAt {
+            storage: &*self.storage,
+            offset: 0,
+            file_len: self.storage.len()?,
+            pending: &[],
+        };
+        Header::read(io::BufReader::new(reader))
+    }
+
+    /// Writes the header of the database if the segment is empty.
+    pub fn init(&mut self, header: &[u8]) -> io::Result<()> {
+        if self.len == 0 {
+            self.storage.append(header)?;
+            self.len = header.len() as u64;
+        }
+        Ok(())
+    }
+}
+
+/// Reader over a segment which doesn't depend on the file's cursor.
+pub(crate) struct ReadAt<'a> {
+    storage: &'a dyn Backend,
+    offset: u64,
+    /// Length of the part of the segment which was written to the file.
+    file_len: u64,
+    pending: &'a [u8],
+}
+
+impl Read for ReadAt<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = match self.offset.checked_sub(self.file_len) {
+            Some(start) => self
+                .pending
+                .get(start as usize..)
+                .unwrap_or_default()
+                .read(buf)?,
+            None => {
+                let len = buf.len().min((self.file_len - self.offset) as usize);
+                self.storage.read_at(&mut buf[..len], self.offset)?
+            }
+        };
+        self.offset += read as u64;
+        Ok(read)
+    }
+}
+
+/// Path of the segment with the given id.
+pub(crate) fn segment_path(path: &Path, id: u32) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(format!(".{id:04}"));
+    path.with_file_name(file_name)
+}
+
+/// Path of the file holding the lock of a segmented database, which also records the process
+/// owning any database.
+pub(crate) fn lock_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".lock");
+    path.with_file_name(file_name)
+}
+
+/// Records the current process as the owner of the database, once it holds its lock.
+///
+/// The record is only read when the lock is held, so one left behind by a process which crashed,
+/// and whose lock the operating system released, is simply overwritten by the next owner.
+pub(crate) fn write_owner(path: &Path) -> io::Result<()> {
+    std::fs::write(lock_path(path), format!("pid={}\n", std::process::id()))
+}
+
+/// Returns the id of the process recorded as owning the database, if any.
+pub(crate) fn read_owner(path: &Path) -> Option<u32> {
+    let owner = std::fs::read_to_string(lock_path(path)).ok()?;
+    owner.trim_end().strip_prefix("pid=")?.parse().ok()
+}
+
+/// Returns the ids of the existing segments of the database, in order.
+pub(crate) fn list_segments(path: &Path) -> io::Result<Vec<u32>> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+    let mut ids = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let file_name = entry?.file_name();
+        let Some(suffix) = file_name
+            .to_str()
+            .and_then(|file_name| file_name.strip_prefix(&*name))
+            .and_then(|rest| rest.strip_prefix('.'))
+        else {
+            continue;
+        };
+        if !suffix.is_empty() && suffix.bytes().all(|b| b.is_ascii_digit()) {
+            if let Ok(id) = suffix.parse::<u32>() {
+                if id > 0 {
+                    ids.push(id);
+                }
+            }
+        }
+    }
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn list() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        for name in [
+            "db.0002",
+            "db.0010",
+            "db.0001",
+            "db.lock",
+            "db.compact",
+            "db2.0003",
+            "db",
+        ] {
+            File::create(dir.path().join(name)).unwrap();
+        }
+        assert_eq!(vec![1, 2, 10], list_segments(&path).unwrap());
+        assert_eq!(dir.path().join("db.0012"), segment_path(&path, 12));
+    }
+
+    #[test]
+    fn append() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let mut segment = Segment::open(0, path.clone()).unwrap();
+        segment.append(b"small,", 8).unwrap();
+        assert!(std::fs::read(&path).unwrap().is_empty());
+        // Large writes go out along with the pending bytes.
+        segment.append(b"large enough", 8).unwrap();
+        assert_eq!(b"small,large enough", &*std::fs::read(&path).unwrap());
+        assert!(segment.pending.is_empty());
+        assert_eq!(18, segment.len);
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::{Codec, Error, JsonCodec, Location, Store};
+
+/// A read-only view of a [`Store`] as it was at a single point in time.
+///
+/// Created by [`Store::snapshot`], or by [`Store::as_of`] for an earlier point in time. Writes made
+/// after that point, through any clone of the store, aren't visible through it, so reads of several
+/// keys are consistent with each other. Keys which expire after that point are still visible
+/// through it.
+pub struct Snapshot<T, C = JsonCodec> {
+    store: Store<T, C>,
+    /// Location of the latest record of every key which was live when the snapshot was taken.
+    index: BTreeMap<String, Location>,
+    _pin: Arc<()>,
+}
+
+impl<T, C> Snapshot<T, C> {
+    pub(crate) fn new(store: Store<T, C>, index: BTreeMap<String, Location>, pin: Arc<()>) -> Self {
+        Self {
+            store,
+            index,
+            _pin: pin,
+        }
+    }
+
+    /// Checks whether the given key held a value.
+    pub fn contains(&self, key: &str) -> bool {
+        self.index.contains_key(key)
+    }
+
+    /// Returns the keys that held a value, in lexicographic order.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.index.keys().map(String::as_str)
+    }
+}
+
+impl<T, C> Snapshot<T, C>
+where
+    T: for<'a> Deserialize<'a>,
+    C: Codec,
+{
+    /// Retrieves the value that was associated with a key.
+    pub fn get(&self, key: &str) -> Result<Option<T>, Error> {
+        match self.index.get(key) {
+            Some(&location) => self.read(location).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns an iterator over the entries which were live, in lexicographic key order.
+    pub fn iter(&self) -> impl Iterator<Item = Result<(&str, T), Error>> {
+        self.index
+            .iter()
+            .map(|(key, &location)| Ok((key.as_str(), self.read(location)?)))
+    }
+
+    fn read(&self, location: Location) -> Result<T, Error> {
+        // The cache only holds the latest values, so it's bypassed.
+        let value = self.store.0.read().read_at(location)?;
+        C::decode(&value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use super::*;
+
+    #[test]
+    fn snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::<u32>::options()
+            .segment_size(64)
+            .open(&dir.path().join("db"))
+            .unwrap();
+        store.set("a", &1).unwrap();
+        store.set("b", &2).unwrap();
+
+        let snapshot = store.snapshot();
+        store.set("a", &3).unwrap();
+        store.unset("b").unwrap();
+        store.set("c", &4).unwrap();
+        for i in 0..20 {
+            store.set("d", &i).unwrap();
+        }
+
+        assert_eq!(Some(1), snapshot.get("a").unwrap());
+        assert_eq!(Some(2), snapshot.get("b").unwrap());
+        assert!(!snapshot.contains("c"));
+        assert_eq!(
+            vec![("a", 1), ("b", 2)],
+            snapshot.iter().collect::<Result<Vec<_>, _>>().unwrap()
+        );
+        assert!(store.compact().is_err());
+
+        drop(snapshot);
+        store.compact().unwrap();
+        assert_eq!(Some(3), store.get("a").unwrap());
+    }
+
+    #[test]
+    fn as_of() {
+        let store = Store::<u32>::in_memory();
+        store.set("a", &1).unwrap();
+        store.set("b", &2).unwrap();
+        let sleep = || std::thread::sleep(Duration::from_millis(2));
+        sleep();
+        let before = SystemTime::now();
+        sleep();
+        store.set("a", &3).unwrap();
+        store.unset("b").unwrap();
+        store.set("c", &4).unwrap();
+
+        let past = store.as_of(before).unwrap();
+        assert_eq!(
+            vec![("a", 1), ("b", 2)],
+            past.iter().collect::<Result<Vec<_>, _>>().unwrap()
+        );
+        assert!(store.as_of(UNIX_EPOCH).unwrap().keys().next().is_none());
+        let now = store.as_of(SystemTime::now()).unwrap();
+        assert_eq!(vec!["a", "c"], now.keys().collect::<Vec<_>>());
+        assert_eq!(Some(3), now.get("a").unwrap());
+    }
+}
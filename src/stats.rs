@@ -0,0 +1,16 @@
+/// Statistics about a [`Store`](crate::Store), as returned by [`Store::stats`](crate::Store::stats).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stats {
+    /// Size of the database in bytes, across all its segments, including the writes which are
+    /// still buffered in memory.
+    pub size: u64,
+    /// Number of records in the database, live or not.
+    pub records: u64,
+    /// Number of keys which currently hold a value.
+    pub live_keys: usize,
+    /// Number of records which unset a key.
+    pub tombstones: u64,
+    /// Fraction of the records which don't hold the current value of a key, and would be dropped by
+    /// a compaction.
+    pub dead_ratio: f64,
+}
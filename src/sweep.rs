@@ -0,0 +1,115 @@
+//! Unsetting of the keys whose time to live has elapsed, so that they stop taking room in the index
+//! before the next compaction, either on demand or in the background as set with
+//! [`OpenOptions::sweep_interval`](crate::OpenOptions::sweep_interval).
+//!
+//! Expired keys are unset with tombstones which keep the expiry of the record they replace, so that
+//! [`Event::Expired`](crate::Event::Expired) can be told apart from a key being unset explicitly.
+
+use std::sync::{mpsc, Weak};
+use std::time::Duration;
+
+use parking_lot::RwLock;
+
+use crate::record::{Record, TOMBSTONE};
+use crate::{now_millis, Error, Store, StoreInner};
+
+impl StoreInner {
+    /// Unsets the keys which have expired, returning how many there were.
+    pub(crate) fn sweep_expired(&mut self) -> Result<usize, Error> {
+        let now = now_millis();
+        let expired: Vec<(String, Option<u64>)> = self
+            .index
+            .iter()
+            .filter(|(_, slot)| !slot.is_live(now))
+            .map(|(key, slot)| (key.clone(), slot.expires_at))
+            .collect();
+        if expired.is_empty() {
+            return Ok(0);
+        }
+        let records: Vec<Record> = expired
+            .iter()
+            .map(|(key, expires_at)| Record::new(key, TOMBSTONE).with_expiry(*expires_at))
+            .collect();
+        self.append(&records)?;
+        Ok(expired.len())
+    }
+}
+
+impl<T, C> Store<T, C> {
+    /// Unsets every key whose time to live has elapsed, and returns how many there were.
+    ///
+    /// Expired keys are already treated as absent by every read, but stay in the in-memory index
+    /// until the next compaction otherwise. The tombstones are appended with a single write, and
+    /// reported as [`Event::Expired`](crate::Event::Expired) to watchers.
+    pub fn sweep_expired(&self) -> Result<usize, Error> {
+        self.0.write().sweep_expired()
+    }
+}
+
+/// Spawns the thread sweeping the expired keys at the given interval.
+///
+/// The thread exits once the store is dropped, which also drops the returned sender.
+pub(crate) fn spawn_sweeper(
+    inner: Weak<RwLock<StoreInner>>,
+    interval: Duration,
+) -> mpsc::Sender<()> {
+    let (stop, stopped) = mpsc::channel();
+    std::thread::spawn(move || {
+        while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+            let Some(inner) = inner.upgrade() else {
+                break;
+            };
+            // There is no one to report errors to, and the next sweep will try again anyway.
+            let _ = inner.write().sweep_expired();
+        }
+    });
+    stop
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{Event, Store};
+
+    #[test]
+    fn sweep_expired() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let store = Store::<u32>::open(&path).unwrap();
+        let events = store.watch("");
+        store.set("a", &1).unwrap();
+        store
+            .set_with_ttl("b", &2, Duration::from_millis(1))
+            .unwrap();
+        store
+            .set_with_ttl("c", &3, Duration::from_secs(60))
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(1, store.sweep_expired().unwrap());
+        assert_eq!(0, store.sweep_expired().unwrap());
+        assert_eq!(2, store.0.read().index.len());
+        let events: Vec<_> = events.try_iter().collect();
+        assert_eq!(
+            Event::Expired {
+                key: "b".to_string()
+            },
+            events[3]
+        );
+        drop(store);
+
+        // Swept keys stay unset once the database is opened again.
+        let store = Store::<u32>::open(&path).unwrap();
+        assert_eq!(vec!["a", "c"], store.keys().collect::<Vec<_>>());
+
+        let store = Store::<u32>::options()
+            .sweep_interval(Duration::from_millis(10))
+            .open(&dir.path().join("swept"))
+            .unwrap();
+        store
+            .set_with_ttl("a", &1, Duration::from_millis(1))
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(store.0.read().index.is_empty());
+    }
+}
@@ -0,0 +1,281 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufReader, Seek, SeekFrom};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::blob::read_blob;
+use crate::header::Header;
+use crate::record::OwnedRecord;
+use crate::segment::{list_segments, segment_path};
+use crate::{Codec, Error, Event, Format, JsonCodec};
+
+/// Follows the records appended to a database, like `tail -f`, including those written by other
+/// processes.
+///
+/// Unlike [`Store::watch`](crate::Store::watch), a tail doesn't need the database to be opened as
+/// a [`Store`](crate::Store), so it takes no lock and can follow a database owned by another
+/// process. New records are picked up by calling [`Tail::poll`].
+///
+/// Values can't be decrypted by a tail, so it can't follow an encrypted database.
+///
+/// Compactions produce no events, but compacting a segmented database writes the live values to
+/// new segments, which are then reported again.
+pub struct Tail<T, C = JsonCodec> {
+    path: PathBuf,
+    /// Id of the segment being followed, `0` if the database isn't segmented.
+    segment: u32,
+    /// Offset of the next record to be read from the segment.
+    offset: u64,
+    /// Detected once the database holds data, along with the length of its header.
+    format: Option<(Format, u64)>,
+    /// Generation of the database the offset points into.
+    generation: u64,
+    _phantom: PhantomData<fn() -> (T, C)>,
+}
+
+/// How far a [`Tail`] has read into a database, from which another one can resume.
+///
+/// Positions point into the files of the database, so they are invalidated by a compaction. The
+/// default one is the start of a database which was never compacted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Position {
+    /// Id of the segment, `0` if the database isn't segmented.
+    pub segment: u32,
+    /// Offset of the next record to be read from the segment.
+    pub offset: u64,
+    /// Number of times the database was compacted, which only changes for databases which aren't
+    /// segmented, since compacting a segmented one replaces its segments with new ones.
+    pub generation: u64,
+}
+
+impl<T, C> Tail<T, C> {
+    /// Starts following the database at the given path from its current end.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let segment = list_segments(path)?.last().copied().unwrap_or(0);
+        let mut tail = Self {
+            path: path.to_path_buf(),
+            segment,
+            offset: 0,
+            format: None,
+            generation: 0,
+            _phantom: PhantomData,
+        };
+        if let Some(file) = tail.open_segment()? {
+            tail.offset = file.metadata()?.len();
+        }
+        Ok(tail)
+    }
+
+    /// Starts following the database at the given path from a position returned by
+    /// [`Tail::position`], so that nothing written since is missed.
+    ///
+    /// Following starts over from the end of the file if it was compacted since the position was
+    /// returned, unless the position is the start of the file.
+    pub fn open_at(path: &Path, position: Position) -> Result<Self, Error> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            segment: position.segment,
+            offset: position.offset,
+            format: None,
+            generation: position.generation,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Returns how far the tail has read.
+    pub fn position(&self) -> Position {
+        Position {
+            segment: self.segment,
+            offset: self.offset,
+            generation: self.generation,
+        }
+    }
+
+    /// Opens the segment being followed, detecting the format of the database if needed, and
+    /// moving to the end of the file if it isn't segmented and was compacted since it was read.
+    fn open_segment(&mut self) -> Result<Option<File>, Error> {
+        let path = match self.segment {
+            0 => self.path.clone(),
+            id => segment_path(&self.path, id),
+        };
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        if self.format.is_none() || self.segment == 0 {
+            let Some((header, bytes)) = Header::read(BufReader::new(&file))? else {
+                self.format = None;
+                return Ok(Some(file));
+            };
+            let header_len = bytes.len() as u64;
+            if header.generation != self.generation && self.offset > header_len {
+                self.offset = file.metadata()?.len();
+            }
+            self.generation = header.generation;
+            self.format = Some((header.format, header_len));
+        }
+        Ok(Some(file))
+    }
+}
+
+impl<T, C> Tail<T, C>
+where
+    T: for<'a> Deserialize<'a>,
+    C: Codec,
+{
+    /// Returns the events for the records appended since the last call, without blocking.
+    ///
+    /// A record whose write is still in progress is left for the next call. Records whose value
+    /// can't be decoded are skipped.
+    pub fn poll(&mut self) -> Result<Vec<Event<T>>, Error> {
+        let events = self
+            .poll_records()?
+            .into_iter()
+            .filter_map(|record| Event::from_record::<C>(&record.as_record()))
+            .collect();
+        Ok(events)
+    }
+}
+
+impl<T, C> Tail<T, C> {
+    /// Returns the records appended since the last call, as they were written.
+    pub(crate) fn poll_records(&mut self) -> Result<Vec<OwnedRecord>, Error> {
+        let mut records = Vec::new();
+        loop {
+            if let Some(file) = self.open_segment()? {
+                let Some((format, header_len)) = self.format else {
+                    return Ok(records);
+                };
+                let len = file.metadata()?.len();
+                if len < self.offset {
+                    // The file was replaced by a compaction.
+                    self.offset = len;
+                }
+                self.offset = self.offset.max(header_len);
+                self.read_records(file, format, &mut records)?;
+            }
+
+            // Moves on to the next segment once a newer one exists, or if the current one doesn't,
+            // which also covers a database which was created since.
+            let next = list_segments(&self.path)?
+                .into_iter()
+                .find(|&id| id > self.segment);
+            match next {
+                Some(id) => {
+                    self.segment = id;
+                    self.offset = 0;
+                }
+                None => return Ok(records),
+            }
+        }
+    }
+
+    fn read_records(
+        &mut self,
+        mut file: File,
+        format: Format,
+        records: &mut Vec<OwnedRecord>,
+    ) -> Result<(), Error> {
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut reader = BufReader::new(file);
+        let mut raw = Vec::new();
+        loop {
+            raw.clear();
+            let read = format.read(&mut reader, &mut raw)?;
+            if read == 0 || format.is_torn(&raw) {
+                return Ok(());
+            }
+
+            let mut record = format.decode(&raw, self.offset, None)?;
+            if record.blob {
+                record.value = Cow::Owned(read_blob(&self.path, &record.value, None)?);
+                record.blob = false;
+            }
+            records.push(OwnedRecord::from(record));
+            self.offset += read as u64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Store;
+
+    #[test]
+    fn tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let mut created = Tail::<u32>::open(&path).unwrap();
+        assert!(created.poll().unwrap().is_empty());
+
+        let store = Store::<u32>::options()
+            .write_buffer(0)
+            .segment_size(64)
+            .open(&path)
+            .unwrap();
+        store.set("a", &1).unwrap();
+        let mut tail = Tail::<u32>::open(&path).unwrap();
+        for i in 0..5 {
+            store.set("b", &i).unwrap();
+        }
+        store.unset("a").unwrap();
+
+        let events = tail.poll().unwrap();
+        assert_eq!(6, events.len());
+        assert_eq!(
+            Event::Set {
+                key: "b".to_string(),
+                value: 0
+            },
+            events[0]
+        );
+        assert_eq!(
+            Event::Unset {
+                key: "a".to_string()
+            },
+            events[5]
+        );
+        assert!(tail.poll().unwrap().is_empty());
+        assert_eq!(7, created.poll().unwrap().len());
+    }
+
+    #[test]
+    fn compacted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let store = Store::<u32>::options().write_buffer(0).open(&path).unwrap();
+        store.set("a", &1).unwrap();
+        let mut tail = Tail::<u32>::open(&path).unwrap();
+        let position = tail.position();
+        for i in 0..100 {
+            store.set(&format!("key {i}"), &i).unwrap();
+        }
+        assert_eq!(100, tail.poll().unwrap().len());
+
+        // The file is still longer than the position once compacted.
+        store.compact().unwrap();
+        assert!(tail.poll().unwrap().is_empty());
+        store.set("b", &2).unwrap();
+        let expected = vec![Event::Set {
+            key: "b".to_string(),
+            value: 2,
+        }];
+        assert_eq!(expected, tail.poll().unwrap());
+        let mut resumed = Tail::<u32>::open_at(&path, position).unwrap();
+        assert!(resumed.poll().unwrap().is_empty());
+        assert_eq!(1, resumed.position().generation);
+        assert_eq!(
+            102,
+            Tail::<u32>::open_at(&path, Position::default())
+                .unwrap()
+                .poll()
+                .unwrap()
+                .len()
+        );
+    }
+}
@@ -0,0 +1,76 @@
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::record::Record;
+use crate::Codec;
+
+/// A change made to a [`Store`](crate::Store), as reported by [`Store::watch`](crate::Store::watch).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event<T> {
+    Set {
+        key: String,
+        value: T,
+    },
+    Unset {
+        key: String,
+    },
+    /// The key was unset by [`Store::sweep_expired`](crate::Store::sweep_expired) once its time to
+    /// live had elapsed.
+    Expired {
+        key: String,
+    },
+}
+
+impl<T: for<'a> Deserialize<'a>> Event<T> {
+    /// Returns the event reporting the record, or `None` if its value can't be decoded.
+    pub(crate) fn from_record<C: Codec>(record: &Record) -> Option<Self> {
+        let key = record.key.to_string();
+        if !record.is_tombstone() {
+            let value = C::decode(&record.value).ok()?;
+            return Some(Event::Set { key, value });
+        }
+        // Tombstones written by sweeps keep the expiry of the record they replace.
+        match record.expires_at {
+            Some(_) => Some(Event::Expired { key }),
+            None => Some(Event::Unset { key }),
+        }
+    }
+}
+
+/// Callback notified of every record written to the store, which returns `false` once it's no
+/// longer interested so that it can be dropped.
+pub(crate) type Watcher = Box<dyn FnMut(&Record) -> bool + Send + Sync>;
+
+type WriteHook = Arc<dyn Fn(&Record) + Send + Sync>;
+
+/// Callbacks registered with [`OpenOptions::on_set`](crate::OpenOptions::on_set) and the like,
+/// which stay registered for as long as the store is open.
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    write: Vec<WriteHook>,
+    compact: Vec<Arc<dyn Fn() + Send + Sync>>,
+}
+
+impl Hooks {
+    pub fn on_write(&mut self, hook: impl Fn(&Record) + Send + Sync + 'static) {
+        self.write.push(Arc::new(hook));
+    }
+
+    pub fn on_compact(&mut self, hook: impl Fn() + Send + Sync + 'static) {
+        self.compact.push(Arc::new(hook));
+    }
+
+    /// Runs the hooks for the records, once they're written, one record at a time so that they
+    /// see the records in the order in which they were written.
+    pub fn written(&self, records: &[Record]) {
+        for record in records {
+            self.write.iter().for_each(|hook| hook(record));
+        }
+    }
+
+    /// Runs the hooks for a compaction which succeeded.
+    pub fn compacted(&self) {
+        self.compact.iter().for_each(|hook| hook());
+    }
+}
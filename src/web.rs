@@ -0,0 +1,89 @@
+//! Backend keeping a database in the `localStorage` of a browser, with the `web` feature.
+
+use std::io;
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+
+use crate::Backend;
+
+/// [`Backend`] keeping a database under a key of the `localStorage` of the browser, in base64.
+///
+/// The bytes are kept in memory for reads, and the whole item is rewritten by every write which
+/// reaches the backend, so this suits the small databases `localStorage` can hold anyway, which
+/// is usually around 5 MiB per origin. Larger writes buffers make for fewer rewrites.
+///
+/// ```ignore
+/// let backend = LocalStorage::open("settings")?;
+/// let store = Store::<Settings>::options().open_backend(backend)?;
+/// ```
+#[derive(Debug)]
+pub struct LocalStorage {
+    key: String,
+    bytes: Vec<u8>,
+}
+
+impl LocalStorage {
+    /// Opens the database stored under the given key, which is empty if there is none yet.
+    pub fn open(key: &str) -> io::Result<Self> {
+        let bytes = match storage()?.get_item(key).map_err(js_err)? {
+            Some(item) => BASE64_STANDARD
+                .decode(item)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+            None => Vec::new(),
+        };
+        Ok(Self {
+            key: key.to_string(),
+            bytes,
+        })
+    }
+
+    /// Stores the given bytes under the key, which are only to be kept in memory once they are,
+    /// so that a write refused by the storage, such as one past its quota, leaves them unchanged.
+    fn save(&self, bytes: &[u8]) -> io::Result<()> {
+        let item = BASE64_STANDARD.encode(bytes);
+        storage()?.set_item(&self.key, &item).map_err(js_err)
+    }
+}
+
+impl Backend for LocalStorage {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self.bytes.read_at(buf, offset)
+    }
+
+    fn append(&mut self, buf: &[u8]) -> io::Result<()> {
+        let bytes = [&self.bytes, buf].concat();
+        self.save(&bytes)?;
+        self.bytes = bytes;
+        Ok(())
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.bytes.len() as u64)
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        let len = usize::try_from(len).map_or(self.bytes.len(), |len| len.min(self.bytes.len()));
+        self.save(&self.bytes[..len])?;
+        self.bytes.truncate(len);
+        Ok(())
+    }
+
+    /// Replaces the item at once, so an interrupted compaction leaves the previous content.
+    fn replace(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.save(buf)?;
+        buf.clone_into(&mut self.bytes);
+        Ok(())
+    }
+}
+
+/// The storage isn't kept around, since JavaScript objects can't be sent across threads as
+/// backends must.
+fn storage() -> io::Result<web_sys::Storage> {
+    web_sys::window()
+        .and_then(|window| window.local_storage().ok().flatten())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "localStorage is unavailable"))
+}
+
+fn js_err(err: web_sys::wasm_bindgen::JsValue) -> io::Error {
+    io::Error::other(format!("{err:?}"))
+}